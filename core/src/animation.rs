@@ -0,0 +1,1135 @@
+//! Upscaling animated GIF, APNG and WebP images: every frame is composited
+//! into a full picture (applying its offset, blending and disposal) and
+//! written to a work folder, the engine upscales them all in its directory
+//! mode (`-i dir -o dir`), and the upscaled frames are encoded again with the
+//! original delays and loop count.
+//!
+//! Decoding and encoding run on a thread of their own so that they do not
+//! hold up the queue, and stop between two frames when the job is cancelled.
+
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use serde::{Deserialize, Serialize};
+
+use crate::args::Waifu2xArgs;
+use crate::job::{Format, Job};
+use crate::progress::Capture;
+
+/// The file types that always hold an animation. APNG and animated WebP
+/// files usually carry the extension of their still siblings, which
+/// [`AnimationFormat::detect`] tells apart.
+pub const ANIMATION_INCLUDE: &str = "*.gif;*.apng";
+
+/// The containers an animation can be read from and written to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnimationFormat {
+    Gif,
+    Apng,
+    Webp,
+}
+
+impl AnimationFormat {
+    pub const ALL: [AnimationFormat; 3] = [
+        AnimationFormat::Gif,
+        AnimationFormat::Apng,
+        AnimationFormat::Webp,
+    ];
+
+    /// The file extension (without the dot) of the container.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AnimationFormat::Gif => "gif",
+            AnimationFormat::Apng => "png",
+            AnimationFormat::Webp => "webp",
+        }
+    }
+
+    /// The container an output at `path` is written in, by its extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+
+        match extension.as_str() {
+            "gif" => Some(AnimationFormat::Gif),
+            "png" | "apng" => Some(AnimationFormat::Apng),
+            "webp" => Some(AnimationFormat::Webp),
+            _ => None,
+        }
+    }
+
+    /// The container of the animation at `path`, or `None` if it is not one.
+    /// Every GIF counts, but PNG and WebP files only when they hold an
+    /// animation; still ones are left to the engine.
+    pub fn detect(path: &Path) -> Option<Self> {
+        let format = Self::from_path(path)?;
+        if format == AnimationFormat::Gif {
+            return Some(format);
+        }
+
+        let mut file = BufReader::new(File::open(path).ok()?);
+
+        let animated = match format {
+            AnimationFormat::Apng => is_apng(&mut file),
+            _ => {
+                let mut header = Vec::new();
+                file.take(21).read_to_end(&mut header).ok()?;
+                is_animated_webp(&header)
+            }
+        };
+
+        animated.then_some(format)
+    }
+}
+
+impl fmt::Display for AnimationFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            AnimationFormat::Gif => "GIF",
+            AnimationFormat::Apng => "APNG",
+            AnimationFormat::Webp => "WebP",
+        };
+
+        f.write_str(text)
+    }
+}
+
+/// Whether the PNG read by `reader` has an animation control chunk before
+/// its image data. Only the chunk headers are read, so that large metadata
+/// chunks such as a color profile are skipped however long they are.
+fn is_apng(mut reader: impl Read + Seek) -> bool {
+    let mut header = [0; 8];
+    // The signature, then the length and type of each chunk
+    if reader.read_exact(&mut header).is_err() {
+        return false;
+    }
+
+    while reader.read_exact(&mut header).is_ok() {
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+
+        match &header[4..] {
+            b"acTL" => return true,
+            b"IDAT" => return false,
+            // The data, then the CRC
+            _ => {
+                if reader
+                    .seek(SeekFrom::Current(i64::from(length) + 4))
+                    .is_err()
+                {
+                    return false;
+                }
+            }
+        }
+    }
+
+    false
+}
+
+/// Whether the WebP `header` has the animation flag of the extended format.
+fn is_animated_webp(header: &[u8]) -> bool {
+    header.len() > 20 && &header[12..16] == b"VP8X" && header[20] & 0x02 != 0
+}
+
+/// How often an animation plays.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Loops {
+    Forever,
+    /// Plays this many times in total, then stops.
+    Times(u16),
+}
+
+/// How long a frame is shown, in seconds, as `numer / denom`. Each container
+/// has its own unit, which is kept to avoid rounding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Delay {
+    pub numer: u32,
+    pub denom: u32,
+}
+
+impl Delay {
+    pub fn new(numer: u32, denom: u32) -> Self {
+        Self {
+            numer,
+            denom: denom.max(1),
+        }
+    }
+
+    pub fn from_millis(millis: u32) -> Self {
+        Self::new(millis, 1000)
+    }
+
+    /// The delay in `units` per second, rounded to the nearest one.
+    fn in_units(&self, units: u32) -> u64 {
+        (u64::from(self.numer) * u64::from(units) + u64::from(self.denom) / 2)
+            / u64::from(self.denom)
+    }
+
+    pub fn millis(&self) -> u32 {
+        self.in_units(1000).min(u32::MAX.into()) as u32
+    }
+
+    fn centis(&self) -> u16 {
+        self.in_units(100).min(u16::MAX.into()) as u16
+    }
+
+    /// The delay as an APNG fraction, which has 16 bits on either side.
+    fn apng_fraction(&self) -> (u16, u16) {
+        match (u16::try_from(self.numer), u16::try_from(self.denom)) {
+            (Ok(numer), Ok(denom)) => (numer, denom),
+            _ => (self.millis().min(u16::MAX.into()) as u16, 1000),
+        }
+    }
+}
+
+/// One frame of an animation, composited into a full picture.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub delay: Delay,
+    /// The pixels, 8-bit RGBA row by row.
+    pub rgba: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Animation {
+    pub width: u32,
+    pub height: u32,
+    pub loops: Loops,
+    pub frames: Vec<Frame>,
+}
+
+#[derive(Debug)]
+pub enum AnimationError {
+    Io(io::Error),
+    /// The file is not a valid animation, or it cannot be written as one.
+    Format(String),
+    /// The job was cancelled between two frames.
+    Cancelled,
+}
+
+impl fmt::Display for AnimationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnimationError::Io(e) => write!(f, "Unable to access the animation: {}", e),
+            AnimationError::Format(e) => write!(f, "Invalid animation: {}", e),
+            AnimationError::Cancelled => f.write_str("Cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for AnimationError {}
+
+impl From<io::Error> for AnimationError {
+    fn from(e: io::Error) -> Self {
+        AnimationError::Io(e)
+    }
+}
+
+macro_rules! format_errors {
+    ($($error:ty),*) => {
+        $(impl From<$error> for AnimationError {
+            fn from(e: $error) -> Self {
+                AnimationError::Format(e.to_string())
+            }
+        })*
+    };
+}
+
+format_errors!(
+    gif::DecodingError,
+    gif::EncodingError,
+    png::DecodingError,
+    png::EncodingError,
+    image_webp::DecodingError,
+    image_webp::EncodingError
+);
+
+impl Animation {
+    /// Reads the animation at `path`, stored in `format`, with every frame
+    /// in memory at once.
+    pub fn read(path: &Path, format: AnimationFormat) -> Result<Self, AnimationError> {
+        let (mut width, mut height) = (0, 0);
+        let mut frames = Vec::new();
+
+        let loops = decode(path, format, |canvas, delay| {
+            (width, height) = (canvas.width, canvas.height);
+            frames.push(Frame {
+                delay,
+                rgba: canvas.rgba.clone(),
+            });
+            Ok(())
+        })?;
+
+        Ok(Animation {
+            width,
+            height,
+            loops,
+            frames,
+        })
+    }
+
+    /// Writes the animation to `path` in `format`.
+    pub fn write(&self, path: &Path, format: AnimationFormat) -> Result<(), AnimationError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        let mut encoder = AnimationEncoder::new(
+            &mut writer,
+            format,
+            self.width,
+            self.height,
+            self.loops,
+            self.frames.len(),
+        )?;
+        for frame in &self.frames {
+            encoder.write_frame(frame)?;
+        }
+        encoder.finish()?;
+
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Writes an animation one frame at a time, so that only the frame at hand
+/// needs to be in memory.
+pub struct AnimationEncoder<W: Write + Seek> {
+    width: u32,
+    height: u32,
+    container: Container<W>,
+}
+
+enum Container<W: Write + Seek> {
+    Gif(gif::Encoder<W>),
+    Apng(png::Writer<W>),
+    /// The writer, and where the RIFF header starts. Its size is only known
+    /// once the last frame is written.
+    Webp(W, u64),
+}
+
+impl<W: Write + Seek> AnimationEncoder<W> {
+    /// Starts an animation of `frame_count` frames of `width` by `height`
+    /// pixels in `format`.
+    pub fn new(
+        mut writer: W,
+        format: AnimationFormat,
+        width: u32,
+        height: u32,
+        loops: Loops,
+        frame_count: usize,
+    ) -> Result<Self, AnimationError> {
+        let container = match format {
+            AnimationFormat::Gif => {
+                let too_large = || AnimationError::Format(String::from("too large for a GIF"));
+                let width = u16::try_from(width).map_err(|_| too_large())?;
+                let height = u16::try_from(height).map_err(|_| too_large())?;
+
+                let mut encoder = gif::Encoder::new(writer, width, height, &[])?;
+                // GIF counts the repetitions after the first play, and a file
+                // without a count plays once
+                match loops {
+                    Loops::Forever => encoder.set_repeat(gif::Repeat::Infinite)?,
+                    Loops::Times(plays) if plays > 1 => {
+                        encoder.set_repeat(gif::Repeat::Finite(plays - 1))?
+                    }
+                    Loops::Times(_) => {}
+                }
+
+                Container::Gif(encoder)
+            }
+            AnimationFormat::Apng => {
+                let plays = match loops {
+                    Loops::Forever => 0,
+                    Loops::Times(plays) => plays.into(),
+                };
+                let frame_count = u32::try_from(frame_count)
+                    .map_err(|_| AnimationError::Format(String::from("too many frames")))?;
+
+                let mut encoder = png::Encoder::new(writer, width, height);
+                encoder.set_color(png::ColorType::Rgba);
+                encoder.set_depth(png::BitDepth::Eight);
+                encoder.set_animated(frame_count, plays)?;
+
+                Container::Apng(encoder.write_header()?)
+            }
+            AnimationFormat::Webp => {
+                if width > 1 << 14 || height > 1 << 14 {
+                    return Err(AnimationError::Format(String::from("too large for a WebP")));
+                }
+
+                let loops = match loops {
+                    Loops::Forever => 0,
+                    Loops::Times(plays) => plays,
+                };
+
+                let mut vp8x = vec![0x02 | 0x10, 0, 0, 0];
+                vp8x.extend(&(width - 1).to_le_bytes()[..3]);
+                vp8x.extend(&(height - 1).to_le_bytes()[..3]);
+
+                let mut anim = vec![0; 4];
+                anim.extend(loops.to_le_bytes());
+
+                let start = writer.stream_position()?;
+                let mut header = b"RIFF\0\0\0\0WEBP".to_vec();
+                push_chunk(&mut header, b"VP8X", &vp8x);
+                push_chunk(&mut header, b"ANIM", &anim);
+                writer.write_all(&header)?;
+
+                Container::Webp(writer, start)
+            }
+        };
+
+        Ok(Self {
+            width,
+            height,
+            container,
+        })
+    }
+
+    /// Appends `frame`, which must be a full picture of the animation's size.
+    pub fn write_frame(&mut self, frame: &Frame) -> Result<(), AnimationError> {
+        let (width, height) = (self.width, self.height);
+
+        match &mut self.container {
+            Container::Gif(encoder) => {
+                let mut rgba = frame.rgba.clone();
+                let mut gif_frame =
+                    gif::Frame::from_rgba_speed(width as u16, height as u16, &mut rgba, 10);
+                gif_frame.delay = frame.delay.centis();
+                // Every frame is a full picture, so transparent pixels must
+                // not show the previous one
+                gif_frame.dispose = gif::DisposalMethod::Background;
+                encoder.write_frame(&gif_frame)?;
+            }
+            Container::Apng(writer) => {
+                let (numer, denom) = frame.delay.apng_fraction();
+                writer.set_frame_delay(numer, denom)?;
+                writer.write_image_data(&frame.rgba)?;
+            }
+            // The encoder only writes still images, so its bitstreams are
+            // wrapped in animation frame chunks, one lossless frame each
+            Container::Webp(writer, _) => {
+                let mut still = Vec::new();
+                image_webp::WebPEncoder::new(&mut still).encode(
+                    &frame.rgba,
+                    width,
+                    height,
+                    image_webp::ColorType::Rgba8,
+                )?;
+
+                // Offset, size, duration, and neither blending nor disposal
+                let mut anmf = vec![0; 6];
+                anmf.extend(&(width - 1).to_le_bytes()[..3]);
+                anmf.extend(&(height - 1).to_le_bytes()[..3]);
+                anmf.extend(&frame.delay.millis().min(0xFF_FFFF).to_le_bytes()[..3]);
+                anmf.push(0x02);
+                // The encoder's RIFF header is replaced by the frame's
+                anmf.extend(&still[12..]);
+
+                let mut chunk = Vec::new();
+                push_chunk(&mut chunk, b"ANMF", &anmf);
+                writer.write_all(&chunk)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Completes the animation after its last frame.
+    pub fn finish(self) -> Result<(), AnimationError> {
+        match self.container {
+            Container::Gif(encoder) => {
+                encoder.into_inner()?;
+            }
+            Container::Apng(writer) => writer.finish()?,
+            Container::Webp(mut writer, start) => {
+                let end = writer.stream_position()?;
+                let size = u32::try_from(end - start - 8)
+                    .map_err(|_| AnimationError::Format(String::from("too large for a WebP")))?;
+
+                writer.seek(SeekFrom::Start(start + 4))?;
+                writer.write_all(&size.to_le_bytes())?;
+                writer.seek(SeekFrom::Start(end))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Appends a RIFF chunk, padded to an even size.
+fn push_chunk(out: &mut Vec<u8>, fourcc: &[u8; 4], data: &[u8]) {
+    out.extend(fourcc);
+    out.extend((data.len() as u32).to_le_bytes());
+    out.extend(data);
+    if data.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+/// A rectangle of the canvas that a frame covers.
+#[derive(Clone, Copy)]
+struct Rect {
+    left: u32,
+    top: u32,
+    width: u32,
+    height: u32,
+}
+
+/// An RGBA picture that frames are drawn onto.
+struct Canvas {
+    width: u32,
+    height: u32,
+    rgba: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            rgba: vec![0; width as usize * height as usize * 4],
+        }
+    }
+
+    /// Draws the RGBA `pixels` of a frame at `rect`, replacing what is there
+    /// or alpha-blending over it. Whatever lies outside the canvas is cut off.
+    fn draw(&mut self, rect: Rect, pixels: &[u8], blend: bool) {
+        for y in 0..rect.height {
+            let canvas_y = rect.top + y;
+            if canvas_y >= self.height {
+                break;
+            }
+
+            for x in 0..rect.width {
+                let canvas_x = rect.left + x;
+                if canvas_x >= self.width {
+                    break;
+                }
+
+                let from = (y * rect.width + x) as usize * 4;
+                let to = (canvas_y * self.width + canvas_x) as usize * 4;
+                let Some(source) = pixels.get(from..from + 4) else {
+                    return;
+                };
+
+                if blend {
+                    blend_over(&mut self.rgba[to..to + 4], source);
+                } else {
+                    self.rgba[to..to + 4].copy_from_slice(source);
+                }
+            }
+        }
+    }
+
+    /// Makes `rect` transparent.
+    fn clear(&mut self, rect: Rect) {
+        let transparent = vec![0; rect.width as usize * rect.height as usize * 4];
+        self.draw(rect, &transparent, false);
+    }
+}
+
+/// Blends the RGBA pixel `source` over `target`.
+fn blend_over(target: &mut [u8], source: &[u8]) {
+    let source_alpha = u32::from(source[3]);
+    match source_alpha {
+        0 => return,
+        255 => {
+            target.copy_from_slice(source);
+            return;
+        }
+        _ => {}
+    }
+
+    let target_alpha = u32::from(target[3]) * (255 - source_alpha) / 255;
+    let alpha = source_alpha + target_alpha;
+
+    for i in 0..3 {
+        let color = u32::from(source[i]) * source_alpha + u32::from(target[i]) * target_alpha;
+        target[i] = (color / alpha) as u8;
+    }
+    target[3] = alpha as u8;
+}
+
+/// Decodes the animation at `path`, stored in `format`, and hands every
+/// frame to `on_frame` with its delay as soon as it is composited, so that
+/// only one is kept in memory. Returns how often the animation plays.
+fn decode(
+    path: &Path,
+    format: AnimationFormat,
+    on_frame: impl FnMut(&Canvas, Delay) -> Result<(), AnimationError>,
+) -> Result<Loops, AnimationError> {
+    let reader = BufReader::new(File::open(path)?);
+
+    match format {
+        AnimationFormat::Gif => read_gif(reader, on_frame),
+        AnimationFormat::Apng => read_apng(reader, on_frame),
+        AnimationFormat::Webp => read_webp(reader, on_frame),
+    }
+}
+
+fn read_gif(
+    reader: impl Read,
+    mut on_frame: impl FnMut(&Canvas, Delay) -> Result<(), AnimationError>,
+) -> Result<Loops, AnimationError> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::RGBA);
+    let mut decoder = options.read_info(reader)?;
+
+    let mut canvas = Canvas::new(decoder.width().into(), decoder.height().into());
+
+    while let Some(frame) = decoder.read_next_frame()? {
+        let rect = Rect {
+            left: frame.left.into(),
+            top: frame.top.into(),
+            width: frame.width.into(),
+            height: frame.height.into(),
+        };
+        let previous =
+            (frame.dispose == gif::DisposalMethod::Previous).then(|| canvas.rgba.clone());
+
+        // Transparent pixels let the canvas show through
+        canvas.draw(rect, &frame.buffer, true);
+        on_frame(&canvas, Delay::new(frame.delay.into(), 100))?;
+
+        match frame.dispose {
+            gif::DisposalMethod::Background => canvas.clear(rect),
+            gif::DisposalMethod::Previous => canvas.rgba = previous.unwrap_or_default(),
+            _ => {}
+        }
+    }
+
+    let loops = match decoder.repeat() {
+        gif::Repeat::Infinite => Loops::Forever,
+        gif::Repeat::Finite(repeats) => Loops::Times(repeats.saturating_add(1)),
+    };
+
+    Ok(loops)
+}
+
+fn read_apng(
+    reader: impl Read,
+    mut on_frame: impl FnMut(&Canvas, Delay) -> Result<(), AnimationError>,
+) -> Result<Loops, AnimationError> {
+    let mut decoder = png::Decoder::new(reader);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+
+    let info = reader.info();
+    let mut canvas = Canvas::new(info.width, info.height);
+    let (num_frames, loops) = match info.animation_control {
+        Some(control) if control.num_plays == 0 => (control.num_frames, Loops::Forever),
+        Some(control) => (
+            control.num_frames,
+            Loops::Times(control.num_plays.min(u16::MAX.into()) as u16),
+        ),
+        None => (1, Loops::Times(1)),
+    };
+
+    let mut buffer = vec![0; reader.output_buffer_size()];
+
+    // The default image is not shown if it comes without frame control
+    if info.animation_control.is_some() && info.frame_control.is_none() {
+        reader.next_frame(&mut buffer)?;
+    }
+
+    for _ in 0..num_frames {
+        let output = reader.next_frame(&mut buffer)?;
+        let pixels = to_rgba(&buffer[..output.buffer_size()], output.color_type);
+
+        let control = reader.info().frame_control;
+        let rect = match control {
+            Some(control) => Rect {
+                left: control.x_offset,
+                top: control.y_offset,
+                width: control.width,
+                height: control.height,
+            },
+            None => Rect {
+                left: 0,
+                top: 0,
+                width: canvas.width,
+                height: canvas.height,
+            },
+        };
+        let dispose = control.map_or(png::DisposeOp::None, |c| c.dispose_op);
+        let blend = control.is_some_and(|c| c.blend_op == png::BlendOp::Over);
+        let previous = (dispose == png::DisposeOp::Previous).then(|| canvas.rgba.clone());
+
+        canvas.draw(rect, &pixels, blend);
+        let delay = control.map_or(Delay::new(0, 100), |c| {
+            // A denominator of 0 means hundredths of a second
+            Delay::new(
+                c.delay_num.into(),
+                if c.delay_den == 0 {
+                    100
+                } else {
+                    c.delay_den.into()
+                },
+            )
+        });
+        on_frame(&canvas, delay)?;
+
+        match dispose {
+            png::DisposeOp::Background => canvas.clear(rect),
+            png::DisposeOp::Previous => canvas.rgba = previous.unwrap_or_default(),
+            png::DisposeOp::None => {}
+        }
+    }
+
+    Ok(loops)
+}
+
+fn read_webp(
+    reader: impl io::BufRead + io::Seek,
+    mut on_frame: impl FnMut(&Canvas, Delay) -> Result<(), AnimationError>,
+) -> Result<Loops, AnimationError> {
+    let mut decoder = image_webp::WebPDecoder::new(reader)?;
+    let (width, height) = decoder.dimensions();
+    let has_alpha = decoder.has_alpha();
+
+    let loops = match decoder.loop_count() {
+        image_webp::LoopCount::Forever => Loops::Forever,
+        image_webp::LoopCount::Times(plays) => Loops::Times(plays.get()),
+    };
+
+    let size = decoder
+        .output_buffer_size()
+        .ok_or_else(|| AnimationError::Format(String::from("the image is too large")))?;
+    let mut buffer = vec![0; size];
+
+    // The decoder composites the frames by itself
+    for _ in 0..decoder.num_frames() {
+        let millis = decoder.read_frame(&mut buffer)?;
+        let color_type = if has_alpha {
+            png::ColorType::Rgba
+        } else {
+            png::ColorType::Rgb
+        };
+
+        let canvas = Canvas {
+            width,
+            height,
+            rgba: to_rgba(&buffer, color_type),
+        };
+        on_frame(&canvas, Delay::from_millis(millis))?;
+    }
+
+    Ok(loops)
+}
+
+/// Expands 8-bit pixels of `color_type` to RGBA.
+fn to_rgba(pixels: &[u8], color_type: png::ColorType) -> Vec<u8> {
+    match color_type {
+        png::ColorType::Rgba => pixels.to_vec(),
+        png::ColorType::Rgb => pixels
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => pixels
+            .chunks_exact(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        // Indexed images are expanded by the decoder
+        png::ColorType::Grayscale | png::ColorType::Indexed => {
+            pixels.iter().flat_map(|&p| [p, p, p, 255]).collect()
+        }
+    }
+}
+
+/// Writes an RGBA picture as a PNG the engine can read.
+fn write_png(path: &Path, width: u32, height: u32, rgba: &[u8]) -> Result<(), AnimationError> {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_compression(png::Compression::Fast);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgba)?;
+    writer.finish()?;
+
+    Ok(())
+}
+
+/// Reads a PNG written by the engine as RGBA, with its width and height.
+fn read_png(path: &Path) -> Result<(u32, u32, Vec<u8>), AnimationError> {
+    let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let output = reader.next_frame(&mut buffer)?;
+
+    Ok((
+        output.width,
+        output.height,
+        to_rgba(&buffer[..output.buffer_size()], output.color_type),
+    ))
+}
+
+/// The name of frame `index` in the work folder.
+fn frame_name(index: usize) -> String {
+    format!("{:08}.png", index + 1)
+}
+
+/// The frame delays and loop count of an animation, to put back on the
+/// upscaled frames.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Timing {
+    /// The container the animation came in.
+    pub format: AnimationFormat,
+    pub loops: Loops,
+    pub delays: Vec<Delay>,
+}
+
+/// Splits the animation at `input` into one PNG per frame in `dir`, unless
+/// `cancelled` is set before the last one is written. Each frame is written
+/// as soon as it is decoded.
+pub fn split(input: &Path, dir: &Path, cancelled: &AtomicBool) -> Result<Timing, AnimationError> {
+    let format = AnimationFormat::detect(input)
+        .or_else(|| AnimationFormat::from_path(input))
+        .ok_or_else(|| AnimationError::Format(String::from("unknown container")))?;
+
+    if dir.exists() {
+        fs::remove_dir_all(dir)?;
+    }
+    fs::create_dir_all(dir)?;
+
+    let mut delays = Vec::new();
+    let loops = decode(input, format, |canvas, delay| {
+        if cancelled.load(Ordering::Relaxed) {
+            return Err(AnimationError::Cancelled);
+        }
+
+        write_png(
+            &dir.join(frame_name(delays.len())),
+            canvas.width,
+            canvas.height,
+            &canvas.rgba,
+        )?;
+        delays.push(delay);
+        Ok(())
+    })?;
+
+    if delays.is_empty() {
+        return Err(AnimationError::Format(String::from("it has no frames")));
+    }
+
+    Ok(Timing {
+        format,
+        loops,
+        delays,
+    })
+}
+
+/// Encodes the frames in `dir`, as written by [`split`] and upscaled, into
+/// the animation `output` in `format`. Each frame is read just before it is
+/// encoded. If that fails, or `cancelled` is set in between, the partial
+/// output is removed.
+pub fn assemble(
+    dir: &Path,
+    timing: &Timing,
+    output: &Path,
+    format: AnimationFormat,
+    cancelled: &AtomicBool,
+) -> Result<(), AnimationError> {
+    let result = encode_frames(dir, timing, output, format, cancelled);
+
+    if result.is_err() {
+        let _ = fs::remove_file(output);
+    }
+
+    result
+}
+
+fn encode_frames(
+    dir: &Path,
+    timing: &Timing,
+    output: &Path,
+    format: AnimationFormat,
+    cancelled: &AtomicBool,
+) -> Result<(), AnimationError> {
+    if timing.delays.is_empty() {
+        return Err(AnimationError::Format(String::from("it has no frames")));
+    }
+
+    // The first frame tells the size of the animation
+    let (width, height, rgba) = read_png(&dir.join(frame_name(0)))?;
+    let mut first = Some(rgba);
+
+    let mut writer = BufWriter::new(File::create(output)?);
+    let mut encoder = AnimationEncoder::new(
+        &mut writer,
+        format,
+        width,
+        height,
+        timing.loops,
+        timing.delays.len(),
+    )?;
+
+    for (index, &delay) in timing.delays.iter().enumerate() {
+        if cancelled.load(Ordering::Relaxed) {
+            return Err(AnimationError::Cancelled);
+        }
+
+        let rgba = match first.take() {
+            Some(rgba) => rgba,
+            None => {
+                let (frame_width, frame_height, rgba) = read_png(&dir.join(frame_name(index)))?;
+                if (frame_width, frame_height) != (width, height) {
+                    return Err(AnimationError::Format(String::from(
+                        "the upscaled frames differ in size",
+                    )));
+                }
+                rgba
+            }
+        };
+
+        encoder.write_frame(&Frame { delay, rgba })?;
+    }
+
+    encoder.finish()?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// What an animation job is doing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    /// The frames are decoded and written to the work folder.
+    Split,
+    /// The engine upscales the frames.
+    Upscale,
+    /// The upscaled frames are encoded into the output.
+    Assemble,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            Stage::Split => "Splitting frames",
+            Stage::Upscale => "Upscaling frames",
+            Stage::Assemble => "Encoding",
+        };
+
+        f.write_str(text)
+    }
+}
+
+/// The progress of one animation through the stages.
+#[derive(Debug)]
+pub struct AnimationJob {
+    pub stage: Stage,
+    /// Where the frames are kept until the animation is encoded.
+    pub work_dir: PathBuf,
+    /// The delays and loop count, once the frames have been split.
+    pub timing: Option<Timing>,
+    /// The thread splitting or encoding the frames.
+    task: Option<JoinHandle<Result<Option<Timing>, AnimationError>>>,
+    /// Tells that thread to stop after the frame at hand.
+    cancelled: Arc<AtomicBool>,
+}
+
+impl AnimationJob {
+    /// Plans the animation `job`, with the work folder named after it.
+    pub fn new(job: &Job) -> Self {
+        let mut hasher = DefaultHasher::new();
+        job.input.hash(&mut hasher);
+        job.output.hash(&mut hasher);
+
+        Self::with_work_dir(
+            env::temp_dir()
+                .join("waifu2x-gui-animation")
+                .join(format!("{:016x}", hasher.finish())),
+        )
+    }
+
+    pub fn with_work_dir(work_dir: PathBuf) -> Self {
+        Self {
+            stage: Stage::Split,
+            work_dir,
+            timing: None,
+            task: None,
+            cancelled: Arc::default(),
+        }
+    }
+
+    fn frames_dir(&self) -> PathBuf {
+        self.work_dir.join("frames")
+    }
+
+    fn upscaled_dir(&self) -> PathBuf {
+        self.work_dir.join("upscaled")
+    }
+
+    /// The engine arguments that upscale the split frames.
+    pub fn upscale_args(&self, args: &Waifu2xArgs) -> Waifu2xArgs {
+        Waifu2xArgs {
+            input: self.frames_dir(),
+            output: self.upscaled_dir(),
+            format: Some(Format::Png),
+            ..args.clone()
+        }
+    }
+
+    /// What each stage does to `job`, for a preview. Splitting and encoding
+    /// are not commands, so they are described in parentheses; `upscale` is the engine's command line.
+    pub fn planned_steps(&self, job: &Job, upscale: String) -> Vec<String> {
+        vec![
+            format!(
+                "(split {} into frames in {})",
+                job.input.to_string_lossy(),
+                self.frames_dir().to_string_lossy()
+            ),
+            upscale,
+            format!(
+                "(encode the frames in {} into {})",
+                self.upscaled_dir().to_string_lossy(),
+                job.output.to_string_lossy()
+            ),
+        ]
+    }
+
+    /// Starts the current stage. Splitting and encoding run on a thread and
+    /// return `None`, to be checked on with [`Self::poll`]; upscaling returns
+    /// the engine's command.
+    pub fn start(
+        &mut self,
+        job: &Job,
+        args: &Waifu2xArgs,
+        exe: &Path,
+    ) -> io::Result<Option<Command>> {
+        let task = match self.stage {
+            Stage::Split => {
+                let (input, frames) = (job.input.clone(), self.frames_dir());
+                let cancelled = Arc::clone(&self.cancelled);
+                thread::Builder::new()
+                    .spawn(move || split(&input, &frames, &cancelled).map(Some))?
+            }
+            Stage::Upscale => {
+                let upscaled = self.upscaled_dir();
+                if upscaled.exists() {
+                    fs::remove_dir_all(&upscaled)?;
+                }
+                fs::create_dir_all(&upscaled)?;
+
+                return Ok(Some(self.upscale_args(args).to_command(exe)));
+            }
+            Stage::Assemble => {
+                let timing = self.timing.clone().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, "the frames were never split")
+                })?;
+                let format = AnimationFormat::from_path(&job.output).unwrap_or(timing.format);
+                let (upscaled, output) = (self.upscaled_dir(), job.output.clone());
+                let cancelled = Arc::clone(&self.cancelled);
+
+                thread::Builder::new().spawn(move || {
+                    assemble(&upscaled, &timing, &output, format, &cancelled).map(|()| None)
+                })?
+            }
+        };
+
+        self.task = Some(task);
+        Ok(None)
+    }
+
+    /// How the thread of the current stage went, once it has finished.
+    pub fn poll(&mut self) -> Option<Result<(), String>> {
+        if !self.task.as_ref()?.is_finished() {
+            return None;
+        }
+
+        let result = match self.task.take()?.join() {
+            Ok(Ok(timing)) => {
+                if timing.is_some() {
+                    self.timing = timing;
+                }
+                Ok(())
+            }
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err(format!("{} failed unexpectedly", self.stage)),
+        };
+
+        Some(result)
+    }
+
+    /// Moves on to the next stage after the current one succeeded. Returns
+    /// `false` once the animation is done.
+    pub fn advance(&mut self) -> Result<bool, String> {
+        match self.stage {
+            Stage::Split => self.stage = Stage::Upscale,
+            Stage::Upscale => {
+                let upscaled = self.upscaled_dir();
+                let count = self.timing.as_ref().map_or(0, |t| t.delays.len());
+                let missing = (0..count)
+                    .filter(|&i| !upscaled.join(frame_name(i)).is_file())
+                    .count();
+
+                if missing > 0 {
+                    return Err(format!("{} frames were not upscaled", missing));
+                }
+
+                self.stage = Stage::Assemble;
+            }
+            Stage::Assemble => {
+                // The frames are worthless once the animation is encoded
+                let _ = fs::remove_dir_all(&self.work_dir);
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Starts over from the first stage.
+    pub fn restart(&mut self) {
+        self.stage = Stage::Split;
+        self.task = None;
+        self.cancelled = Arc::default();
+    }
+
+    /// Stops the thread of the current stage after the frame at hand, waits
+    /// for it, and removes the work folder. Returns whether the animation had
+    /// been encoded completely before the thread noticed, in which case the
+    /// output is kept; a partial one is removed by [`assemble`].
+    pub fn cancel(&mut self) -> bool {
+        self.cancelled.store(true, Ordering::Relaxed);
+
+        let encoded = match self.task.take() {
+            Some(task) => matches!(task.join(), Ok(Ok(_))) && self.stage == Stage::Assemble,
+            None => false,
+        };
+
+        let _ = fs::remove_dir_all(&self.work_dir);
+        encoded
+    }
+
+    /// While upscaling, the frames the engine has finished and the total.
+    pub fn frames(&self, capture: &Capture) -> Option<(usize, usize)> {
+        let total = self.timing.as_ref()?.delays.len();
+
+        match self.stage {
+            Stage::Upscale => Some((capture.frames.min(total), total)),
+            _ => None,
+        }
+    }
+
+    /// The progress of the whole animation in percent, from 0 to 100.
+    pub fn progress(&self, capture: &Capture) -> f32 {
+        match (self.stage, self.frames(capture)) {
+            (Stage::Split, _) => 0.0,
+            (Stage::Upscale, Some((done, total))) if total > 0 => {
+                5.0 + 90.0 * done as f32 / total as f32
+            }
+            (Stage::Upscale, _) => 5.0,
+            (Stage::Assemble, _) => 95.0,
+        }
+    }
+}
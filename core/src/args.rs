@@ -0,0 +1,240 @@
+use std::ffi::OsString;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::engine::Engine;
+use crate::job::Format;
+
+/// The name of the waifu2x executable, resolved through the usual PATH lookup.
+pub const WAIFU2X_EXE: &str = "waifu2x-ncnn-vulkan-cli";
+
+/// Every command line option understood by waifu2x-ncnn-vulkan, and by the
+/// other engines where they differ.
+///
+/// Options left at their default (`None`, empty or `false`) are not passed at
+/// all, so waifu2x picks its own default for them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Waifu2xArgs {
+    /// The engine the arguments are meant for.
+    pub engine: Engine,
+    /// `-i`, an input image or directory.
+    pub input: PathBuf,
+    /// `-o`, an output image or directory.
+    pub output: PathBuf,
+    /// `-s`, the upscale ratio.
+    pub scale: i32,
+    /// `-n`, the denoise level, where -1 disables denoising. Not passed to
+    /// engines without a denoise level.
+    pub denoise: i32,
+    /// `-t`, the tile size, or one per GPU. 0 or an empty list lets waifu2x
+    /// choose one from the available memory.
+    pub tile_sizes: Vec<u32>,
+    /// `-g`, the GPUs to use. Empty means automatic selection, -1 is the CPU.
+    pub gpus: Vec<i32>,
+    /// `-j`, the thread count in `load:proc:save` form.
+    pub threads: Option<String>,
+    /// `-m`, the model directory, or `-n` and a model name for Real-ESRGAN.
+    pub model: Option<PathBuf>,
+    /// `-x`, enables TTA mode.
+    pub tta: bool,
+    /// `-f`, the output format. `None` infers it from the output extension.
+    pub format: Option<Format>,
+    /// `-v`, enables verbose output.
+    pub verbose: bool,
+}
+
+impl Default for Waifu2xArgs {
+    fn default() -> Self {
+        Self {
+            engine: Engine::default(),
+            input: PathBuf::new(),
+            output: PathBuf::new(),
+            scale: 2,
+            denoise: 0,
+            tile_sizes: Vec::new(),
+            gpus: Vec::new(),
+            threads: None,
+            model: None,
+            tta: false,
+            format: None,
+            verbose: false,
+        }
+    }
+}
+
+impl Waifu2xArgs {
+    pub fn to_argv(&self) -> Vec<OsString> {
+        let mut argv = vec![
+            OsString::from("-i"),
+            self.input.clone().into_os_string(),
+            OsString::from("-o"),
+            self.output.clone().into_os_string(),
+            OsString::from("-s"),
+            OsString::from(self.scale.to_string()),
+        ];
+
+        if self.engine.has_denoise() {
+            argv.push("-n".into());
+            argv.push(self.denoise.to_string().into());
+        }
+
+        if !self.tile_sizes.is_empty() {
+            argv.push("-t".into());
+            argv.push(join(&self.tile_sizes).into());
+        }
+
+        if !self.gpus.is_empty() {
+            argv.push("-g".into());
+            argv.push(join(&self.gpus).into());
+        }
+
+        if let Some(threads) = &self.threads {
+            argv.push("-j".into());
+            argv.push(threads.into());
+        }
+
+        if let Some(model) = &self.model {
+            argv.push(self.engine.model_flag().into());
+            argv.push(model.clone().into_os_string());
+        }
+
+        if self.tta {
+            argv.push("-x".into());
+        }
+
+        if let Some(format) = &self.format {
+            argv.push("-f".into());
+            argv.push(format.extension().into());
+        }
+
+        if self.verbose {
+            argv.push("-v".into());
+        }
+
+        argv
+    }
+
+    /// The tile size the engine is told to use, or `None` if it chooses one
+    /// itself or is given one per GPU.
+    pub fn tile_size(&self) -> Option<u32> {
+        match self.tile_sizes[..] {
+            [size] if size > 0 => Some(size),
+            _ => None,
+        }
+    }
+
+    /// The arguments for running on the single device at `position` in a
+    /// device list: `-g` is narrowed to `id`, and per-GPU thread counts such
+    /// as `1:2,2:2` and tile sizes such as `200,100` to the entry of that
+    /// device.
+    pub fn on_device(&self, position: usize, id: i32) -> Waifu2xArgs {
+        let threads = self
+            .threads
+            .as_ref()
+            .map(|threads| match ThreadSpec::parse(threads) {
+                Ok(spec) => spec.for_device(position).to_string(),
+                Err(_) => threads.clone(),
+            });
+
+        let tile_sizes = match self.tile_sizes.get(position) {
+            Some(&size) if self.tile_sizes.len() > 1 => vec![size],
+            _ => self.tile_sizes.clone(),
+        };
+
+        Waifu2xArgs {
+            gpus: vec![id],
+            threads,
+            tile_sizes,
+            ..self.clone()
+        }
+    }
+
+    pub fn to_command(&self, exe: &Path) -> Command {
+        let mut command = Command::new(exe);
+        command.args(self.to_argv());
+        command
+    }
+}
+
+fn join<T: ToString>(values: &[T]) -> String {
+    values
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Parses a `-t` tile size, or a comma-separated list of one per GPU. Each is
+/// 0 for automatic or at least 32; `auto` (or nothing) yields an empty list.
+pub fn parse_tile_sizes(text: &str) -> Result<Vec<u32>, String> {
+    let text = text.trim();
+
+    if text.is_empty() || text.eq_ignore_ascii_case("auto") {
+        return Ok(Vec::new());
+    }
+
+    text.split(',')
+        .map(|size| {
+            size.trim()
+                .parse::<u32>()
+                .ok()
+                .filter(|&s| s == 0 || s >= 32)
+                .ok_or_else(|| format!("\"{}\" is not a valid tile size (0 or at least 32)", size))
+        })
+        .collect()
+}
+
+/// A `-j` thread count in `load:proc:save` form, where `proc` may list one
+/// count per GPU (`1:2,2,2:2`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ThreadSpec {
+    pub load: u32,
+    pub proc: Vec<u32>,
+    pub save: u32,
+}
+
+impl ThreadSpec {
+    pub fn parse(text: &str) -> Result<ThreadSpec, String> {
+        let count = |part: &str, what: &str| {
+            part.trim()
+                .parse::<u32>()
+                .ok()
+                .filter(|&n| n > 0)
+                .ok_or_else(|| format!("\"{}\" is not a valid {} thread count", part, what))
+        };
+
+        let parts = text.split(':').collect::<Vec<_>>();
+        let [load, proc, save] = parts[..] else {
+            return Err(String::from("Expected load:proc:save, e.g. 1:2:2"));
+        };
+
+        Ok(ThreadSpec {
+            load: count(load, "load")?,
+            proc: proc
+                .split(',')
+                .map(|p| count(p, "proc"))
+                .collect::<Result<_, _>>()?,
+            save: count(save, "save")?,
+        })
+    }
+
+    /// The counts for the single device at `position`.
+    pub fn for_device(&self, position: usize) -> ThreadSpec {
+        let proc = match self.proc.get(position) {
+            Some(&proc) if self.proc.len() > 1 => vec![proc],
+            _ => self.proc.clone(),
+        };
+
+        ThreadSpec {
+            proc,
+            ..self.clone()
+        }
+    }
+}
+
+impl fmt::Display for ThreadSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.load, join(&self.proc), self.save)
+    }
+}
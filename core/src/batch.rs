@@ -0,0 +1,515 @@
+//! The headless batch mode behind the `waifu2x-batch` binary.
+//!
+//! It plans and runs a batch exactly like the GUI does (same filename
+//! template, same [`JobQueue`](crate::JobQueue) and concurrency limit) but takes its settings
+//! from the command line and a preset instead of from widgets.
+
+use std::ffi::OsString;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use crate::animation::AnimationFormat;
+use crate::engine::Engine;
+use crate::job::Format;
+use crate::plan::{Collision, CollisionPolicy, Plan};
+use crate::preset::{PresetError, PresetStore};
+use crate::queue::{JobState, QueueEvent};
+use crate::staging::Strategy;
+use crate::state::{PlanError, Waifu2xState};
+
+pub const USAGE: &str = "\
+Usage: waifu2x-batch [options] --out <dir> <inputs...>
+
+Inputs may be images (animated GIF, APNG and WebP too), videos or folders.
+
+Options:
+  -o, --out <dir>        directory to write the outputs into (required)
+  -p, --preset <name>    start from the settings of a saved preset
+      --presets <file>   read presets from this preset pack
+  -e, --engine <name>    waifu2x, realesrgan, realcugan or srmd
+  -s, --scale <n>        upscale ratio
+  -n, --denoise <n>      denoise level, -1 to disable denoising
+  -f, --format <fmt>     output format: png, jpg or webp
+  -m, --model <name>     model folder, or model name for realesrgan
+  -x, --tta              enable TTA mode
+      --threads <l:p:s>  load:proc:save thread counts, e.g. 1:2:2
+      --name <template>  output filename template
+  -g, --gpu <ids>        devices as id[:slots[:weight]], e.g. 0:2,1,-1 or auto
+  -t, --tile-size <n>    tile size, one per device (e.g. 200,100), or auto
+  -j, --jobs <n>         maximum number of concurrent engine processes
+      --batching <mode>  per-file (default), or directory to let images with
+                         identical settings share one engine process
+      --batch-threshold <n>
+                         fewest images worth sharing a process (default: 8)
+  -r, --recursive        search subfolders of input folders
+      --include <globs>  only take files from folders matching these, e.g. *.png;*.jpg
+      --exclude <globs>  skip files and folders matching these, e.g. *_2x*
+      --mirror           mirror the structure of input folders under --out
+      --if-exists <rule> what to do with existing outputs: overwrite (default),
+                         skip, rename, skip-newer or ask
+      --preview          print the plan as CSV instead of running it
+      --exe <path>       engine executable to run
+      --codec <name>     ffmpeg encoder for videos, e.g. libx265
+      --crf <n>          constant rate factor for videos
+      --ffmpeg <path>    ffmpeg executable for videos
+      --animation <fmt>  container of animated images: gif, apng or webp
+                         (default: same as the input)
+  -h, --help             print this help
+";
+
+/// What the command line asked for.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BatchOptions {
+    pub inputs: Vec<PathBuf>,
+    pub output_dir: PathBuf,
+    pub preset: Option<String>,
+    pub preset_file: Option<PathBuf>,
+    pub engine: Option<Engine>,
+    pub scale: Option<i32>,
+    pub denoise: Option<i32>,
+    pub format: Option<Format>,
+    pub model: Option<String>,
+    pub tta: bool,
+    pub threads: Option<String>,
+    pub filename_format: Option<String>,
+    pub gpu_id: Option<String>,
+    pub tile_size: Option<String>,
+    pub jobs: Option<usize>,
+    pub exe: Option<PathBuf>,
+    pub codec: Option<String>,
+    pub crf: Option<u32>,
+    pub ffmpeg: Option<PathBuf>,
+    pub animation_format: Option<AnimationFormat>,
+    pub batching: Option<Strategy>,
+    pub batch_threshold: Option<usize>,
+    pub if_exists: Option<CollisionPolicy>,
+    pub recursive: bool,
+    pub include: Option<String>,
+    pub exclude: Option<String>,
+    pub mirror: bool,
+    pub preview: bool,
+    pub help: bool,
+}
+
+#[derive(Debug)]
+pub enum BatchError {
+    Usage(String),
+    Preset(PresetError),
+    Plan(PlanError),
+    /// An input folder could not be read.
+    Folder(PathBuf, io::Error),
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BatchError::Usage(e) => write!(f, "{}\n\n{}", e, USAGE),
+            BatchError::Preset(e) => e.fmt(f),
+            BatchError::Plan(e) => e.fmt(f),
+            BatchError::Folder(path, e) => {
+                write!(f, "Unable to read the folder {}: {}", path.display(), e)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BatchError {}
+
+impl From<PresetError> for BatchError {
+    fn from(e: PresetError) -> Self {
+        BatchError::Preset(e)
+    }
+}
+
+impl From<PlanError> for BatchError {
+    fn from(e: PlanError) -> Self {
+        BatchError::Plan(e)
+    }
+}
+
+fn usage(message: impl Into<String>) -> BatchError {
+    BatchError::Usage(message.into())
+}
+
+fn parse_value<T: std::str::FromStr>(flag: &str, value: OsString) -> Result<T, BatchError> {
+    value.to_str().and_then(|v| v.parse().ok()).ok_or_else(|| {
+        usage(format!(
+            "invalid value for {}: {}",
+            flag,
+            value.to_string_lossy()
+        ))
+    })
+}
+
+impl BatchOptions {
+    /// Parses the command line, without the program name.
+    pub fn parse(args: impl IntoIterator<Item = OsString>) -> Result<Self, BatchError> {
+        let mut options = BatchOptions::default();
+        let mut output_dir = None;
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let flag = match arg.to_str() {
+                Some(flag) if flag.starts_with('-') && flag.len() > 1 => flag.to_owned(),
+                _ => {
+                    options.inputs.push(PathBuf::from(arg));
+                    continue;
+                }
+            };
+
+            // Switches take no value
+            let switch = match flag.as_str() {
+                "-h" | "--help" => Some(&mut options.help),
+                "-x" | "--tta" => Some(&mut options.tta),
+                "-r" | "--recursive" => Some(&mut options.recursive),
+                "--mirror" => Some(&mut options.mirror),
+                "--preview" => Some(&mut options.preview),
+                _ => None,
+            };
+
+            if let Some(switch) = switch {
+                *switch = true;
+                continue;
+            }
+
+            let value = args
+                .next()
+                .ok_or_else(|| usage(format!("missing value for {}", flag)))?;
+
+            match flag.as_str() {
+                "-o" | "--out" => output_dir = Some(PathBuf::from(value)),
+                "-p" | "--preset" => options.preset = Some(parse_value(&flag, value)?),
+                "--presets" => options.preset_file = Some(PathBuf::from(value)),
+                "-e" | "--engine" => options.engine = Some(parse_value(&flag, value)?),
+                "-s" | "--scale" => options.scale = Some(parse_value(&flag, value)?),
+                "-n" | "--denoise" => options.denoise = Some(parse_value(&flag, value)?),
+                "-f" | "--format" => {
+                    options.format = Some(match value.to_str() {
+                        Some("png") => Format::Png,
+                        Some("jpg") | Some("jpeg") => Format::Jpg,
+                        Some("webp") => Format::Webp,
+                        _ => return Err(usage(format!("unknown format: {:?}", value))),
+                    })
+                }
+                "-m" | "--model" => options.model = Some(parse_value(&flag, value)?),
+                "--threads" => options.threads = Some(parse_value(&flag, value)?),
+                "--name" => options.filename_format = Some(parse_value(&flag, value)?),
+                "-g" | "--gpu" => options.gpu_id = Some(parse_value(&flag, value)?),
+                "-t" | "--tile-size" => options.tile_size = Some(parse_value(&flag, value)?),
+                "-j" | "--jobs" => options.jobs = Some(parse_value(&flag, value)?),
+                "--batching" => {
+                    options.batching = Some(match value.to_str() {
+                        Some("per-file") => Strategy::PerFile,
+                        Some("directory") => Strategy::Directory,
+                        _ => return Err(usage(format!("unknown batching mode: {:?}", value))),
+                    })
+                }
+                "--if-exists" => {
+                    options.if_exists = Some(match value.to_str() {
+                        Some("overwrite") => CollisionPolicy::Overwrite,
+                        Some("skip") => CollisionPolicy::Skip,
+                        Some("rename") => CollisionPolicy::Rename,
+                        Some("skip-newer") => CollisionPolicy::SkipIfNewer,
+                        Some("ask") => CollisionPolicy::Ask,
+                        _ => return Err(usage(format!("unknown rule: {:?}", value))),
+                    })
+                }
+                "--batch-threshold" => options.batch_threshold = Some(parse_value(&flag, value)?),
+                "--exe" => options.exe = Some(PathBuf::from(value)),
+                "--codec" => options.codec = Some(parse_value(&flag, value)?),
+                "--crf" => options.crf = Some(parse_value(&flag, value)?),
+                "--ffmpeg" => options.ffmpeg = Some(PathBuf::from(value)),
+                "--animation" => {
+                    options.animation_format = Some(match value.to_str() {
+                        Some("gif") => AnimationFormat::Gif,
+                        Some("apng") => AnimationFormat::Apng,
+                        Some("webp") => AnimationFormat::Webp,
+                        _ => return Err(usage(format!("unknown animation format: {:?}", value))),
+                    })
+                }
+                "--include" => options.include = Some(parse_value(&flag, value)?),
+                "--exclude" => options.exclude = Some(parse_value(&flag, value)?),
+                _ => return Err(usage(format!("unknown option: {}", flag))),
+            }
+        }
+
+        if options.help {
+            return Ok(options);
+        }
+
+        options.output_dir = output_dir.ok_or_else(|| usage("--out is required"))?;
+
+        if options.inputs.is_empty() {
+            return Err(usage("no input files given"));
+        }
+
+        Ok(options)
+    }
+
+    /// Builds the state the batch runs with: the preset (if any) overridden by
+    /// the individual options. Input files the engine cannot read, and
+    /// subfolders of the inputs that cannot be read, are skipped like in the
+    /// GUI, with a line about each passed to `report`.
+    pub fn to_state(&self, mut report: impl FnMut(&str)) -> Result<Waifu2xState, BatchError> {
+        let mut state = Waifu2xState::default();
+
+        if let Some(name) = &self.preset {
+            let path = match &self.preset_file {
+                Some(path) => path.clone(),
+                None => PresetStore::default_path()
+                    .ok_or_else(|| usage("unable to locate the preset file"))?,
+            };
+
+            let presets = PresetStore::load(&path)?;
+            let preset = presets
+                .get(name)
+                .ok_or_else(|| PresetError::NotFound(name.clone()))?;
+
+            preset.settings.apply_to(&mut state);
+        }
+
+        if let Some(engine) = self.engine {
+            state.set_engine(engine);
+        }
+        if let Some(exe) = &self.exe {
+            state.engine_paths.insert(state.engine, exe.clone());
+        } else if !state.engine_paths.contains_key(&state.engine) {
+            if let Some(exe) = state.engine.discover() {
+                state.discovered_engines.insert(state.engine, exe);
+            }
+        }
+        if let Some(scale) = self.scale {
+            state.scale_level = scale;
+        }
+        if let Some(denoise) = self.denoise {
+            state.denoise_level = denoise;
+        }
+        if let Some(format) = &self.format {
+            state.format = format.clone();
+        }
+        if let Some(model) = &self.model {
+            state.model_path = model.clone();
+        }
+        if self.tta {
+            state.tta_mode = true;
+        }
+        if let Some(threads) = &self.threads {
+            state.thread_count = threads.clone();
+        }
+        if let Some(filename_format) = &self.filename_format {
+            state.filename_format = filename_format.clone();
+        }
+        if let Some(gpu_id) = &self.gpu_id {
+            state.gpu_id = gpu_id.clone();
+        }
+        if let Some(tile_size) = &self.tile_size {
+            state.tile_size = tile_size.clone();
+        }
+        if let Some(jobs) = self.jobs {
+            state.job_limit = jobs;
+        }
+        if let Some(strategy) = self.batching {
+            state.batching.strategy = strategy;
+        }
+        if let Some(threshold) = self.batch_threshold {
+            state.batching.threshold = threshold;
+        }
+        if let Some(if_exists) = self.if_exists {
+            state.if_exists = if_exists;
+        }
+        if let Some(codec) = &self.codec {
+            state.video.codec = codec.clone();
+        }
+        if let Some(crf) = self.crf {
+            state.video.crf = crf;
+        }
+        if let Some(ffmpeg) = &self.ffmpeg {
+            state.ffmpeg_path = ffmpeg.clone();
+        }
+        if self.animation_format.is_some() {
+            state.animation_format = self.animation_format;
+        }
+
+        if self.recursive {
+            state.recursive = true;
+        }
+        if let Some(include) = &self.include {
+            state.include_patterns = include.clone();
+        }
+        if let Some(exclude) = &self.exclude {
+            state.exclude_patterns = exclude.clone();
+        }
+        if self.mirror {
+            state.mirror_structure = true;
+        }
+
+        for input in self.inputs.iter() {
+            if input.is_dir() {
+                let summary = state
+                    .add_folder(input)
+                    .map_err(|e| BatchError::Folder(input.clone(), e))?;
+
+                for folder in summary.unreadable {
+                    report(&format!(
+                        "Skipped the unreadable folder {}",
+                        folder.display()
+                    ));
+                }
+            } else if state.add_paths(std::slice::from_ref(input)).unsupported > 0 {
+                report(&format!(
+                    "Skipped {}, which is not an image, animation or video",
+                    input.display()
+                ));
+            }
+        }
+
+        state.output_dir = self.output_dir.clone().into();
+
+        Ok(state)
+    }
+}
+
+/// The outcome of a batch.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Summary {
+    pub succeeded: usize,
+    /// Inputs whose output already existed and was left alone.
+    pub skipped: usize,
+    /// Every failed input with the reason it failed.
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+impl Summary {
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} succeeded, {} failed",
+            self.succeeded,
+            self.failed.len()
+        )?;
+
+        if self.skipped > 0 {
+            write!(f, ", {} skipped", self.skipped)?;
+        }
+        writeln!(f)?;
+
+        for (input, message) in self.failed.iter() {
+            writeln!(f, "  {}: {}", input.display(), message)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Plans the batch without running anything, for `--preview`. `report` is
+/// called like for [`run`].
+pub fn preview(options: &BatchOptions, report: impl FnMut(&str)) -> Result<Plan, BatchError> {
+    let state = options.to_state(report)?;
+    Ok(state.plan(Path::new(&state.output_dir))?)
+}
+
+/// Runs the batch to completion. `report` is called with a line of text
+/// for every input folder that could not be read, whenever a job finishes,
+/// and with the plan's summary before the first one starts if any output
+/// already exists.
+///
+/// With `--if-exists ask`, `overwrite` is called with the number of outputs
+/// that already exist, and decides whether they are overwritten or skipped.
+pub fn run(
+    options: &BatchOptions,
+    overwrite: impl FnOnce(usize) -> bool,
+    mut report: impl FnMut(&str),
+) -> Result<Summary, BatchError> {
+    let mut state = options.to_state(&mut report)?;
+    let mut plan = state.plan(Path::new(&state.output_dir))?;
+
+    let asked = plan.count(Collision::Ask);
+    if asked > 0 {
+        plan.answer(overwrite(asked));
+    }
+
+    if plan.has_collisions() {
+        report(&plan.to_string());
+    }
+
+    state.execute(&plan);
+    let queue = &mut state.queue;
+
+    let total = queue.jobs().len();
+    let mut done = 0;
+
+    loop {
+        for event in queue.tick() {
+            let id = match event {
+                QueueEvent::Started(_) => continue,
+                QueueEvent::Retrying(id, tile_size) => {
+                    let input = &queue.jobs()[id].job.input;
+                    report(&format!(
+                        "{} ran out of memory, retrying with tile size {}",
+                        input.display(),
+                        tile_size
+                    ));
+                    continue;
+                }
+                QueueEvent::Finished(id, _)
+                | QueueEvent::SpawnFailed(id, _)
+                | QueueEvent::Failed(id, _) => id,
+            };
+
+            done += 1;
+            let job = &queue.jobs()[id];
+            let line = match job.failure_message() {
+                None => format!(
+                    "[{}/{}] {} -> {}{}",
+                    done,
+                    total,
+                    job.job.input.display(),
+                    job.job.output.display(),
+                    job.tile_size
+                        .map(|size| format!(" (tile size {})", size))
+                        .unwrap_or_default()
+                ),
+                Some(message) => format!(
+                    "[{}/{}] {} failed: {}",
+                    done,
+                    total,
+                    job.job.input.display(),
+                    message
+                ),
+            };
+            report(&line);
+        }
+
+        if queue.is_finished() {
+            break;
+        }
+
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    let mut summary = Summary {
+        skipped: plan.jobs.len() - queue.jobs().len(),
+        ..Default::default()
+    };
+    for job in queue.jobs() {
+        match job.state {
+            JobState::Succeeded => summary.succeeded += 1,
+            _ => summary.failed.push((
+                job.job.input.clone(),
+                job.failure_message()
+                    .unwrap_or_else(|| job.state.to_string()),
+            )),
+        }
+    }
+
+    Ok(summary)
+}
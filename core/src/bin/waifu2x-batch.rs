@@ -0,0 +1,61 @@
+use std::io::{self, Write};
+use std::process::ExitCode;
+
+use waifu2x_gui_core::batch::{self, BatchError, BatchOptions, USAGE};
+
+fn main() -> ExitCode {
+    let options = match BatchOptions::parse(std::env::args_os().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return ExitCode::from(2);
+        }
+    };
+
+    if options.help {
+        print!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+
+    if options.preview {
+        return match batch::preview(&options, |line| eprintln!("{}", line)) {
+            Ok(plan) => {
+                print!("{}", plan.to_csv());
+                eprintln!("{}", plan);
+                ExitCode::SUCCESS
+            }
+            Err(e) => fail(e),
+        };
+    }
+
+    match batch::run(&options, ask_overwrite, |line| eprintln!("{}", line)) {
+        Ok(summary) => {
+            print!("{}", summary);
+            if summary.is_success() {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+        Err(e) => fail(e),
+    }
+}
+
+/// Asks on the terminal whether existing outputs should be overwritten.
+/// Anything but "y" skips them.
+fn ask_overwrite(count: usize) -> bool {
+    eprint!("{} outputs already exist. Overwrite them? [y/N] ", count);
+    let _ = io::stderr().flush();
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).is_ok() && answer.trim().eq_ignore_ascii_case("y")
+}
+
+fn fail(e: BatchError) -> ExitCode {
+    eprintln!("error: {}", e);
+
+    match e {
+        BatchError::Usage(_) => ExitCode::from(2),
+        _ => ExitCode::FAILURE,
+    }
+}
@@ -0,0 +1,265 @@
+//! Settings that persist between sessions.
+//!
+//! They are stored as TOML in the per-user configuration directory. Every file
+//! carries a schema version, so that files written by older releases can be
+//! upgraded in [`migrate`] before they are deserialized.
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::animation::AnimationFormat;
+use crate::engine::Engine;
+use crate::job::Format;
+use crate::plan::CollisionPolicy;
+use crate::staging::BatchSettings;
+use crate::state::Waifu2xState;
+use crate::video::VideoSettings;
+
+/// The schema version written by this release.
+pub const CONFIG_VERSION: u32 = 1;
+
+const APP_DIR: &str = "waifu2x-ncnn-vulkan-gui";
+
+/// The user-facing processing and output options of a [`Waifu2xState`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub engine: Engine,
+    pub scale_level: i32,
+    pub denoise_level: i32,
+    pub tta_mode: bool,
+    pub format: Format,
+    pub thread_count: String,
+    pub gpu_id: String,
+    pub tile_size: String,
+    pub model_path: String,
+    pub filename_format: String,
+    pub job_limit: usize,
+    pub recursive: bool,
+    pub include_patterns: String,
+    pub exclude_patterns: String,
+    pub mirror_structure: bool,
+    pub video: VideoSettings,
+    pub animation_format: Option<AnimationFormat>,
+    pub batching: BatchSettings,
+    pub if_exists: CollisionPolicy,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings::from_state(&Waifu2xState::default())
+    }
+}
+
+impl Settings {
+    pub fn from_state(state: &Waifu2xState) -> Self {
+        Self {
+            engine: state.engine,
+            scale_level: state.scale_level,
+            denoise_level: state.denoise_level,
+            tta_mode: state.tta_mode,
+            format: state.format.clone(),
+            thread_count: state.thread_count.clone(),
+            gpu_id: state.gpu_id.clone(),
+            tile_size: state.tile_size.clone(),
+            model_path: state.model_path.clone(),
+            filename_format: state.filename_format.clone(),
+            job_limit: state.job_limit,
+            recursive: state.recursive,
+            include_patterns: state.include_patterns.clone(),
+            exclude_patterns: state.exclude_patterns.clone(),
+            mirror_structure: state.mirror_structure,
+            video: state.video.clone(),
+            animation_format: state.animation_format,
+            batching: state.batching.clone(),
+            if_exists: state.if_exists,
+        }
+    }
+
+    pub fn apply_to(&self, state: &mut Waifu2xState) {
+        state.engine = self.engine;
+        state.scale_level = self.scale_level;
+        state.denoise_level = self.denoise_level;
+        state.tta_mode = self.tta_mode;
+        state.format = self.format.clone();
+        state.thread_count = self.thread_count.clone();
+        state.gpu_id = self.gpu_id.clone();
+        state.tile_size = self.tile_size.clone();
+        state.model_path = self.model_path.clone();
+        state.filename_format = self.filename_format.clone();
+        state.job_limit = self.job_limit;
+        state.recursive = self.recursive;
+        state.include_patterns = self.include_patterns.clone();
+        state.exclude_patterns = self.exclude_patterns.clone();
+        state.mirror_structure = self.mirror_structure;
+        state.video = self.video.clone();
+        state.animation_format = self.animation_format;
+        state.batching = self.batching.clone();
+        state.if_exists = self.if_exists;
+    }
+}
+
+/// The contents of the configuration file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub version: u32,
+    /// The last output directory, empty if none was chosen.
+    pub output_dir: PathBuf,
+    /// The executables chosen for each engine. Engines without one are
+    /// discovered.
+    pub engine_paths: BTreeMap<Engine, PathBuf>,
+    /// The ffmpeg used for videos, empty if it is discovered.
+    pub ffmpeg_path: PathBuf,
+    pub settings: Settings,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            version: CONFIG_VERSION,
+            output_dir: PathBuf::new(),
+            engine_paths: BTreeMap::new(),
+            ffmpeg_path: PathBuf::new(),
+            settings: Settings::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    Serialize(toml::ser::Error),
+    /// The file was written by a newer release with an unknown schema.
+    TooNew(i64),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "Unable to access the settings file: {}", e),
+            ConfigError::Parse(e) => write!(f, "The settings file is malformed: {}", e),
+            ConfigError::Serialize(e) => write!(f, "Unable to serialize the settings: {}", e),
+            ConfigError::TooNew(version) => write!(
+                f,
+                "The settings file has version {}, but only up to {} is supported",
+                version, CONFIG_VERSION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(e: toml::de::Error) -> Self {
+        ConfigError::Parse(e)
+    }
+}
+
+impl From<toml::ser::Error> for ConfigError {
+    fn from(e: toml::ser::Error) -> Self {
+        ConfigError::Serialize(e)
+    }
+}
+
+/// The per-user configuration directory of the platform, such as `%APPDATA%`
+/// on Windows or `$XDG_CONFIG_HOME` elsewhere.
+pub fn config_dir() -> Option<PathBuf> {
+    let non_empty = |var: &str| std::env::var_os(var).filter(|v| !v.is_empty());
+
+    let base = if cfg!(windows) {
+        PathBuf::from(non_empty("APPDATA")?)
+    } else if cfg!(target_os = "macos") {
+        PathBuf::from(non_empty("HOME")?).join("Library/Application Support")
+    } else {
+        match non_empty("XDG_CONFIG_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(non_empty("HOME")?).join(".config"),
+        }
+    };
+
+    Some(base.join(APP_DIR))
+}
+
+/// Upgrades a configuration table of any older schema version to
+/// [`CONFIG_VERSION`]. Each schema change adds one step here.
+pub fn migrate(mut table: toml::Table) -> Result<toml::Table, ConfigError> {
+    let version = table
+        .get("version")
+        .and_then(toml::Value::as_integer)
+        .unwrap_or(0);
+
+    if version > CONFIG_VERSION.into() {
+        return Err(ConfigError::TooNew(version));
+    }
+
+    // Version 1 is the first schema; a missing version is treated as such.
+
+    table.insert("version".into(), i64::from(CONFIG_VERSION).into());
+    Ok(table)
+}
+
+impl Config {
+    /// Where the configuration is stored by default.
+    pub fn default_path() -> Option<PathBuf> {
+        Some(config_dir()?.join("settings.toml"))
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, ConfigError> {
+        let table = migrate(text.parse::<toml::Table>()?)?;
+        Ok(table.try_into()?)
+    }
+
+    pub fn to_toml(&self) -> Result<String, ConfigError> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+
+    /// Reads the configuration at `path`. A missing file yields the defaults.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        match fs::read_to_string(path) {
+            Ok(text) => Config::from_toml(&text),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ConfigError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(path, self.to_toml()?)?;
+        Ok(())
+    }
+
+    pub fn from_state(state: &Waifu2xState) -> Self {
+        Self {
+            version: CONFIG_VERSION,
+            output_dir: PathBuf::from(&state.output_dir),
+            engine_paths: state.engine_paths.clone(),
+            ffmpeg_path: state.ffmpeg_path.clone(),
+            settings: Settings::from_state(state),
+        }
+    }
+
+    pub fn apply_to(&self, state: &mut Waifu2xState) {
+        state.output_dir = OsString::from(&self.output_dir);
+        state.engine_paths = self.engine_paths.clone();
+        state.ffmpeg_path = self.ffmpeg_path.clone();
+        self.settings.apply_to(state);
+    }
+}
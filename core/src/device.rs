@@ -0,0 +1,117 @@
+//! The devices a batch is spread across.
+//!
+//! The GPU ID field holds a comma-separated list of devices, each written as
+//! `id[:slots[:weight]]`. `id` is a GPU index, or -1 for the CPU. `slots` is
+//! how many processes may run on the device at once (1 by default), and
+//! `weight` decides which device gets the next file when several are free: a
+//! device of weight 2 is handed twice as many files as one of weight 1. For
+//! example, `0:2,1,-1` runs two processes on GPU 0, one on GPU 1 and one on
+//! the CPU. `auto` (or nothing) leaves the choice to the engine.
+
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Device {
+    /// The GPU index passed with `-g`, where -1 is the CPU.
+    pub id: i32,
+    /// The number of processes that may run on the device at once.
+    pub slots: usize,
+    pub weight: u32,
+}
+
+impl Device {
+    pub fn new(id: i32) -> Self {
+        Self {
+            id,
+            slots: 1,
+            weight: 1,
+        }
+    }
+
+    pub fn is_cpu(&self) -> bool {
+        self.id == -1
+    }
+
+    /// Parses one `id[:slots[:weight]]` entry.
+    fn parse(entry: &str) -> Option<Device> {
+        let mut parts = entry.split(':').map(str::trim);
+        let mut device = Device::new(parts.next()?.parse().ok()?);
+
+        if device.id < -1 {
+            return None;
+        }
+
+        if let Some(slots) = parts.next() {
+            device.slots = slots.parse().ok().filter(|&s| s > 0)?;
+        }
+
+        if let Some(weight) = parts.next() {
+            device.weight = weight.parse().ok().filter(|&w| w > 0)?;
+        }
+
+        match parts.next() {
+            Some(_) => None,
+            None => Some(device),
+        }
+    }
+
+    /// Parses the GPU ID field. `auto` (or nothing) yields an empty list. On
+    /// failure, the offending entry is returned.
+    pub fn parse_list(text: &str) -> Result<Vec<Device>, String> {
+        let text = text.trim();
+
+        if text.is_empty() || text.eq_ignore_ascii_case("auto") {
+            return Ok(Vec::new());
+        }
+
+        let mut devices: Vec<Device> = Vec::new();
+
+        for entry in text.split(',').map(str::trim) {
+            match Device::parse(entry) {
+                Some(device) if !devices.iter().any(|d| d.id == device.id) => devices.push(device),
+                // A device listed twice is most likely a typo
+                _ => return Err(entry.to_owned()),
+            }
+        }
+
+        Ok(devices)
+    }
+}
+
+/// Rewrites the GPU ID field `text` to list exactly the devices in `ids`, as
+/// picked from a device list. Devices that stay keep their slots and weight.
+/// An empty selection yields `auto`.
+pub fn select_ids(text: &str, ids: &[i32]) -> String {
+    if ids.is_empty() {
+        return String::from("auto");
+    }
+
+    let current = Device::parse_list(text).unwrap_or_default();
+
+    ids.iter()
+        .map(|&id| {
+            let device = current
+                .iter()
+                .copied()
+                .find(|d| d.id == id)
+                .unwrap_or(Device::new(id));
+
+            match (device.slots, device.weight) {
+                (1, 1) => format!("{}", id),
+                (slots, 1) => format!("{}:{}", id, slots),
+                (slots, weight) => format!("{}:{}:{}", id, slots, weight),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_cpu() {
+            write!(f, "CPU")
+        } else {
+            write!(f, "GPU {}", self.id)
+        }
+    }
+}
@@ -0,0 +1,394 @@
+//! The ncnn upscalers that can do the actual work.
+//!
+//! waifu2x-ncnn-vulkan and its siblings by the same author share most of
+//! their command line, but differ in the scales and denoise levels they
+//! accept and in how a model is chosen. [`Engine`] describes those
+//! differences, and [`Waifu2xArgs`](crate::Waifu2xArgs) consults it when
+//! building a command line. It also knows where an engine is usually
+//! installed, and how to tell that an executable really is that engine.
+
+use std::env;
+use std::fmt;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::args::WAIFU2X_EXE;
+
+/// How long [`Engine::probe`] waits for the usage text.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Engine {
+    #[default]
+    Waifu2x,
+    RealEsrgan,
+    RealCugan,
+    Srmd,
+}
+
+impl Engine {
+    pub const ALL: [Engine; 4] = [
+        Engine::Waifu2x,
+        Engine::RealEsrgan,
+        Engine::RealCugan,
+        Engine::Srmd,
+    ];
+
+    /// The name used in settings files and on the command line.
+    pub fn id(&self) -> &'static str {
+        match self {
+            Engine::Waifu2x => "waifu2x",
+            Engine::RealEsrgan => "realesrgan",
+            Engine::RealCugan => "realcugan",
+            Engine::Srmd => "srmd",
+        }
+    }
+
+    /// The executable name, resolved through the usual PATH lookup.
+    pub fn executable(&self) -> &'static str {
+        match self {
+            // Renamed, so it does not clash with the GUI itself
+            Engine::Waifu2x => WAIFU2X_EXE,
+            Engine::RealEsrgan => "realesrgan-ncnn-vulkan",
+            Engine::RealCugan => "realcugan-ncnn-vulkan",
+            Engine::Srmd => "srmd-ncnn-vulkan",
+        }
+    }
+
+    /// The name of the upstream project, which is also the name its usage
+    /// text starts with.
+    pub fn project_name(&self) -> &'static str {
+        match self {
+            Engine::Waifu2x => "waifu2x-ncnn-vulkan",
+            _ => self.executable(),
+        }
+    }
+
+    /// The file names the executable may have, preferred first. The waifu2x
+    /// release is also accepted under its original name.
+    pub fn file_names(&self) -> Vec<String> {
+        let mut names = vec![self.executable()];
+        if self.project_name() != self.executable() {
+            names.push(self.project_name());
+        }
+
+        names
+            .into_iter()
+            .map(|n| format!("{}{}", n, env::consts::EXE_SUFFIX))
+            .collect()
+    }
+
+    /// Looks for the executable next to the running program, then on the
+    /// `PATH`, then in the usual install locations, and returns the first
+    /// one that [probes](Self::probe) as this engine. The running program
+    /// itself is never returned, even if it carries an engine's name.
+    ///
+    /// Every candidate is run, so this may take a few seconds; keep it off
+    /// the UI thread.
+    pub fn discover(&self) -> Option<PathBuf> {
+        let mut dirs = Vec::new();
+        dirs.extend(
+            env::current_exe()
+                .ok()
+                .as_deref()
+                .and_then(Path::parent)
+                .map(Path::to_path_buf),
+        );
+        dirs.extend(env::var_os("PATH").iter().flat_map(env::split_paths));
+        dirs.extend(self.install_dirs());
+
+        self.discover_in(&dirs)
+    }
+
+    /// Like [`Self::discover`], but only searches `dirs`, in order.
+    pub fn discover_in(&self, dirs: &[PathBuf]) -> Option<PathBuf> {
+        let names = self.file_names();
+        dirs.iter()
+            .flat_map(|dir| names.iter().map(move |name| dir.join(name)))
+            .find(|path| path.is_file() && self.probe(path).is_ok())
+    }
+
+    /// Where the engine is commonly installed on this platform.
+    fn install_dirs(&self) -> Vec<PathBuf> {
+        let env_dir = |var: &str| {
+            env::var_os(var)
+                .filter(|v| !v.is_empty())
+                .map(PathBuf::from)
+        };
+        let project = self.project_name();
+        let mut dirs = Vec::new();
+
+        if cfg!(windows) {
+            for var in ["ProgramFiles", "ProgramFiles(x86)"] {
+                dirs.extend(env_dir(var).map(|d| d.join(project)));
+            }
+            dirs.extend(env_dir("LOCALAPPDATA").map(|d| d.join("Programs").join(project)));
+            dirs.extend(
+                env_dir("USERPROFILE").map(|d| d.join("scoop/apps").join(project).join("current")),
+            );
+        } else {
+            dirs.extend(env_dir("HOME").map(|d| d.join(".local/bin")));
+            dirs.push(PathBuf::from("/usr/local/bin"));
+            dirs.push(PathBuf::from("/usr/bin"));
+            dirs.push(Path::new("/opt").join(project));
+        }
+
+        dirs
+    }
+
+    /// Whether `output` is the usage text of this engine.
+    pub fn is_usage(&self, output: &str) -> bool {
+        output.contains(&format!("Usage: {} ", self.project_name()))
+    }
+
+    /// Whether `-g -1` runs the engine on the CPU.
+    pub fn supports_cpu(&self) -> bool {
+        *self != Engine::RealEsrgan
+    }
+
+    /// Runs `exe -h` and checks that it prints the usage text of this
+    /// engine.
+    pub fn probe(&self, exe: &Path) -> Result<(), ProbeError> {
+        if is_current_exe(exe) {
+            return Err(ProbeError::IsSelf);
+        }
+
+        let output = run_captured(Command::new(exe).arg("-h"), PROBE_TIMEOUT)?;
+
+        if self.is_usage(&output) {
+            Ok(())
+        } else {
+            Err(ProbeError::NotEngine(*self))
+        }
+    }
+
+    /// The upscale ratios `-s` accepts with the model setting `model` (empty
+    /// for the default).
+    pub fn scales(&self, model: &str) -> &'static [i32] {
+        match self {
+            Engine::Waifu2x => &[1, 2, 4, 8, 16, 32],
+            // Only the animevideov3 models do 2x and 3x, the others are 4x
+            Engine::RealEsrgan if self.model_location(model).1.contains("animevideov3") => {
+                &[2, 3, 4]
+            }
+            Engine::RealEsrgan => &[4],
+            Engine::RealCugan => &[1, 2, 3, 4],
+            Engine::Srmd => &[2, 3, 4],
+        }
+    }
+
+    /// The denoise levels `-n` accepts, where -1 disables denoising.
+    /// Real-ESRGAN has no separate denoise step, so it only accepts -1.
+    pub fn denoise_levels(&self) -> &'static [i32] {
+        match self {
+            Engine::Waifu2x | Engine::RealCugan => &[-1, 0, 1, 2, 3],
+            Engine::RealEsrgan => &[-1],
+            Engine::Srmd => &[-1, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10],
+        }
+    }
+
+    pub fn supports_scale(&self, model: &str, scale: i32) -> bool {
+        self.scales(model).contains(&scale)
+    }
+
+    pub fn supports_denoise(&self, denoise: i32) -> bool {
+        self.denoise_levels().contains(&denoise)
+    }
+
+    /// The models shipped with the engine, the default first. For Real-ESRGAN
+    /// these are model names inside its `models` folder, for the others they
+    /// are model directories.
+    pub fn models(&self) -> &'static [&'static str] {
+        match self {
+            Engine::Waifu2x => &[
+                "models-cunet",
+                "models-upconv_7_anime_style_art_rgb",
+                "models-upconv_7_photo",
+            ],
+            Engine::RealEsrgan => &[
+                "realesr-animevideov3",
+                "realesrgan-x4plus",
+                "realesrgan-x4plus-anime",
+            ],
+            Engine::RealCugan => &["models-se", "models-pro", "models-nose"],
+            Engine::Srmd => &["models-srmd"],
+        }
+    }
+
+    pub fn default_model(&self) -> &'static str {
+        self.models()[0]
+    }
+
+    /// The models folder, relative to the executable unless absolute, and the
+    /// model within it, for the model setting `model` (empty for the
+    /// default). Real-ESRGAN keeps all its models in one folder and selects
+    /// them by name; the other engines have one folder per model.
+    pub fn model_location(&self, model: &str) -> (PathBuf, String) {
+        let model = match model.trim() {
+            "" => self.default_model(),
+            model => model,
+        };
+
+        match self {
+            Engine::RealEsrgan => (PathBuf::from("models"), model.to_owned()),
+            _ => (PathBuf::from(model), model.to_owned()),
+        }
+    }
+
+    /// The names (without `.param`/`.bin`) of the model files the engine
+    /// loads for `scale` and `denoise`, as it derives them. Empty where the
+    /// files cannot be told in advance.
+    pub fn model_files(&self, model: &str, scale: i32, denoise: i32) -> Vec<String> {
+        match self {
+            // Larger scales repeat the 2x model
+            Engine::Waifu2x => match (scale, denoise) {
+                (1, -1) => Vec::new(),
+                (1, n) => vec![format!("noise{}_model", n)],
+                (_, -1) => vec![String::from("scale2.0x_model")],
+                (_, n) => vec![format!("noise{}_scale2.0x_model", n)],
+            },
+            Engine::RealEsrgan if model.contains("animevideov3") => {
+                vec![format!("{}-x{}", model, scale)]
+            }
+            Engine::RealEsrgan => vec![model.to_owned()],
+            Engine::RealCugan => match (scale, denoise) {
+                (1, _) => Vec::new(),
+                (s, -1) => vec![format!("up{}x-conservative", s)],
+                (s, 0) => vec![format!("up{}x-no-denoise", s)],
+                (s, n) => vec![format!("up{}x-denoise{}x", s, n)],
+            },
+            Engine::Srmd if denoise == -1 => vec![format!("srmdnf_x{}", scale)],
+            Engine::Srmd => vec![format!("srmd_x{}", scale)],
+        }
+    }
+
+    /// The flag that selects a model: Real-ESRGAN takes a model name with
+    /// `-n`, the others a model directory with `-m`.
+    pub fn model_flag(&self) -> &'static str {
+        match self {
+            Engine::RealEsrgan => "-n",
+            _ => "-m",
+        }
+    }
+
+    /// Whether `-n` sets the denoise level.
+    pub fn has_denoise(&self) -> bool {
+        *self != Engine::RealEsrgan
+    }
+}
+
+impl fmt::Display for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Engine::Waifu2x => "waifu2x",
+            Engine::RealEsrgan => "Real-ESRGAN",
+            Engine::RealCugan => "Real-CUGAN",
+            Engine::Srmd => "SRMD",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Engine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Engine::ALL
+            .into_iter()
+            .find(|e| e.id().eq_ignore_ascii_case(s) || e.to_string().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("unknown engine: {}", s))
+    }
+}
+
+/// Why an executable was rejected by [`Engine::probe`].
+#[derive(Debug)]
+pub enum ProbeError {
+    /// The executable is this very program.
+    IsSelf,
+    Spawn(io::Error),
+    /// The executable did not exit within a few seconds.
+    Timeout,
+    /// The executable did not print the usage text of the engine.
+    NotEngine(Engine),
+}
+
+impl fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProbeError::IsSelf => write!(f, "This is the GUI itself, not the engine."),
+            ProbeError::Spawn(e) => write!(f, "Unable to run the engine: {}", e),
+            ProbeError::Timeout => write!(f, "The engine did not respond in time."),
+            ProbeError::NotEngine(engine) => {
+                write!(f, "This does not look like {}.", engine.project_name())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProbeError {}
+
+/// Runs `command` to completion and returns everything it printed, stderr
+/// first. It is killed if it takes longer than `timeout`.
+///
+/// Both pipes are read on threads of their own while waiting, so commands
+/// that print a lot do not stall.
+pub(crate) fn run_captured(command: &mut Command, timeout: Duration) -> Result<String, ProbeError> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(ProbeError::Spawn)?;
+
+    // Read both pipes while waiting, or a chatty program blocks once one
+    // of them is full and never exits
+    let read = |pipe: Option<Box<dyn Read + Send>>| {
+        thread::spawn(move || {
+            let mut output = String::new();
+            if let Some(mut pipe) = pipe {
+                let mut bytes = Vec::new();
+                let _ = pipe.read_to_end(&mut bytes);
+                output = String::from_utf8_lossy(&bytes).into_owned();
+            }
+            output
+        })
+    };
+    let stderr = read(child.stderr.take().map(|p| Box::new(p) as _));
+    let stdout = read(child.stdout.take().map(|p| Box::new(p) as _));
+
+    // Something that is not an engine might never exit on its own
+    let started = Instant::now();
+    while child.try_wait().map_err(ProbeError::Spawn)?.is_none() {
+        if started.elapsed() > timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            // The readers finish once the pipes close, which a child of the
+            // killed program may delay, so they are left behind
+            return Err(ProbeError::Timeout);
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    let mut output = stderr.join().unwrap_or_default();
+    output += &stdout.join().unwrap_or_default();
+
+    Ok(output)
+}
+
+pub(crate) fn is_current_exe(path: &Path) -> bool {
+    let Ok(current) = env::current_exe().and_then(|p| p.canonicalize()) else {
+        return false;
+    };
+
+    path.canonicalize().is_ok_and(|p| p == current)
+}
@@ -0,0 +1,156 @@
+//! Finding out which GPUs an engine can use.
+//!
+//! ncnn describes every Vulkan device it finds on stderr when it starts up,
+//! one aspect per line, each prefixed with the device index and name:
+//!
+//! ```text
+//! [0 NVIDIA GeForce RTX 3060]  queueC=2[8]  queueG=0[16]  queueT=1[2]
+//! [0 NVIDIA GeForce RTX 3060]  bugsbn1=0  bugbilz=0  bugcopc=0  bugihfa=0
+//! [0 NVIDIA GeForce RTX 3060]  fp16-p/s/a=1/1/1  int8-p/s/a=1/1/1
+//! ```
+//!
+//! Older builds write `fp16p=1  fp16s=1  fp16a=0` instead. [`list_gpus`]
+//! makes an engine print this by upscaling a one pixel image.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::{self, Command};
+use std::time::Duration;
+
+use crate::engine::{self, ProbeError};
+
+/// How long [`list_gpus`] waits for the engine.
+const LIST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A 1x1 PNG, for the engine to chew on while it lists its devices.
+const PROBE_IMAGE: &[u8] = &[
+    0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44, 0x52,
+    0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x08, 0x02, 0x00, 0x00, 0x00, 0x90, 0x77, 0x53,
+    0xde, 0x00, 0x00, 0x00, 0x0c, 0x49, 0x44, 0x41, 0x54, 0x08, 0xd7, 0x63, 0xf8, 0xff, 0xff, 0x3f,
+    0x00, 0x05, 0xfe, 0x02, 0xfe, 0xdc, 0xcc, 0x59, 0xe7, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4e,
+    0x44, 0xae, 0x42, 0x60, 0x82,
+];
+
+/// A Vulkan device as reported by ncnn.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GpuInfo {
+    /// The index to pass with `-g`.
+    pub index: i32,
+    pub name: String,
+    /// The number of queues in the compute, graphics and transfer queue
+    /// families.
+    pub compute_queues: u32,
+    pub graphics_queues: u32,
+    pub transfer_queues: u32,
+    /// Whether fp16 values can be stored, and computed with.
+    pub fp16_storage: bool,
+    pub fp16_arithmetic: bool,
+}
+
+impl GpuInfo {
+    fn apply(&mut self, key: &str, value: &str) {
+        let flag = |v: &str| v == "1";
+        // `2[8]` is queue family 2 with 8 queues
+        let queues = |v: &str| {
+            v.split_once('[')
+                .and_then(|(_, count)| count.strip_suffix(']')?.parse().ok())
+                .unwrap_or(0)
+        };
+
+        match key {
+            "queueC" => self.compute_queues = queues(value),
+            "queueG" => self.graphics_queues = queues(value),
+            "queueT" => self.transfer_queues = queues(value),
+            "fp16s" => self.fp16_storage = flag(value),
+            "fp16a" => self.fp16_arithmetic = flag(value),
+            // `fp16-p/s/a=1/1/0` or `fp16-p/s/u/a=1/1/1/0`
+            _ if key.starts_with("fp16-") => {
+                let names = key["fp16-".len()..].split('/');
+                for (name, value) in names.zip(value.split('/')) {
+                    match name {
+                        "s" => self.fp16_storage = flag(value),
+                        "a" => self.fp16_arithmetic = flag(value),
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Splits `[0 Name]  key=value ...` into the index, the name and the rest.
+fn split_line(line: &str) -> Option<(i32, &str, &str)> {
+    let line = line.trim().strip_prefix('[')?;
+
+    // The name may contain brackets itself, so take the `]` that is followed
+    // by `key=value` pairs
+    let (inside, rest) = line
+        .match_indices(']')
+        .map(|(i, _)| (&line[..i], &line[i + 1..]))
+        .find(|(_, rest)| rest.split_whitespace().all(|t| t.contains('=')))?;
+
+    let (index, name) = inside.split_once(' ')?;
+    Some((index.parse().ok()?, name.trim(), rest))
+}
+
+/// Extracts the device list from the output of an ncnn engine, in index
+/// order. Lines that do not describe a device are ignored.
+pub fn parse_gpu_list(output: &str) -> Vec<GpuInfo> {
+    let mut gpus: Vec<GpuInfo> = Vec::new();
+
+    for (index, name, rest) in output.lines().filter_map(split_line) {
+        let gpu = match gpus.iter_mut().find(|g| g.index == index) {
+            Some(gpu) => gpu,
+            None => {
+                gpus.push(GpuInfo {
+                    index,
+                    name: name.to_owned(),
+                    ..Default::default()
+                });
+                gpus.last_mut().unwrap()
+            }
+        };
+
+        for (key, value) in rest.split_whitespace().filter_map(|t| t.split_once('=')) {
+            gpu.apply(key, value);
+        }
+    }
+
+    gpus.sort_by_key(|g| g.index);
+    gpus
+}
+
+/// Lists the GPUs `exe` can use, by having it upscale a tiny image in a
+/// temporary folder with verbose output.
+pub fn list_gpus(exe: &Path) -> Result<Vec<GpuInfo>, ProbeError> {
+    if engine::is_current_exe(exe) {
+        return Err(ProbeError::IsSelf);
+    }
+
+    let dir = env::temp_dir().join(format!("waifu2x-gui-gpu-probe-{}", process::id()));
+    fs::create_dir_all(&dir).map_err(ProbeError::Spawn)?;
+
+    let input = dir.join("probe.png");
+    let result = fs::write(&input, PROBE_IMAGE)
+        .map_err(ProbeError::Spawn)
+        .and_then(|()| {
+            let output = dir.join("probe_out.png");
+            engine::run_captured(
+                Command::new(exe)
+                    .arg("-i")
+                    .arg(&input)
+                    .arg("-o")
+                    .arg(&output)
+                    .arg("-v"),
+                LIST_TIMEOUT,
+            )
+        });
+
+    let _ = fs::remove_dir_all(&dir);
+
+    // Without a usable Vulkan device, ncnn reports an error instead of a
+    // device list, which leaves the list empty
+    Ok(parse_gpu_list(&result?))
+}
@@ -0,0 +1,434 @@
+//! The list of input files, and turning folders into such lists.
+
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::animation;
+use crate::naming;
+use crate::queue::JobState;
+use crate::video;
+
+/// The image types waifu2x can read.
+pub const IMAGE_INCLUDE: &str = "*.png;*.jpg;*.jpeg;*.webp";
+
+/// The patterns a folder is searched with unless the user sets others: every
+/// image, video and animation type that can be added.
+pub fn default_include() -> String {
+    [
+        IMAGE_INCLUDE,
+        video::VIDEO_INCLUDE,
+        animation::ANIMATION_INCLUDE,
+    ]
+    .join(";")
+}
+
+fn matches_any(path: &Path, patterns: &str) -> bool {
+    let Some(name) = path.file_name() else {
+        return false;
+    };
+
+    Glob::parse_list(patterns).iter().any(|g| g.matches(name))
+}
+
+/// Whether waifu2x can read the file at `path`, judging by its extension.
+pub fn is_supported_image(path: &Path) -> bool {
+    matches_any(path, IMAGE_INCLUDE)
+}
+
+/// Whether the file at `path` can be added to the input list: an image
+/// waifu2x can read, an animation, or a video.
+pub fn is_supported(path: &Path) -> bool {
+    matches_any(path, &default_include())
+}
+
+/// What happened when paths were added to the selection.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AddSummary {
+    pub added: usize,
+    /// Files that were already selected.
+    pub duplicates: usize,
+    /// Files that waifu2x cannot read.
+    pub unsupported: usize,
+    /// Files in added folders that the folder filter rejected.
+    pub filtered: usize,
+    /// Folders that could not be read.
+    pub unreadable: Vec<PathBuf>,
+}
+
+impl AddSummary {
+    /// Adds the counts of `other` to these.
+    pub fn merge(&mut self, other: AddSummary) {
+        self.added += other.added;
+        self.duplicates += other.duplicates;
+        self.unsupported += other.unsupported;
+        self.filtered += other.filtered;
+        self.unreadable.extend(other.unreadable);
+    }
+}
+
+impl fmt::Display for AddSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Added {} files", self.added)?;
+
+        if self.unsupported > 0 {
+            write!(f, ", skipped {} unsupported", self.unsupported)?;
+        }
+
+        if self.duplicates > 0 {
+            write!(f, ", skipped {} already selected", self.duplicates)?;
+        }
+
+        if self.filtered > 0 {
+            write!(
+                f,
+                ", skipped {} excluded by the folder filter",
+                self.filtered
+            )?;
+        }
+
+        if !self.unreadable.is_empty() {
+            write!(f, ", failed to read {} folders", self.unreadable.len())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Where an input stands, as shown next to it in the input list.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputStatus {
+    Ready,
+    /// The file no longer exists.
+    Missing,
+    /// The file exists, but its image header could not be read.
+    Unreadable,
+    /// The file is part of the current batch.
+    Job(JobState),
+}
+
+impl fmt::Display for InputStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputStatus::Ready => write!(f, "Ready"),
+            InputStatus::Missing => write!(f, "Missing"),
+            InputStatus::Unreadable => write!(f, "Unreadable"),
+            InputStatus::Job(state) => state.fmt(f),
+        }
+    }
+}
+
+/// One queued input with what is known about it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InputFile {
+    pub path: PathBuf,
+    /// The file size in bytes.
+    pub size: Option<u64>,
+    pub dimensions: Option<(u32, u32)>,
+    pub status: InputStatus,
+}
+
+impl InputFile {
+    /// Reads the size and dimensions of the file at `path`. The dimensions
+    /// of videos are not read.
+    pub fn probe(path: PathBuf) -> Self {
+        let size = fs::metadata(&path).ok().map(|m| m.len());
+        let dimensions = naming::image_dimensions(&path);
+        let status = match (size, dimensions) {
+            (None, _) => InputStatus::Missing,
+            (Some(_), None) if video::is_video(&path) => InputStatus::Ready,
+            (Some(_), None) => InputStatus::Unreadable,
+            (Some(_), Some(_)) => InputStatus::Ready,
+        };
+
+        Self {
+            path,
+            size,
+            dimensions,
+            status,
+        }
+    }
+}
+
+/// The ordered list of inputs of the next batch. Every path appears at most
+/// once.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InputList {
+    files: Vec<InputFile>,
+}
+
+impl InputList {
+    pub fn files(&self) -> &[InputFile] {
+        &self.files
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.files.iter().map(|f| f.path.as_path())
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    pub fn position(&self, path: &Path) -> Option<usize> {
+        self.files.iter().position(|f| f.path == path)
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.position(path).is_some()
+    }
+
+    /// Appends `path` unless it is listed already. Returns whether it was
+    /// added.
+    pub fn add(&mut self, path: PathBuf) -> bool {
+        if self.contains(&path) {
+            return false;
+        }
+
+        self.files.push(InputFile::probe(path));
+        true
+    }
+
+    /// Removes the inputs at `indices`. Indices out of range are ignored.
+    pub fn remove(&mut self, indices: &[usize]) {
+        let mut index = 0;
+        self.files.retain(|_| {
+            index += 1;
+            !indices.contains(&(index - 1))
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.files.clear();
+    }
+
+    /// Moves the inputs at `indices` one place up, keeping their order.
+    /// Inputs that are blocked by the top of the list stay put. Returns the
+    /// new indices, to keep the same inputs selected.
+    pub fn move_up(&mut self, indices: &[usize]) -> Vec<usize> {
+        let mut sorted = indices.to_vec();
+        sorted.sort_unstable();
+        sorted.dedup();
+
+        let len = self.files.len();
+        let mut moved = Vec::with_capacity(sorted.len());
+        for i in sorted.into_iter().filter(|&i| i < len) {
+            if i > 0 && !moved.contains(&(i - 1)) {
+                self.files.swap(i - 1, i);
+                moved.push(i - 1);
+            } else {
+                moved.push(i);
+            }
+        }
+
+        moved
+    }
+
+    /// Moves the inputs at `indices` one place down. See [`move_up`].
+    ///
+    /// [`move_up`]: InputList::move_up
+    pub fn move_down(&mut self, indices: &[usize]) -> Vec<usize> {
+        let mut sorted = indices.to_vec();
+        sorted.sort_unstable_by(|a, b| b.cmp(a));
+        sorted.dedup();
+
+        let len = self.files.len();
+        let mut moved = Vec::with_capacity(sorted.len());
+        for i in sorted.into_iter().filter(|&i| i < len) {
+            if i + 1 < len && !moved.contains(&(i + 1)) {
+                self.files.swap(i, i + 1);
+                moved.push(i + 1);
+            } else {
+                moved.push(i);
+            }
+        }
+
+        moved.reverse();
+        moved
+    }
+
+    /// Sets the status of the input at `path`, returning its index.
+    pub fn set_status(&mut self, path: &Path, status: InputStatus) -> Option<usize> {
+        let index = self.position(path)?;
+        self.files[index].status = status;
+        Some(index)
+    }
+
+    /// Reads the size and dimensions of every input again, forgetting the
+    /// status of the last batch.
+    pub fn refresh(&mut self) {
+        for file in self.files.iter_mut() {
+            *file = InputFile::probe(std::mem::take(&mut file.path));
+        }
+    }
+}
+
+/// A file name pattern, where `*` matches any run of characters and `?` any
+/// single character. Matching ignores ASCII case, like Windows does.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Glob {
+    pattern: Vec<char>,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Self {
+        Self {
+            pattern: pattern.chars().map(|c| c.to_ascii_lowercase()).collect(),
+        }
+    }
+
+    /// Parses a list of patterns separated by `;` or `,`, such as
+    /// `*.png;*.jpg`.
+    pub fn parse_list(patterns: &str) -> Vec<Glob> {
+        patterns
+            .split([';', ','])
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .map(Glob::new)
+            .collect()
+    }
+
+    pub fn matches(&self, name: &OsStr) -> bool {
+        let name = name
+            .to_string_lossy()
+            .chars()
+            .map(|c| c.to_ascii_lowercase())
+            .collect::<Vec<_>>();
+
+        let (mut p, mut n) = (0, 0);
+        // Where to resume if the text after the last `*` fails to match
+        let mut backtrack = None;
+
+        while n < name.len() {
+            match self.pattern.get(p) {
+                Some('*') => {
+                    backtrack = Some((p, n));
+                    p += 1;
+                }
+                Some(&c) if c == '?' || c == name[n] => {
+                    p += 1;
+                    n += 1;
+                }
+                _ => match backtrack {
+                    Some((star, start)) => {
+                        p = star + 1;
+                        n = start + 1;
+                        backtrack = Some((star, start + 1));
+                    }
+                    None => return false,
+                },
+            }
+        }
+
+        self.pattern[p..].iter().all(|&c| c == '*')
+    }
+}
+
+/// Decides which files of a folder become inputs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FolderFilter {
+    /// Whether subfolders are searched as well.
+    pub recursive: bool,
+    /// A file is taken only if its name matches one of these. An empty list
+    /// matches everything.
+    pub include: Vec<Glob>,
+    /// Files and folders whose names match any of these are skipped.
+    pub exclude: Vec<Glob>,
+}
+
+impl Default for FolderFilter {
+    fn default() -> Self {
+        FolderFilter::new(false, &default_include(), "")
+    }
+}
+
+impl FolderFilter {
+    pub fn new(recursive: bool, include: &str, exclude: &str) -> Self {
+        Self {
+            recursive,
+            include: Glob::parse_list(include),
+            exclude: Glob::parse_list(exclude),
+        }
+    }
+
+    fn excludes(&self, name: &OsStr) -> bool {
+        self.exclude.iter().any(|g| g.matches(name))
+    }
+
+    /// Whether the file at `path` passes the include and exclude patterns.
+    pub fn accepts(&self, path: &Path) -> bool {
+        let Some(name) = path.file_name() else {
+            return false;
+        };
+
+        let included = self.include.is_empty() || self.include.iter().any(|g| g.matches(name));
+        included && !self.excludes(name)
+    }
+
+    /// Lists every accepted file under `root`. Subfolders that cannot be
+    /// read are skipped and listed in the result; only an unreadable `root`
+    /// fails the scan.
+    pub fn scan(&self, root: &Path) -> io::Result<Scan> {
+        let mut scan = Scan::default();
+        let mut pending = vec![root.to_path_buf()];
+
+        while let Some(dir) = pending.pop() {
+            let entries = match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(e) if dir == root => return Err(e),
+                Err(_) => {
+                    scan.unreadable.push(dir);
+                    continue;
+                }
+            };
+
+            let mut complete = true;
+            for entry in entries {
+                let Ok(entry) = entry else {
+                    complete = false;
+                    continue;
+                };
+                let path = entry.path();
+
+                // Linked folders are not followed, so a link cycle cannot trap
+                // the scan
+                match entry.file_type() {
+                    Ok(file_type) if file_type.is_dir() => {
+                        if self.recursive && !self.excludes(&entry.file_name()) {
+                            pending.push(path);
+                        }
+                    }
+                    Ok(_) if !path.is_file() => {}
+                    Ok(_) if self.accepts(&path) => scan.files.push(path),
+                    Ok(_) => scan.filtered += 1,
+                    Err(_) => complete = false,
+                }
+            }
+
+            if !complete {
+                scan.unreadable.push(dir);
+            }
+        }
+
+        scan.files.sort();
+        scan.unreadable.sort();
+        Ok(scan)
+    }
+}
+
+/// The files found by [`FolderFilter::scan`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Scan {
+    /// Every accepted file, sorted by path.
+    pub files: Vec<PathBuf>,
+    /// How many files the include and exclude patterns rejected.
+    pub filtered: usize,
+    /// Folders that could not be read completely.
+    pub unreadable: Vec<PathBuf>,
+}
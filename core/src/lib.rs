@@ -0,0 +1,42 @@
+//! The platform-independent part of waifu2x-ncnn-vulkan-gui.
+//!
+//! Everything that decides *what* gets run lives here: the job model, the
+//! engines and their argument builder, the output filename resolver and the
+//! process supervisor. The front-ends (currently only the Win32 one in `src/main.rs`)
+//! merely copy widget values into a [`Waifu2xState`] and report back whatever
+//! this crate tells them.
+
+pub mod animation;
+pub mod args;
+pub mod batch;
+pub mod config;
+pub mod device;
+pub mod engine;
+pub mod gpu;
+pub mod input;
+pub mod job;
+pub mod model;
+pub mod naming;
+pub mod plan;
+pub mod preset;
+pub mod process;
+pub mod progress;
+pub mod queue;
+pub mod staging;
+pub mod state;
+pub mod template;
+pub mod validate;
+pub mod video;
+
+pub use args::Waifu2xArgs;
+pub use config::{Config, Settings};
+pub use device::Device;
+pub use engine::{Engine, ProbeError};
+pub use job::{Format, Job};
+pub use model::Model;
+pub use plan::{CollisionPolicy, Plan};
+pub use preset::{Preset, PresetStore};
+pub use process::{Exit, Supervisor};
+pub use queue::{JobQueue, JobState, QueueEvent};
+pub use state::{PlanError, Waifu2xState};
+pub use template::{Template, TemplateError};
@@ -0,0 +1,129 @@
+//! The models next to an engine, and which scale and denoise levels each can
+//! handle, as told by the files in its folder.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::engine::Engine;
+use crate::validate;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Model {
+    pub engine: Engine,
+    /// The model setting that selects it.
+    pub name: String,
+    /// The folder holding its files.
+    pub dir: PathBuf,
+    /// The `(scale, denoise)` pairs it has files for.
+    pub levels: BTreeSet<(i32, i32)>,
+}
+
+impl Model {
+    /// Inspects the model `name` of `engine`, whose files are in `dir`.
+    pub fn load(engine: Engine, dir: &Path, name: &str) -> Self {
+        let mut levels = BTreeSet::new();
+
+        for &scale in engine.scales(name) {
+            for &denoise in engine.denoise_levels() {
+                let files = engine.model_files(name, scale, denoise);
+
+                let found = files.iter().all(|file| {
+                    dir.join(format!("{}.param", file)).is_file()
+                        && dir.join(format!("{}.bin", file)).is_file()
+                });
+
+                if !files.is_empty() && found {
+                    levels.insert((scale, denoise));
+                }
+            }
+        }
+
+        Self {
+            engine,
+            name: name.to_owned(),
+            dir: dir.to_path_buf(),
+            levels,
+        }
+    }
+
+    /// Finds the model `model` (empty for the default) the way the engine at
+    /// `exe` would, and inspects it.
+    pub fn locate(engine: Engine, exe: &Path, model: &str) -> Option<Self> {
+        let (dir, name) = engine.model_location(model);
+        let dir = validate::find_model_dir(&dir, exe)?;
+
+        Some(Model::load(engine, &dir, &name))
+    }
+
+    /// Whether the model can process at `scale` and `denoise`. Combinations
+    /// that need no model files are always possible.
+    pub fn supports(&self, scale: i32, denoise: i32) -> bool {
+        self.levels.contains(&(scale, denoise))
+            || self
+                .engine
+                .model_files(&self.name, scale, denoise)
+                .is_empty()
+    }
+}
+
+/// Lists the models of `engine` next to the executable `exe`: the `models-*`
+/// folders, or for Real-ESRGAN the models in its `models` folder. Folders
+/// without any usable model files are left out.
+pub fn scan(engine: Engine, exe: &Path) -> Vec<Model> {
+    let base = exe
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+
+    let names = match engine {
+        Engine::RealEsrgan => model_names(&base.join("models")),
+        _ => folder_names(base),
+    };
+
+    names
+        .into_iter()
+        .map(|name| {
+            let dir = match engine {
+                Engine::RealEsrgan => base.join("models"),
+                _ => base.join(&name),
+            };
+
+            Model::load(engine, &dir, &name)
+        })
+        .filter(|model| !model.levels.is_empty())
+        .collect()
+}
+
+/// The `models-*` folders in `dir`.
+fn folder_names(dir: &Path) -> BTreeSet<String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return BTreeSet::new();
+    };
+
+    entries
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| name.starts_with("models-"))
+        .collect()
+}
+
+/// The model names of the `.param` files in `dir`, without the `-x2` style
+/// scale suffix of models that come in one file per scale.
+fn model_names(dir: &Path) -> BTreeSet<String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return BTreeSet::new();
+    };
+
+    entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|e| e == "param"))
+        .filter_map(|path| Some(path.file_stem()?.to_str()?.to_owned()))
+        .map(|stem| match stem.rsplit_once("-x") {
+            Some((name, scale)) if scale.parse::<u32>().is_ok() => name.to_owned(),
+            _ => stem,
+        })
+        .collect()
+}
@@ -0,0 +1,163 @@
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::animation::AnimationFormat;
+use crate::state::Waifu2xState;
+use crate::template::{Context, Template, TemplateError, Value};
+use crate::video;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum NameError {
+    /// The filename template does not parse.
+    Template(TemplateError),
+    /// The input path has no file name to substitute into `{name}`.
+    InvalidInput(PathBuf),
+}
+
+impl fmt::Display for NameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NameError::Template(e) => e.fmt(f),
+            NameError::InvalidInput(path) => write!(
+                f,
+                "The following input file has an invalid path: {}",
+                path.to_string_lossy()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for NameError {}
+
+impl From<TemplateError> for NameError {
+    fn from(e: TemplateError) -> Self {
+        NameError::Template(e)
+    }
+}
+
+/// The template variables of a single input file.
+pub struct FileContext<'a> {
+    pub input: &'a Path,
+    /// The position of the file in the batch, starting from 0.
+    pub index: usize,
+    /// The date the batch was started, formatted as `YYYY-MM-DD`.
+    pub date: &'a str,
+    /// The input image's width and height, if known.
+    pub dimensions: Option<(u32, u32)>,
+    pub state: &'a Waifu2xState,
+}
+
+impl Context for FileContext<'_> {
+    fn value(&self, variable: &str) -> Value {
+        let state = self.state;
+        let unset = || Value::Unset(String::new());
+
+        match variable {
+            "name" => self
+                .input
+                .file_stem()
+                .map_or_else(unset, |n| Value::Text(n.to_owned())),
+            "ext" => self
+                .input
+                .extension()
+                .map_or_else(unset, |e| Value::Text(e.to_owned())),
+            "parent" => self
+                .input
+                .parent()
+                .and_then(Path::file_name)
+                .map_or_else(unset, |p| Value::Text(p.to_owned())),
+            "index" => Value::Int(self.index as i64 + 1),
+            "scale" => Value::Int(state.scale_level.into()),
+            "denoise" if state.denoise_level == -1 => Value::Unset(String::from("-1")),
+            "denoise" => Value::Int(state.denoise_level.into()),
+            "model" if state.model_path.is_empty() => unset(),
+            "model" => Value::Text(state.model_path.clone().into()),
+            // Names are decided before jobs are spread across the devices,
+            // so this is the whole list, without slots and weights
+            "gpu" => match state.devices() {
+                Ok(devices) if !devices.is_empty() => Value::Text(
+                    devices
+                        .iter()
+                        .map(|d| d.id.to_string())
+                        .collect::<Vec<_>>()
+                        .join(",")
+                        .into(),
+                ),
+                _ => Value::Unset(String::from("auto")),
+            },
+            "tta" if state.tta_mode => Value::Text("tta".into()),
+            "date" => Value::Text(self.date.into()),
+            "width" => self
+                .dimensions
+                .map_or_else(unset, |(w, _)| Value::Int(w.into())),
+            "height" => self
+                .dimensions
+                .map_or_else(unset, |(_, h)| Value::Int(h.into())),
+            _ => unset(),
+        }
+    }
+}
+
+/// Reads the width and height of an image from its header.
+pub fn image_dimensions(path: &Path) -> Option<(u32, u32)> {
+    let size = imagesize::size(path).ok()?;
+    Some((size.width as u32, size.height as u32))
+}
+
+/// Today's date in UTC, formatted as `YYYY-MM-DD`.
+pub fn today() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+
+    // Howard Hinnant's `civil_from_days`
+    let z = (secs / 86400) as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Expands `template` for one input file, without any extension.
+pub fn output_file_name(template: &Template, context: &FileContext) -> Result<OsString, NameError> {
+    if context.input.file_name().is_none() {
+        return Err(NameError::InvalidInput(context.input.to_path_buf()));
+    }
+
+    Ok(template.render(context))
+}
+
+/// Resolves the full output path of one input file inside `output_dir`.
+/// Videos keep their container, and animations take the extension of the
+/// animation format if one is chosen; images take the extension of the
+/// output format.
+pub fn output_path(
+    output_dir: &Path,
+    template: &Template,
+    context: &FileContext,
+) -> Result<PathBuf, NameError> {
+    let animation = AnimationFormat::detect(context.input)
+        .map(|format| context.state.animation_format.unwrap_or(format));
+
+    let extension = match (context.input.extension(), animation) {
+        (Some(extension), _) if video::is_video(context.input) => extension,
+        (_, Some(format)) => OsStr::new(format.extension()),
+        _ => OsStr::new(context.state.format.extension()),
+    };
+
+    // The rendered name may contain dots of its own (`photo.v2`, or
+    // `{name}.{ext}`), so the extension is appended rather than set
+    let mut name = output_file_name(template, context)?;
+    name.push(".");
+    name.push(extension);
+
+    Ok(output_dir.join(name))
+}
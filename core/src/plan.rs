@@ -0,0 +1,437 @@
+//! The plan of a batch: every job with its engine arguments and what happens
+//! to an output that already exists. It is worked out before any process
+//! starts, so the user can preview what a batch will do to the output
+//! folder, and it is what the queue is then filled from. The commands are
+//! worked out with the queue's own settings, so the preview shows the
+//! directory mode processes and the stages of videos and animations that
+//! are actually run.
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+
+use crate::args::Waifu2xArgs;
+use crate::job::Job;
+use crate::queue::{JobQueue, QueueSettings};
+
+/// What to do when an output already exists.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CollisionPolicy {
+    #[default]
+    Overwrite,
+    Skip,
+    /// Writes the output under the first free name with a `_1`, `_2`, ...
+    /// suffix.
+    Rename,
+    /// Skips outputs that were written after their input was last changed,
+    /// so that an interrupted batch picks up where it stopped.
+    SkipIfNewer,
+    /// Leaves the choice to the user; see [`Plan::answer`].
+    Ask,
+}
+
+impl CollisionPolicy {
+    pub const ALL: [CollisionPolicy; 5] = [
+        CollisionPolicy::Overwrite,
+        CollisionPolicy::Skip,
+        CollisionPolicy::Rename,
+        CollisionPolicy::SkipIfNewer,
+        CollisionPolicy::Ask,
+    ];
+}
+
+impl fmt::Display for CollisionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            CollisionPolicy::Overwrite => "Overwrite",
+            CollisionPolicy::Skip => "Skip",
+            CollisionPolicy::Rename => "Auto-rename",
+            CollisionPolicy::SkipIfNewer => "Skip if newer than input",
+            CollisionPolicy::Ask => "Ask",
+        };
+
+        f.write_str(text)
+    }
+}
+
+/// What the plan does about the output of one job.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Collision {
+    /// No file is in the way.
+    Free,
+    Overwrite,
+    Skip,
+    /// The job writes to a new name instead.
+    Rename,
+    /// The existing output is newer than the input, so the job is skipped.
+    UpToDate,
+    /// The user has yet to decide.
+    Ask,
+}
+
+impl Collision {
+    pub const ALL: [Collision; 6] = [
+        Collision::Free,
+        Collision::Overwrite,
+        Collision::Skip,
+        Collision::Rename,
+        Collision::UpToDate,
+        Collision::Ask,
+    ];
+
+    /// Whether the job is run.
+    pub fn runs(self) -> bool {
+        matches!(
+            self,
+            Collision::Free | Collision::Overwrite | Collision::Rename
+        )
+    }
+}
+
+impl fmt::Display for Collision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            Collision::Free => "new",
+            Collision::Overwrite => "overwritten",
+            Collision::Skip => "skipped",
+            Collision::Rename => "renamed",
+            Collision::UpToDate => "skipped as up to date",
+            Collision::Ask => "waiting for an answer",
+        };
+
+        f.write_str(text)
+    }
+}
+
+/// The commands a job runs, one per stage.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Steps {
+    pub commands: Vec<String>,
+    /// Whether parts of the commands are only known once the job runs, such
+    /// as the device it is sent to. They show as placeholders like `{gpu}`.
+    pub template: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlannedJob {
+    /// The job as it is run; a renamed output is already in place.
+    pub job: Job,
+    /// The engine arguments it is run with.
+    pub args: Waifu2xArgs,
+    pub collision: Collision,
+    /// What it runs; nothing if it is not run.
+    pub steps: Steps,
+    /// Likely mistakes, such as two inputs writing to the same output.
+    pub warnings: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Plan {
+    /// The engine executable the jobs are run with.
+    pub exe: PathBuf,
+    /// How the queue runs them.
+    pub settings: QueueSettings,
+    pub jobs: Vec<PlannedJob>,
+}
+
+impl Plan {
+    /// Decides what happens to every output of `jobs` that already exists,
+    /// as `policy` says, works out what the runnable jobs run with
+    /// `settings`, and looks for likely mistakes.
+    pub fn new(
+        exe: PathBuf,
+        settings: QueueSettings,
+        jobs: Vec<(Job, Waifu2xArgs)>,
+        policy: CollisionPolicy,
+    ) -> Self {
+        // Renamed outputs must not collide with each other either
+        let mut taken = jobs
+            .iter()
+            .map(|(j, _)| j.output.clone())
+            .collect::<HashSet<_>>();
+
+        let mut jobs = jobs
+            .into_iter()
+            .map(|(mut job, mut args)| {
+                let collision = match policy {
+                    _ if !job.output.exists() => Collision::Free,
+                    CollisionPolicy::Overwrite => Collision::Overwrite,
+                    CollisionPolicy::Skip => Collision::Skip,
+                    CollisionPolicy::Rename => {
+                        job.output = free_name(&job.output, &taken);
+                        args.output = job.output.clone();
+                        taken.insert(job.output.clone());
+                        Collision::Rename
+                    }
+                    CollisionPolicy::SkipIfNewer if is_newer(&job.output, &job.input) => {
+                        Collision::UpToDate
+                    }
+                    CollisionPolicy::SkipIfNewer => Collision::Overwrite,
+                    CollisionPolicy::Ask => Collision::Ask,
+                };
+
+                PlannedJob {
+                    job,
+                    args,
+                    collision,
+                    steps: Steps::default(),
+                    warnings: Vec::new(),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut writers = HashMap::<&Path, usize>::new();
+        for job in jobs.iter() {
+            *writers.entry(job.job.output.as_path()).or_default() += 1;
+        }
+        let shared = writers
+            .into_iter()
+            .filter(|(_, count)| *count > 1)
+            .map(|(output, _)| output.to_path_buf())
+            .collect::<HashSet<_>>();
+
+        for job in jobs.iter_mut() {
+            job.warnings = warnings(&job.job, shared.contains(&job.job.output));
+        }
+
+        let mut plan = Self {
+            exe,
+            settings,
+            jobs,
+        };
+        plan.plan_steps();
+        plan
+    }
+
+    /// Fills a queue with the runnable jobs, as [`Self::runnable`] lists
+    /// them, and records what it would run for each.
+    fn plan_steps(&mut self) {
+        let mut queue = JobQueue::with_settings(self.exe.clone(), &self.settings);
+        for planned in self.runnable() {
+            queue.push(planned.job.clone(), planned.args.clone());
+        }
+
+        let mut steps = queue.planned_steps().into_iter();
+        for job in self.jobs.iter_mut() {
+            job.steps = if job.collision.runs() {
+                steps.next().unwrap_or_default()
+            } else {
+                Steps::default()
+            };
+        }
+    }
+
+    /// How many jobs `collision` applies to.
+    pub fn count(&self, collision: Collision) -> usize {
+        self.jobs
+            .iter()
+            .filter(|j| j.collision == collision)
+            .count()
+    }
+
+    /// Settles every output left to the user: they are overwritten, or
+    /// skipped.
+    pub fn answer(&mut self, overwrite: bool) {
+        for job in self.jobs.iter_mut() {
+            if job.collision == Collision::Ask {
+                job.collision = if overwrite {
+                    Collision::Overwrite
+                } else {
+                    Collision::Skip
+                };
+            }
+        }
+
+        // Directory mode groups form anew around the skipped jobs
+        self.plan_steps();
+    }
+
+    /// The jobs that are run. Outputs still waiting for an answer are left
+    /// out.
+    pub fn runnable(&self) -> impl Iterator<Item = &PlannedJob> {
+        self.jobs.iter().filter(|j| j.collision.runs())
+    }
+
+    /// Whether any output already exists.
+    pub fn has_collisions(&self) -> bool {
+        self.jobs.iter().any(|j| j.collision != Collision::Free)
+    }
+
+    /// How many jobs have warnings.
+    pub fn warning_count(&self) -> usize {
+        self.jobs.iter().filter(|j| !j.warnings.is_empty()).count()
+    }
+
+    /// Whether any command is a template.
+    pub fn has_templates(&self) -> bool {
+        self.jobs.iter().any(|j| j.steps.template)
+    }
+
+    /// The commands of `job` as a shell would take them, one stage after
+    /// another, or nothing if it is not run. Templates are labelled as such.
+    pub fn command_line(&self, job: &PlannedJob) -> String {
+        let commands = job.steps.commands.join(" && ");
+
+        if job.steps.template {
+            format!("Template: {}", commands)
+        } else {
+            commands
+        }
+    }
+
+    /// The plan as CSV, one row per job.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("Input,Output,Command,If output exists,Warnings\r\n");
+
+        for job in self.jobs.iter() {
+            let fields = [
+                job.job.input.to_string_lossy().into_owned(),
+                job.job.output.to_string_lossy().into_owned(),
+                self.command_line(job),
+                job.collision.to_string(),
+                job.warnings.join("; "),
+            ];
+
+            let row = fields
+                .iter()
+                .map(|f| csv_field(f))
+                .collect::<Vec<_>>()
+                .join(",");
+            csv.push_str(&row);
+            csv.push_str("\r\n");
+        }
+
+        csv
+    }
+}
+
+/// How many files each rule applied to, e.g. "10 files: 7 new, 3 skipped".
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} files: {} {}",
+            self.jobs.len(),
+            self.count(Collision::Free),
+            Collision::Free
+        )?;
+
+        for collision in &Collision::ALL[1..] {
+            let count = self.count(*collision);
+            if count > 0 {
+                write!(f, ", {} {}", count, collision)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The likely mistakes in `job`. `shared` is whether another job writes to
+/// the same output.
+fn warnings(job: &Job, shared: bool) -> Vec<String> {
+    let mut warnings = Vec::new();
+
+    if !job.input.exists() {
+        warnings.push(String::from("The input no longer exists"));
+    }
+    if job.output == job.input {
+        warnings.push(String::from("The output replaces the input"));
+    } else if shared {
+        warnings.push(String::from("Another input is written to the same output"));
+    }
+    // The usual limit on Windows, unless long paths are enabled
+    if job.output.as_os_str().len() >= 260 {
+        warnings.push(String::from(
+            "The output path is longer than 259 characters",
+        ));
+    }
+
+    warnings
+}
+
+/// Quotes the arguments of `command` that contain spaces.
+pub(crate) fn command_line(command: &Command) -> String {
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(quote)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The command line of an engine `command` that runs on one of several
+/// devices, with the device, and the tile size and threads picked for it,
+/// left as `{gpu}`, `{tile size}` and `{threads}`.
+pub(crate) fn template_line(command: &Command) -> String {
+    let mut line = vec![quote(command.get_program())];
+    let mut flag = None;
+
+    for arg in command.get_args() {
+        let value = arg.to_string_lossy();
+        let placeholder = match flag {
+            Some("-g") => Some("{gpu}"),
+            Some("-t") if value.contains(',') => Some("{tile size}"),
+            Some("-j") if value.contains(',') => Some("{threads}"),
+            _ => None,
+        };
+
+        line.push(placeholder.map_or_else(|| quote(arg), String::from));
+        flag = ["-g", "-t", "-j"].into_iter().find(|f| *f == value);
+    }
+
+    line.join(" ")
+}
+
+/// Quotes `arg` if it is empty or contains spaces.
+fn quote(arg: &OsStr) -> String {
+    let arg = arg.to_string_lossy();
+    if arg.is_empty() || arg.contains(char::is_whitespace) {
+        format!("\"{}\"", arg)
+    } else {
+        arg.into_owned()
+    }
+}
+
+/// Quotes `field` if it contains a separator, quote or line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// Whether `output` was last written after `input` was changed.
+fn is_newer(output: &Path, input: &Path) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+
+    match (modified(output), modified(input)) {
+        (Some(output), Some(input)) => output >= input,
+        _ => false,
+    }
+}
+
+/// The first of `name_1.ext`, `name_2.ext`, ... that neither exists nor is
+/// in `taken`.
+fn free_name(path: &Path, taken: &HashSet<PathBuf>) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default();
+
+    (1..)
+        .map(|n| {
+            let mut name = stem.to_os_string();
+            name.push(format!("_{}", n));
+            if let Some(extension) = path.extension() {
+                name.push(".");
+                name.push(extension);
+            }
+            path.with_file_name(name)
+        })
+        .find(|candidate| !candidate.exists() && !taken.contains(candidate))
+        .unwrap_or_else(|| path.to_path_buf())
+}
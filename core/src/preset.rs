@@ -0,0 +1,195 @@
+//! Named sets of [`Settings`].
+//!
+//! Presets are kept together in a single TOML file, a "preset pack", which
+//! uses the same schema versioning as the configuration file. The same format
+//! is used to import and export presets, so a pack can be shared as is.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{self, ConfigError, Settings, CONFIG_VERSION};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Preset {
+    pub name: String,
+    #[serde(default)]
+    pub settings: Settings,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PresetStore {
+    version: u32,
+    #[serde(default, rename = "preset")]
+    presets: Vec<Preset>,
+}
+
+impl Default for PresetStore {
+    fn default() -> Self {
+        Self {
+            version: CONFIG_VERSION,
+            presets: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PresetError {
+    File(ConfigError),
+    EmptyName,
+    NotFound(String),
+    AlreadyExists(String),
+}
+
+impl fmt::Display for PresetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PresetError::File(e) => e.fmt(f),
+            PresetError::EmptyName => write!(f, "A preset needs a name."),
+            PresetError::NotFound(name) => write!(f, "There is no preset named \"{}\".", name),
+            PresetError::AlreadyExists(name) => {
+                write!(f, "A preset named \"{}\" already exists.", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PresetError {}
+
+impl From<ConfigError> for PresetError {
+    fn from(e: ConfigError) -> Self {
+        PresetError::File(e)
+    }
+}
+
+impl From<io::Error> for PresetError {
+    fn from(e: io::Error) -> Self {
+        PresetError::File(e.into())
+    }
+}
+
+fn validate_name(name: &str) -> Result<&str, PresetError> {
+    match name.trim() {
+        "" => Err(PresetError::EmptyName),
+        name => Ok(name),
+    }
+}
+
+impl PresetStore {
+    /// Where the user's presets are stored by default.
+    pub fn default_path() -> Option<PathBuf> {
+        Some(config::config_dir()?.join("presets.toml"))
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, PresetError> {
+        let table = config::migrate(text.parse::<toml::Table>().map_err(ConfigError::from)?)?;
+        Ok(table.try_into().map_err(ConfigError::from)?)
+    }
+
+    pub fn to_toml(&self) -> Result<String, PresetError> {
+        Ok(toml::to_string_pretty(self).map_err(ConfigError::from)?)
+    }
+
+    /// Reads the preset pack at `path`. A missing file yields no presets.
+    pub fn load(path: &Path) -> Result<Self, PresetError> {
+        match fs::read_to_string(path) {
+            Ok(text) => PresetStore::from_toml(&text),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(PresetStore::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), PresetError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(path, self.to_toml()?)?;
+        Ok(())
+    }
+
+    pub fn presets(&self) -> &[Preset] {
+        &self.presets
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.presets.iter().map(|p| p.name.clone()).collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Preset> {
+        self.presets.iter().find(|p| p.name == name)
+    }
+
+    /// Stores `settings` under `name`, replacing any preset of the same name.
+    pub fn insert(&mut self, name: &str, settings: Settings) -> Result<(), PresetError> {
+        let name = validate_name(name)?;
+
+        match self.presets.iter_mut().find(|p| p.name == name) {
+            Some(preset) => preset.settings = settings,
+            None => self.presets.push(Preset {
+                name: name.to_owned(),
+                settings,
+            }),
+        }
+
+        Ok(())
+    }
+
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<(), PresetError> {
+        let to = validate_name(to)?;
+
+        if from != to && self.get(to).is_some() {
+            return Err(PresetError::AlreadyExists(to.to_owned()));
+        }
+
+        match self.presets.iter_mut().find(|p| p.name == from) {
+            Some(preset) => {
+                preset.name = to.to_owned();
+                Ok(())
+            }
+            None => Err(PresetError::NotFound(from.to_owned())),
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<Preset, PresetError> {
+        match self.presets.iter().position(|p| p.name == name) {
+            Some(index) => Ok(self.presets.remove(index)),
+            None => Err(PresetError::NotFound(name.to_owned())),
+        }
+    }
+
+    /// Merges the preset pack at `path` into this one. Presets with the same
+    /// name are overwritten. Returns the number of presets imported.
+    ///
+    /// Nothing is imported if any preset of the pack is invalid, or if the
+    /// pack holds two presets with the same name. Unlike [`Self::load`], a
+    /// missing file is an error.
+    pub fn import(&mut self, path: &Path) -> Result<usize, PresetError> {
+        let pack = PresetStore::from_toml(&fs::read_to_string(path)?)?;
+        let count = pack.presets.len();
+
+        let mut merged = self.clone();
+        let mut imported = Vec::with_capacity(count);
+
+        for preset in pack.presets {
+            let name = validate_name(&preset.name)?.to_owned();
+            if imported.contains(&name) {
+                return Err(PresetError::AlreadyExists(name));
+            }
+
+            merged.insert(&name, preset.settings)?;
+            imported.push(name);
+        }
+
+        *self = merged;
+        Ok(count)
+    }
+
+    /// Writes every preset into a pack at `path`.
+    pub fn export(&self, path: &Path) -> Result<(), PresetError> {
+        self.save(path)
+    }
+}
@@ -0,0 +1,123 @@
+use std::io;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread::JoinHandle;
+
+use crate::progress::{self, SharedCapture};
+
+/// How a supervised process ended.
+#[derive(Debug)]
+pub enum Exit {
+    Success,
+    Failure(ExitStatus),
+    Error(io::Error),
+}
+
+impl Exit {
+    pub fn is_success(&self) -> bool {
+        matches!(self, Exit::Success)
+    }
+}
+
+#[derive(Debug)]
+struct Supervised {
+    id: usize,
+    child: Child,
+    readers: Vec<JoinHandle<()>>,
+}
+
+/// Keeps track of the running waifu2x processes, each tagged with the ID of
+/// the job it belongs to.
+#[derive(Debug, Default)]
+pub struct Supervisor {
+    children: Vec<Supervised>,
+}
+
+impl Supervisor {
+    /// Spawns `command` with its output captured into the returned
+    /// [`SharedCapture`].
+    pub fn spawn(&mut self, id: usize, command: &mut Command) -> io::Result<SharedCapture> {
+        let mut child = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let capture = SharedCapture::default();
+        let mut readers = Vec::with_capacity(2);
+
+        if let Some(stdout) = child.stdout.take() {
+            readers.push(progress::spawn_reader(stdout, capture.clone()));
+        }
+
+        if let Some(stderr) = child.stderr.take() {
+            readers.push(progress::spawn_reader(stderr, capture.clone()));
+        }
+
+        self.children.push(Supervised { id, child, readers });
+        Ok(capture)
+    }
+
+    /// Reaps every child that has exited since the last poll, without
+    /// blocking on the ones that are still running.
+    pub fn poll(&mut self) -> Vec<(usize, Exit)> {
+        let mut exits = Vec::new();
+
+        let mut i = 0;
+        while i < self.children.len() {
+            let exit = match self.children[i].child.try_wait() {
+                Ok(None) => {
+                    i += 1;
+                    continue;
+                }
+                Ok(Some(status)) if status.success() => Exit::Success,
+                Ok(Some(status)) => Exit::Failure(status),
+                Err(e) => Exit::Error(e),
+            };
+
+            let mut supervised = self.children.remove(i);
+            supervised.join_readers();
+            exits.push((supervised.id, exit));
+        }
+
+        exits
+    }
+
+    /// Kills every child that is still running and waits for it to exit.
+    /// Returns the children that had already exited by themselves, with
+    /// how they ended, and the IDs of the jobs whose children were killed.
+    pub fn kill_all(&mut self) -> (Vec<(usize, Exit)>, Vec<usize>) {
+        let exited = self.poll();
+
+        let killed = self
+            .children
+            .drain(..)
+            .map(|mut supervised| {
+                // Killing fails only if the child has exited since the poll,
+                // in which case waiting just reaps it
+                let _ = supervised.child.kill();
+                let _ = supervised.child.wait();
+                supervised.join_readers();
+                supervised.id
+            })
+            .collect();
+
+        (exited, killed)
+    }
+
+    pub fn len(&self) -> usize {
+        self.children.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.children.is_empty()
+    }
+}
+
+impl Supervised {
+    /// Waits until everything the exited child printed has been captured.
+    fn join_readers(&mut self) {
+        for reader in self.readers.drain(..) {
+            let _ = reader.join();
+        }
+    }
+}
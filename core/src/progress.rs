@@ -0,0 +1,141 @@
+//! Reading the output of a running waifu2x process.
+//!
+//! In verbose mode, waifu2x reports how many of an image's tiles are done by
+//! printing lines like `37.50%` on stderr, and in directory mode, every
+//! finished file as `in.png -> out.png done`. ffmpeg reports the frames it
+//! has written as `frame=  120 fps=...`.
+
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// Everything a process has printed so far, and the last progress it reported.
+#[derive(Clone, Debug, Default)]
+pub struct Capture {
+    /// Progress in percent, from 0 to 100.
+    pub progress: f32,
+    /// The number of files or frames finished so far.
+    pub frames: usize,
+    /// The file names of the outputs reported as done in directory mode.
+    pub done: HashSet<String>,
+    /// Every line that is not a progress report, from both stdout and stderr.
+    pub log: String,
+}
+
+pub type SharedCapture = Arc<Mutex<Capture>>;
+
+/// Parses a progress line such as `37.50%` into a percentage.
+pub fn parse_progress(line: &str) -> Option<f32> {
+    let percent = line
+        .trim()
+        .strip_suffix('%')?
+        .trim_end()
+        .parse::<f32>()
+        .ok()?;
+
+    if (0.0..=100.0).contains(&percent) {
+        Some(percent)
+    } else {
+        None
+    }
+}
+
+/// Parses an ffmpeg statistics line such as `frame=  120 fps= 30 ...` into
+/// the number of frames written.
+pub fn parse_frame_count(line: &str) -> Option<usize> {
+    let count = line.trim().strip_prefix("frame=")?.trim_start();
+    let digits = count
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(count.len());
+
+    count[..digits].parse().ok()
+}
+
+impl Capture {
+    /// Records one line of output.
+    pub fn feed(&mut self, line: &str) {
+        if let Some(frames) = parse_frame_count(line) {
+            self.frames = frames;
+            return;
+        }
+
+        if let Some(report) = line.trim_end().strip_suffix(" done") {
+            self.frames += 1;
+
+            let output = report.rsplit_once(" -> ").map(|(_, output)| output);
+            if let Some(name) = output.and_then(|o| Path::new(o).file_name()) {
+                self.done.insert(name.to_string_lossy().into_owned());
+            }
+        }
+
+        match parse_progress(line) {
+            Some(progress) => self.progress = progress,
+            None if line.trim().is_empty() => {}
+            None => {
+                self.log.push_str(line.trim_end());
+                self.log.push('\n');
+            }
+        }
+    }
+}
+
+/// Whether `log` shows that the engine ran out of GPU or host memory, which a
+/// smaller tile size may avoid.
+pub fn is_allocation_error(log: &str) -> bool {
+    let log = log.to_ascii_lowercase();
+
+    [
+        "vkallocatememory failed",
+        "out_of_device_memory",
+        "out_of_host_memory",
+        "out of memory",
+    ]
+    .iter()
+    .any(|error| log.contains(error))
+}
+
+/// Feeds everything `reader` produces into `capture` on a background thread,
+/// until the reader reaches its end.
+pub fn spawn_reader<R>(reader: R, capture: SharedCapture) -> JoinHandle<()>
+where
+    R: Read + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut reader = BufReader::new(reader);
+        let mut line = Vec::new();
+
+        // Some programs redraw their progress with a bare '\r', and never
+        // end the line until they are done, so either byte ends a line
+        loop {
+            let chunk = match reader.fill_buf() {
+                Ok([]) | Err(_) => break,
+                Ok(chunk) => chunk,
+            };
+
+            let mut capture = match capture.lock() {
+                Ok(x) => x,
+                Err(_) => return,
+            };
+
+            for &byte in chunk {
+                if byte == b'\n' || byte == b'\r' {
+                    capture.feed(&String::from_utf8_lossy(&line));
+                    line.clear();
+                } else {
+                    line.push(byte);
+                }
+            }
+
+            let read = chunk.len();
+            reader.consume(read);
+        }
+
+        if !line.is_empty() {
+            if let Ok(mut capture) = capture.lock() {
+                capture.feed(&String::from_utf8_lossy(&line));
+            }
+        }
+    })
+}
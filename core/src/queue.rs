@@ -0,0 +1,1050 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::animation::{self, AnimationFormat, AnimationJob};
+use crate::args::Waifu2xArgs;
+use crate::device::Device;
+use crate::input;
+use crate::job::Job;
+use crate::plan::{self, Steps};
+use crate::process::{Exit, Supervisor};
+use crate::progress::{self, Capture, SharedCapture};
+use crate::staging::{self, BatchSettings, Staged};
+use crate::video::{self, VideoJob, VideoSettings};
+
+/// The tile sizes tried, largest first, when a job runs out of memory.
+const TILE_SIZES: [u32; 5] = [400, 200, 100, 64, 32];
+
+/// The first tile size tried when the engine's own choice ran out of memory.
+/// waifu2x picks 400, 200 or 100 depending on the free video memory, but
+/// ncnn does not report that memory when it lists the devices, so the retry
+/// starts below the smallest of them rather than at a size it may have
+/// failed with already.
+const AUTO_RETRY_TILE_SIZE: u32 = 64;
+
+/// The next tile size to try after running out of memory with `tile_size`
+/// (`None` for the engine's choice), or `None` if none is left.
+pub fn smaller_tile_size(tile_size: Option<u32>) -> Option<u32> {
+    match tile_size {
+        Some(current) => TILE_SIZES.into_iter().find(|&size| size < current),
+        None => Some(AUTO_RETRY_TILE_SIZE),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JobState {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    pub fn is_done(self) -> bool {
+        !matches!(self, JobState::Pending | JobState::Running)
+    }
+}
+
+impl fmt::Display for JobState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            JobState::Pending => "Pending",
+            JobState::Running => "Running",
+            JobState::Succeeded => "Done",
+            JobState::Failed => "Failed",
+            JobState::Cancelled => "Cancelled",
+        };
+
+        f.write_str(text)
+    }
+}
+
+#[derive(Debug)]
+pub struct QueuedJob {
+    pub job: Job,
+    pub args: Waifu2xArgs,
+    pub state: JobState,
+    /// The device the job was sent to, if the queue has a device list.
+    pub device: Option<Device>,
+    /// The tile size the job was last run with, `None` if the engine chose
+    /// it. Once the job has succeeded, this is the tile size that worked.
+    pub tile_size: Option<u32>,
+    /// The stages of a video job, `None` for an image.
+    pub video: Option<VideoJob>,
+    /// The stages of an animated image, `None` for a still one.
+    pub animation: Option<AnimationJob>,
+    /// Where the input is staged while it shares a directory mode process
+    /// with other images.
+    pub staged: Option<Staged>,
+    /// The output of the job's process, once it has been started.
+    pub capture: Option<SharedCapture>,
+    /// The exit code of the job's process, once it has exited normally.
+    pub exit_code: Option<i32>,
+    /// Why the process could not be spawned or waited for, if that happened.
+    pub error: Option<String>,
+}
+
+impl QueuedJob {
+    /// The job's progress in percent, from 0 to 100.
+    pub fn progress(&self) -> f32 {
+        // A shared process works through its files one after another
+        if let (JobState::Running, Some(staged)) = (self.state, &self.staged) {
+            let done = self
+                .capture
+                .as_ref()
+                .and_then(|c| c.lock().ok().map(|c| staged.is_done(&c)));
+
+            return if done == Some(true) { 100.0 } else { 0.0 };
+        }
+
+        match (self.state, &self.video, &self.animation) {
+            (JobState::Pending, _, _) => 0.0,
+            (JobState::Succeeded, _, _) => 100.0,
+            (_, Some(video), _) => video.progress(&self.capture()),
+            (_, _, Some(animation)) => animation.progress(&self.capture()),
+            (_, None, None) => self
+                .capture
+                .as_ref()
+                .and_then(|c| c.lock().ok().map(|c| c.progress))
+                .unwrap_or(0.0),
+        }
+    }
+
+    /// For a running video or animation, the frames its current stage has
+    /// processed and the total, if known.
+    pub fn frames(&self) -> Option<(usize, usize)> {
+        if self.state != JobState::Running {
+            return None;
+        }
+
+        match (&self.video, &self.animation) {
+            (Some(video), _) => video.frames(&self.capture()),
+            (_, Some(animation)) => animation.frames(&self.capture()),
+            _ => None,
+        }
+    }
+
+    /// What a running video or animation is doing, such as "Encoding".
+    pub fn stage(&self) -> Option<String> {
+        if self.state != JobState::Running {
+            return None;
+        }
+
+        match (&self.video, &self.animation) {
+            (Some(video), _) => Some(video.stage.to_string()),
+            (_, Some(animation)) => Some(animation.stage.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Whether the engine is what the job is running, or would run next.
+    fn is_upscaling(&self) -> bool {
+        match (&self.video, &self.animation) {
+            (Some(video), _) => video.stage == video::Stage::Upscale,
+            (_, Some(animation)) => animation.stage == animation::Stage::Upscale,
+            _ => true,
+        }
+    }
+
+    /// A one-line explanation of why the job failed, or `None` if it did not.
+    ///
+    /// waifu2x prints its error (e.g. "decode image failed") last, so the last
+    /// line of output is the most helpful part of it.
+    pub fn failure_message(&self) -> Option<String> {
+        if self.state != JobState::Failed {
+            return None;
+        }
+
+        if let Some(error) = &self.error {
+            return Some(error.clone());
+        }
+
+        let capture = self.capture();
+        let last_line = capture
+            .log
+            .lines()
+            .rev()
+            .map(str::trim)
+            .find(|l| !l.is_empty());
+
+        Some(match (last_line, self.exit_code) {
+            (Some(line), _) => line.to_owned(),
+            (None, Some(code)) => format!("waifu2x exited with code {}", code),
+            (None, None) => String::from("waifu2x was terminated"),
+        })
+    }
+
+    /// A snapshot of everything the job's process has printed so far.
+    pub fn capture(&self) -> Capture {
+        self.capture
+            .as_ref()
+            .and_then(|c| c.lock().ok().map(|c| c.clone()))
+            .unwrap_or_default()
+    }
+}
+
+/// Something that happened to a job during a [`JobQueue::tick`].
+#[derive(Debug)]
+pub enum QueueEvent {
+    Started(usize),
+    Finished(usize, Exit),
+    /// The engine could not be spawned. The rest of the batch would most
+    /// likely fail the same way.
+    SpawnFailed(usize, io::Error),
+    /// The job failed without the engine failing: its output folder could
+    /// not be created, ffmpeg could not be spawned, or a video or an
+    /// animation could not be split or reassembled.
+    Failed(usize, io::Error),
+    /// The job ran out of memory and was put back into the queue with the
+    /// given smaller tile size.
+    Retrying(usize, u32),
+}
+
+/// A copy of `exit` for another job of the same process.
+fn copy_exit(exit: &Exit) -> Exit {
+    match exit {
+        Exit::Success => Exit::Success,
+        Exit::Failure(status) => Exit::Failure(*status),
+        Exit::Error(e) => Exit::Error(io::Error::new(e.kind(), e.to_string())),
+    }
+}
+
+impl QueueEvent {
+    /// The ID of the job this event is about.
+    pub fn job(&self) -> usize {
+        match self {
+            QueueEvent::Started(id)
+            | QueueEvent::Finished(id, _)
+            | QueueEvent::SpawnFailed(id, _)
+            | QueueEvent::Failed(id, _)
+            | QueueEvent::Retrying(id, _) => *id,
+        }
+    }
+}
+
+/// How a queue runs its jobs: everything but the engine and the jobs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueueSettings {
+    /// How many processes run at a time.
+    pub limit: usize,
+    /// The devices the jobs are spread across; empty lets the engine choose.
+    pub devices: Vec<Device>,
+    pub ffmpeg: PathBuf,
+    pub video: VideoSettings,
+    pub batching: BatchSettings,
+}
+
+impl Default for QueueSettings {
+    fn default() -> Self {
+        Self {
+            limit: 1,
+            devices: Vec::new(),
+            ffmpeg: PathBuf::from("ffmpeg"),
+            video: VideoSettings::default(),
+            batching: BatchSettings::default(),
+        }
+    }
+}
+
+/// Runs jobs in order, with at most `limit` waifu2x processes at a time.
+///
+/// With a device list, every job runs on a single device that has a free
+/// slot, so that all devices are kept busy. Without one, the engine is left
+/// to pick its devices. Images with identical settings may share a process
+/// in directory mode, as the batching settings say.
+#[derive(Debug)]
+pub struct JobQueue {
+    exe: PathBuf,
+    ffmpeg: PathBuf,
+    video: VideoSettings,
+    batching: BatchSettings,
+    limit: usize,
+    devices: Vec<Device>,
+    /// How many jobs each device has been handed, by position in `devices`.
+    handed_out: Vec<usize>,
+    jobs: Vec<QueuedJob>,
+    supervisor: Supervisor,
+}
+
+impl Default for JobQueue {
+    fn default() -> Self {
+        Self::new(PathBuf::from(crate::args::WAIFU2X_EXE), 1)
+    }
+}
+
+impl JobQueue {
+    pub fn new(exe: PathBuf, limit: usize) -> Self {
+        Self {
+            exe,
+            ffmpeg: PathBuf::from("ffmpeg"),
+            video: VideoSettings::default(),
+            batching: BatchSettings::default(),
+            limit: limit.max(1),
+            devices: Vec::new(),
+            handed_out: Vec::new(),
+            jobs: Vec::new(),
+            supervisor: Supervisor::default(),
+        }
+    }
+
+    /// An empty queue that runs `exe` as `settings` say.
+    pub fn with_settings(exe: PathBuf, settings: &QueueSettings) -> Self {
+        let mut queue = Self::new(exe, settings.limit);
+        queue.set_devices(settings.devices.clone());
+        queue.set_video(settings.ffmpeg.clone(), settings.video.clone());
+        queue.set_batching(settings.batching.clone());
+        queue
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit.max(1);
+    }
+
+    pub fn devices(&self) -> &[Device] {
+        &self.devices
+    }
+
+    /// Spreads the jobs across `devices` from now on. An empty list lets the
+    /// engine choose.
+    pub fn set_devices(&mut self, devices: Vec<Device>) {
+        self.handed_out = vec![0; devices.len()];
+        self.devices = devices;
+    }
+
+    /// Splits and reassembles videos with `ffmpeg`, encoding them as
+    /// `settings` say.
+    pub fn set_video(&mut self, ffmpeg: PathBuf, settings: VideoSettings) {
+        self.ffmpeg = ffmpeg;
+        self.video = settings;
+    }
+
+    /// Lets images with identical settings share a process as `batching`
+    /// says.
+    pub fn set_batching(&mut self, batching: BatchSettings) {
+        self.batching = batching;
+    }
+
+    /// Whether job `id` takes up a process slot: it is running, and not just
+    /// along in the directory mode process of another job.
+    fn holds_slot(&self, id: usize) -> bool {
+        let job = &self.jobs[id];
+        job.state == JobState::Running && job.staged.as_ref().is_none_or(|s| s.leader == id)
+    }
+
+    /// How many processes (or animation threads) are running.
+    fn running(&self) -> usize {
+        (0..self.jobs.len())
+            .filter(|&id| self.holds_slot(id))
+            .count()
+    }
+
+    /// The position of the device that should run the next job: of those
+    /// with a free slot, the one that has been handed the fewest jobs for
+    /// its weight.
+    fn free_device(&self) -> Option<usize> {
+        let running = |device: &Device| {
+            (0..self.jobs.len())
+                .filter(|&id| self.holds_slot(id) && self.jobs[id].device == Some(*device))
+                .count()
+        };
+
+        (0..self.devices.len())
+            .filter(|&i| running(&self.devices[i]) < self.devices[i].slots)
+            .min_by(|&a, &b| {
+                // handed_out[a] / weight[a] < handed_out[b] / weight[b]
+                let a_share = self.handed_out[a] as u64 * u64::from(self.devices[b].weight);
+                let b_share = self.handed_out[b] as u64 * u64::from(self.devices[a].weight);
+                a_share.cmp(&b_share)
+            })
+    }
+
+    /// Appends a job and returns its ID, which is its index in [`Self::jobs`].
+    pub fn push(&mut self, job: Job, args: Waifu2xArgs) -> usize {
+        let video = video::is_video(&job.input).then(|| VideoJob::new(&job, &args));
+        let animation = AnimationFormat::detect(&job.input).map(|_| AnimationJob::new(&job));
+
+        self.jobs.push(QueuedJob {
+            job,
+            args,
+            state: JobState::Pending,
+            device: None,
+            tile_size: None,
+            video,
+            animation,
+            staged: None,
+            capture: None,
+            exit_code: None,
+            error: None,
+        });
+        self.jobs.len() - 1
+    }
+
+    pub fn jobs(&self) -> &[QueuedJob] {
+        &self.jobs
+    }
+
+    /// Forgets about all jobs. Must not be called while jobs are running.
+    pub fn clear(&mut self) {
+        debug_assert!(self.supervisor.is_empty());
+        self.jobs.clear();
+    }
+
+    pub fn count(&self, state: JobState) -> usize {
+        self.jobs.iter().filter(|j| j.state == state).count()
+    }
+
+    /// The progress of the whole batch in percent, from 0 to 100. Jobs that
+    /// failed or were cancelled count as complete.
+    pub fn progress(&self) -> f32 {
+        if self.jobs.is_empty() {
+            return 100.0;
+        }
+
+        let total = self
+            .jobs
+            .iter()
+            .map(|j| {
+                if j.state.is_done() {
+                    100.0
+                } else {
+                    j.progress()
+                }
+            })
+            .sum::<f32>();
+
+        total / self.jobs.len() as f32
+    }
+
+    /// Whether every job has reached a final state.
+    pub fn is_finished(&self) -> bool {
+        self.jobs.iter().all(|j| j.state.is_done())
+    }
+
+    /// Marks every job that has not been started yet as cancelled.
+    pub fn cancel_pending(&mut self) {
+        for job in self.jobs.iter_mut() {
+            if job.state == JobState::Pending {
+                job.state = JobState::Cancelled;
+            }
+        }
+    }
+
+    /// Puts every failed job back into the queue, forgetting its old output.
+    /// Returns how many jobs are going to be retried.
+    pub fn retry_failed(&mut self) -> usize {
+        let mut retried = 0;
+
+        for job in self.jobs.iter_mut() {
+            if job.state == JobState::Failed {
+                job.state = JobState::Pending;
+                job.device = None;
+                if let Some(video) = &mut job.video {
+                    video.restart();
+                }
+                if let Some(animation) = &mut job.animation {
+                    animation.restart();
+                }
+                job.staged = None;
+                job.capture = None;
+                job.exit_code = None;
+                job.error = None;
+                retried += 1;
+            }
+        }
+
+        retried
+    }
+
+    /// A plain text report of every failed job with its complete output,
+    /// suitable for pasting into a bug report.
+    pub fn failure_log(&self) -> String {
+        let mut log = String::new();
+
+        for job in self.jobs.iter().filter(|j| j.state == JobState::Failed) {
+            log.push_str(&format!("==> {}\n", job.job.input.to_string_lossy()));
+
+            match job.exit_code {
+                Some(code) => log.push_str(&format!("exit code: {}\n", code)),
+                None => log.push_str("exit code: none\n"),
+            }
+
+            if let Some(error) = &job.error {
+                log.push_str(error);
+                log.push('\n');
+            }
+
+            log.push_str(&job.capture().log);
+            log.push('\n');
+        }
+
+        log
+    }
+
+    /// Stops the whole batch: running processes are killed and their partially
+    /// written outputs deleted, and every unfinished job is marked cancelled.
+    /// Processes that had already exited are recorded as they ended, so
+    /// finished outputs are kept, as are the files a shared directory mode
+    /// process had reported as done. Animations being split or encoded are
+    /// stopped between two frames, and their work folders removed.
+    pub fn cancel(&mut self) {
+        let (exited, killed) = self.supervisor.kill_all();
+
+        // Jobs that would move on to a next stage are cancelled below
+        let (mut events, mut next_stage) = (Vec::new(), Vec::new());
+        for (id, exit) in exited {
+            self.reap(id, exit, &mut events, &mut next_stage);
+        }
+
+        for id in killed {
+            if self.jobs[id].staged.is_some() {
+                self.cancel_group(id);
+                continue;
+            }
+
+            let job = &mut self.jobs[id];
+            job.state = JobState::Cancelled;
+
+            // The output may not have been created yet, and a leftover partial
+            // file is not worth failing the cancellation over
+            let _ = fs::remove_file(&job.job.output);
+
+            if let Some(animation) = &mut job.animation {
+                animation.cancel();
+            }
+        }
+
+        for job in self.jobs.iter_mut() {
+            if job.state != JobState::Running {
+                continue;
+            }
+
+            // An animation may have been encoded before it was stopped
+            let encoded = job.animation.as_mut().is_some_and(|a| a.cancel());
+            job.state = if encoded {
+                JobState::Succeeded
+            } else {
+                JobState::Cancelled
+            };
+        }
+
+        self.cancel_pending();
+    }
+
+    /// Settles the jobs of the killed directory mode process led by
+    /// `leader`: the files the engine had finished are moved into place,
+    /// the rest are cancelled, and the work folder is removed.
+    fn cancel_group(&mut self, leader: usize) {
+        let capture = self.jobs[leader].capture();
+        let mut work_dir = None;
+
+        for job in self.jobs.iter_mut() {
+            if job.state != JobState::Running {
+                continue;
+            }
+            let Some(staged) = job.staged.take_if(|s| s.leader == leader) else {
+                continue;
+            };
+
+            work_dir = Some(staged.work_dir.clone());
+            job.state = JobState::Cancelled;
+
+            if staged.is_done(&capture) {
+                match staged.deliver(&job.job.output) {
+                    Ok(()) => job.state = JobState::Succeeded,
+                    Err(e) => {
+                        job.state = JobState::Failed;
+                        job.error = Some(format!("Unable to move the output into place: {}", e));
+                    }
+                }
+            }
+        }
+
+        if let Some(work_dir) = work_dir {
+            let _ = fs::remove_dir_all(work_dir);
+        }
+    }
+
+    /// Reaps finished processes, then starts pending jobs until the
+    /// concurrency limit is reached.
+    ///
+    /// A job that ran out of memory is retried with the next smaller tile
+    /// size instead of failing, down to the smallest one. A video or an
+    /// animation moves on to its next stage on the same device.
+    pub fn tick(&mut self) -> Vec<QueueEvent> {
+        let mut events = Vec::new();
+        let mut next_stage = Vec::new();
+
+        for (id, job) in self.jobs.iter_mut().enumerate() {
+            if job.state != JobState::Running {
+                continue;
+            }
+            let Some(animation) = &mut job.animation else {
+                continue;
+            };
+            let Some(result) = animation.poll() else {
+                continue;
+            };
+
+            match result.and_then(|()| animation.advance()) {
+                Ok(true) => {
+                    next_stage.push(id);
+                    continue;
+                }
+                Ok(false) => {
+                    job.state = JobState::Succeeded;
+                    events.push(QueueEvent::Finished(id, Exit::Success));
+                }
+                Err(e) => {
+                    job.state = JobState::Failed;
+                    job.error = Some(e.clone());
+                    events.push(QueueEvent::Failed(id, io::Error::other(e)));
+                }
+            }
+        }
+
+        for (id, exit) in self.supervisor.poll() {
+            self.reap(id, exit, &mut events, &mut next_stage);
+        }
+
+        for id in next_stage {
+            let job = &self.jobs[id];
+            let args = match self.devices.iter().position(|d| Some(*d) == job.device) {
+                Some(position) => job.args.on_device(position, self.devices[position].id),
+                None => job.args.clone(),
+            };
+
+            events.push(self.start(id, args));
+        }
+
+        let mut pending = self
+            .jobs
+            .iter()
+            .enumerate()
+            .filter(|(_, j)| j.state == JobState::Pending)
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>()
+            .into_iter();
+
+        while self.running() < self.limit {
+            let device = if self.devices.is_empty() {
+                None
+            } else {
+                match self.free_device() {
+                    Some(position) => Some(position),
+                    None => break,
+                }
+            };
+
+            let Some(id) = pending.next() else { break };
+            // Already taken along by a directory mode process
+            if self.jobs[id].state != JobState::Pending {
+                continue;
+            }
+
+            let job = &mut self.jobs[id];
+
+            // waifu2x does not create missing output folders by itself
+            if let Some(parent) = job.job.output.parent() {
+                if let Err(e) = fs::create_dir_all(parent) {
+                    job.state = JobState::Failed;
+                    job.error = Some(format!("Unable to create the output folder: {}", e));
+                    events.push(QueueEvent::Failed(id, e));
+                    continue;
+                }
+            }
+
+            let args = match device {
+                Some(position) => {
+                    let device = self.devices[position];
+                    job.device = Some(device);
+                    self.handed_out[position] += 1;
+                    job.args.on_device(position, device.id)
+                }
+                None => job.args.clone(),
+            };
+
+            let pending = |other: usize| self.jobs[other].state == JobState::Pending;
+            match self.group_with(id, pending) {
+                Some(members) => events.extend(self.start_group(id, members, args)),
+                None => events.push(self.start(id, args)),
+            }
+        }
+
+        events
+    }
+
+    /// The images that can share a directory mode process with job `id`,
+    /// starting with it, or `None` if it should run by itself. Only jobs
+    /// that are `available` are taken along.
+    fn group_with(&self, id: usize, available: impl Fn(usize) -> bool) -> Option<Vec<usize>> {
+        let lead = &self.jobs[id];
+        let settings = |args: &Waifu2xArgs| Waifu2xArgs {
+            input: PathBuf::new(),
+            output: PathBuf::new(),
+            ..args.clone()
+        };
+        let lead_settings = settings(&lead.args);
+
+        let compatible = |other: usize| {
+            let job = &self.jobs[other];
+            available(other)
+                && job.video.is_none()
+                && job.animation.is_none()
+                && input::is_supported_image(&job.job.input)
+                && settings(&job.args) == lead_settings
+        };
+
+        if !compatible(id) {
+            return None;
+        }
+
+        let candidates = (id..self.jobs.len())
+            .filter(|&other| compatible(other))
+            .collect::<Vec<_>>();
+        let size = self.batching.group_size(candidates.len(), self.limit)?;
+
+        Some(candidates[..size].to_vec())
+    }
+
+    /// What every pending job is going to run, worked out the way
+    /// [`Self::tick`] starts them: which images share a directory mode
+    /// process, and every stage of a video or an animation. Which of
+    /// several devices runs a job is only decided once one has a free slot,
+    /// so their commands are templates, as are those of videos, whose frame
+    /// rate is not known before they are probed.
+    pub fn planned_steps(&self) -> Vec<Steps> {
+        let mut steps = vec![Steps::default(); self.jobs.len()];
+        let mut taken = HashSet::new();
+        let per_device = self.devices.len() > 1;
+
+        for (id, job) in self.jobs.iter().enumerate() {
+            if job.state != JobState::Pending || taken.contains(&id) {
+                continue;
+            }
+
+            let args = match self.devices.first() {
+                Some(device) if !per_device => job.args.on_device(0, device.id),
+                _ => job.args.clone(),
+            };
+
+            let (commands, template) = match (&job.video, &job.animation) {
+                (Some(video), _) => {
+                    let commands = video
+                        .planned_commands(&job.job, &args, &self.exe, &self.ffmpeg, &self.video)
+                        .iter()
+                        .map(|command| self.planned_line(command, per_device))
+                        .collect();
+                    (commands, true)
+                }
+                (_, Some(animation)) => {
+                    let upscale = animation.upscale_args(&args).to_command(&self.exe);
+                    let upscale = self.planned_line(&upscale, per_device);
+                    (animation.planned_steps(&job.job, upscale), per_device)
+                }
+                _ => {
+                    let available = |other: usize| {
+                        self.jobs[other].state == JobState::Pending && !taken.contains(&other)
+                    };
+
+                    match self.group_with(id, available) {
+                        Some(members) => {
+                            let work_dir = staging::work_dir(id, &job.job);
+                            let command =
+                                staging::group_args(&args, &work_dir).to_command(&self.exe);
+                            let group = Steps {
+                                commands: vec![self.planned_line(&command, per_device)],
+                                template: per_device,
+                            };
+
+                            for member in members {
+                                steps[member] = group.clone();
+                                taken.insert(member);
+                            }
+                            continue;
+                        }
+                        None => {
+                            let command = args.to_command(&self.exe);
+                            (vec![self.planned_line(&command, per_device)], per_device)
+                        }
+                    }
+                }
+            };
+
+            steps[id] = Steps { commands, template };
+        }
+
+        steps
+    }
+
+    /// The command line of `command`, with the arguments that depend on the
+    /// device left as placeholders if it runs the engine on one of several.
+    fn planned_line(&self, command: &Command, per_device: bool) -> String {
+        if per_device && command.get_program() == self.exe.as_os_str() {
+            plan::template_line(command)
+        } else {
+            plan::command_line(command)
+        }
+    }
+
+    /// Stages the inputs of `members` and starts one engine process for them
+    /// all with `args`, led by the job `leader`. If the inputs cannot be
+    /// staged, the leader runs by itself instead.
+    fn start_group(
+        &mut self,
+        leader: usize,
+        members: Vec<usize>,
+        args: Waifu2xArgs,
+    ) -> Vec<QueueEvent> {
+        let work_dir = staging::work_dir(leader, &self.jobs[leader].job);
+        let jobs = members
+            .iter()
+            .map(|&id| &self.jobs[id].job)
+            .collect::<Vec<_>>();
+        let format = args.format.clone().unwrap_or_default();
+
+        let staged = match staging::stage(&work_dir, leader, &jobs, &format) {
+            Ok(staged) => staged,
+            Err(_) => {
+                let _ = fs::remove_dir_all(&work_dir);
+                return vec![self.start(leader, args)];
+            }
+        };
+
+        let mut command = staging::group_args(&args, &work_dir).to_command(&self.exe);
+        let device = self.jobs[leader].device;
+
+        match self.supervisor.spawn(leader, &mut command) {
+            Ok(capture) => members
+                .into_iter()
+                .zip(staged)
+                .map(|(id, staged)| {
+                    let job = &mut self.jobs[id];
+                    job.state = JobState::Running;
+                    job.device = device;
+                    job.tile_size = args.tile_size();
+                    job.staged = Some(staged);
+                    job.capture = Some(capture.clone());
+                    QueueEvent::Started(id)
+                })
+                .collect(),
+            Err(e) => {
+                let _ = fs::remove_dir_all(&work_dir);
+
+                members
+                    .into_iter()
+                    .map(|id| {
+                        let job = &mut self.jobs[id];
+                        job.state = JobState::Failed;
+                        job.error = Some(format!("Unable to spawn a waifu2x instance: {}", e));
+                        QueueEvent::SpawnFailed(id, io::Error::new(e.kind(), e.to_string()))
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Records how the process of job `id` ended. Jobs that move on to
+    /// their next stage are added to `next_stage`.
+    fn reap(
+        &mut self,
+        id: usize,
+        exit: Exit,
+        events: &mut Vec<QueueEvent>,
+        next_stage: &mut Vec<usize>,
+    ) {
+        if self.jobs[id].staged.is_some() {
+            self.finish_group(id, exit, events);
+            return;
+        }
+
+        let job = &mut self.jobs[id];
+        let log = job.capture().log;
+
+        // ncnn does not always fail the process when an allocation fails
+        let out_of_memory = job.is_upscaling()
+            && !matches!(exit, Exit::Error(_))
+            && progress::is_allocation_error(&log);
+
+        if out_of_memory {
+            if let Some(tile_size) = smaller_tile_size(job.tile_size) {
+                job.args.tile_sizes = vec![tile_size];
+                job.state = JobState::Pending;
+                job.device = None;
+                job.capture = None;
+                events.push(QueueEvent::Retrying(id, tile_size));
+                return;
+            }
+        }
+
+        match &exit {
+            Exit::Success if out_of_memory => {
+                job.state = JobState::Failed;
+                job.exit_code = Some(0);
+                job.error = Some(String::from(
+                    "waifu2x ran out of memory, even with the smallest tile size",
+                ));
+            }
+            Exit::Success => {
+                job.exit_code = Some(0);
+
+                let advanced = match (&mut job.video, &mut job.animation) {
+                    (Some(video), _) => Some(video.advance(&log)),
+                    (_, Some(animation)) => Some(animation.advance()),
+                    _ => None,
+                };
+
+                match advanced {
+                    Some(Ok(true)) => {
+                        next_stage.push(id);
+                        return;
+                    }
+                    Some(Err(e)) => {
+                        job.state = JobState::Failed;
+                        job.error = Some(e.clone());
+                        events.push(QueueEvent::Failed(id, io::Error::other(e)));
+                        return;
+                    }
+                    Some(Ok(false)) | None => job.state = JobState::Succeeded,
+                }
+            }
+            Exit::Failure(status) => {
+                job.state = JobState::Failed;
+                job.exit_code = status.code();
+            }
+            Exit::Error(e) => {
+                job.state = JobState::Failed;
+                job.error = Some(format!("Unable to wait for waifu2x: {}", e));
+            }
+        }
+
+        events.push(QueueEvent::Finished(id, exit));
+    }
+
+    /// Settles every job of the directory mode process led by `leader` once
+    /// it has exited: the files the engine finished are moved into place,
+    /// and the rest fail, or are retried with a smaller tile size if the
+    /// engine ran out of memory.
+    fn finish_group(&mut self, leader: usize, exit: Exit, events: &mut Vec<QueueEvent>) {
+        let capture = self.jobs[leader].capture();
+        let log = &capture.log;
+        let out_of_memory = !matches!(exit, Exit::Error(_)) && progress::is_allocation_error(log);
+        let smaller = smaller_tile_size(self.jobs[leader].tile_size).filter(|_| out_of_memory);
+        let mut work_dir = None;
+
+        for (id, job) in self.jobs.iter_mut().enumerate() {
+            if job.state != JobState::Running {
+                continue;
+            }
+            let Some(staged) = job.staged.take_if(|s| s.leader == leader) else {
+                continue;
+            };
+
+            work_dir = Some(staged.work_dir.clone());
+            job.exit_code = match &exit {
+                Exit::Success => Some(0),
+                Exit::Failure(status) => status.code(),
+                Exit::Error(_) => None,
+            };
+
+            if staged.is_done(&capture) {
+                match staged.deliver(&job.job.output) {
+                    Ok(()) => job.state = JobState::Succeeded,
+                    Err(e) => {
+                        job.state = JobState::Failed;
+                        job.error = Some(format!("Unable to move the output into place: {}", e));
+                    }
+                }
+            } else if let Some(tile_size) = smaller {
+                job.args.tile_sizes = vec![tile_size];
+                job.state = JobState::Pending;
+                job.device = None;
+                job.capture = None;
+                job.exit_code = None;
+                events.push(QueueEvent::Retrying(id, tile_size));
+                continue;
+            } else {
+                job.state = JobState::Failed;
+                job.error = Some(match &exit {
+                    Exit::Error(e) => format!("Unable to wait for waifu2x: {}", e),
+                    _ if out_of_memory => {
+                        String::from("waifu2x ran out of memory, even with the smallest tile size")
+                    }
+                    _ => staged
+                        .log_line(log)
+                        .unwrap_or("waifu2x did not write this file")
+                        .to_owned(),
+                });
+            }
+
+            events.push(QueueEvent::Finished(id, copy_exit(&exit)));
+        }
+
+        if let Some(work_dir) = work_dir {
+            let _ = fs::remove_dir_all(work_dir);
+        }
+    }
+
+    /// Spawns the process of job `id` with `args`: the engine for an image,
+    /// or the current stage for a video or an animation. Animations are
+    /// split and encoded on a thread instead of a process.
+    fn start(&mut self, id: usize, args: Waifu2xArgs) -> QueueEvent {
+        let job = &mut self.jobs[id];
+        job.tile_size = args.tile_size();
+
+        let (command, program) = match (&mut job.video, &mut job.animation) {
+            (Some(video), _) => {
+                let command = video.command(&job.job, &args, &self.exe, &self.ffmpeg, &self.video);
+                let program = match video.stage {
+                    video::Stage::Upscale => "a waifu2x instance",
+                    video::Stage::Probe => "ffprobe",
+                    _ => "ffmpeg",
+                };
+                (command.map(Some), program)
+            }
+            (_, Some(animation)) => {
+                let command = animation.start(&job.job, &args, &self.exe);
+                let program = match animation.stage {
+                    animation::Stage::Upscale => "a waifu2x instance",
+                    _ => "a worker thread",
+                };
+                (command, program)
+            }
+            _ => (Ok(Some(args.to_command(&self.exe))), "a waifu2x instance"),
+        };
+        let upscaling = job.is_upscaling();
+
+        let spawned = command.and_then(|command| match command {
+            Some(mut command) => self.supervisor.spawn(id, &mut command).map(Some),
+            None => Ok(None),
+        });
+
+        match spawned {
+            Ok(capture) => {
+                job.state = JobState::Running;
+                // The engine's output stays on display while a thread encodes
+                if capture.is_some() {
+                    job.capture = capture;
+                }
+                QueueEvent::Started(id)
+            }
+            Err(e) => {
+                job.state = JobState::Failed;
+                job.error = Some(format!("Unable to spawn {}: {}", program, e));
+                if upscaling {
+                    QueueEvent::SpawnFailed(id, e)
+                } else {
+                    QueueEvent::Failed(id, e)
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//! Directory mode: images that share identical settings are staged into a
+//! temporary folder and upscaled by a single engine process (`-i dir -o
+//! dir`), instead of one process each. Loading the model and initializing
+//! Vulkan take longer than upscaling a small sprite, so this is what makes
+//! large batches of small files fast.
+//!
+//! The outputs are moved to exactly the paths the files would have been
+//! written to one by one.
+
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use serde::{Deserialize, Serialize};
+
+use crate::args::Waifu2xArgs;
+use crate::job::{Format, Job};
+use crate::progress::Capture;
+use crate::video;
+
+/// How jobs are spread across engine processes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+    /// One process per file.
+    #[default]
+    PerFile,
+    /// Files with identical settings share a process.
+    Directory,
+}
+
+/// When files are upscaled together in directory mode.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BatchSettings {
+    pub strategy: Strategy,
+    /// The fewest files with identical settings that are worth sharing a
+    /// process. Smaller sets get one process per file.
+    pub threshold: usize,
+}
+
+impl Default for BatchSettings {
+    fn default() -> Self {
+        Self {
+            strategy: Strategy::default(),
+            threshold: 8,
+        }
+    }
+}
+
+impl BatchSettings {
+    /// How many of `count` waiting files with identical settings the next
+    /// process should take, or `None` if each should get its own. The files
+    /// are split evenly across the `limit` processes that may run at once,
+    /// so that every device is kept busy.
+    pub fn group_size(&self, count: usize, limit: usize) -> Option<usize> {
+        let threshold = self.threshold.max(2);
+
+        if self.strategy == Strategy::PerFile || count < threshold {
+            return None;
+        }
+
+        Some(count.div_ceil(limit.max(1)).max(threshold).min(count))
+    }
+}
+
+/// Where a job that shares a directory mode process has its input staged
+/// and its output picked up.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Staged {
+    /// The ID of the job whose process is shared.
+    pub leader: usize,
+    pub work_dir: PathBuf,
+    pub input: PathBuf,
+    pub output: PathBuf,
+}
+
+impl Staged {
+    /// Whether the engine has reported the file as done in `capture`.
+    pub fn is_done(&self, capture: &Capture) -> bool {
+        self.output
+            .file_name()
+            .is_some_and(|name| capture.done.contains(&*name.to_string_lossy()))
+    }
+
+    /// The last line of `log` about the file, such as the engine's error for
+    /// it.
+    pub fn log_line<'a>(&self, log: &'a str) -> Option<&'a str> {
+        let name = self.input.file_name()?.to_str()?;
+
+        log.lines().rev().map(str::trim).find(|l| l.contains(name))
+    }
+
+    /// Moves the upscaled file to `to`, where it would have been written
+    /// without directory mode.
+    pub fn deliver(&self, to: &Path) -> io::Result<()> {
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        if fs::rename(&self.output, to).is_err() {
+            // Across drives a move is a copy
+            fs::copy(&self.output, to)?;
+            fs::remove_file(&self.output)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The folder of the group led by the job `leader`.
+pub fn work_dir(leader: usize, job: &Job) -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    process::id().hash(&mut hasher);
+    leader.hash(&mut hasher);
+    job.input.hash(&mut hasher);
+    job.output.hash(&mut hasher);
+
+    env::temp_dir()
+        .join("waifu2x-gui-batch")
+        .join(format!("{:016x}", hasher.finish()))
+}
+
+/// Stages the inputs of `jobs` in a fresh `work_dir` under names of their
+/// own, so that files from different folders cannot collide.
+pub fn stage(
+    work_dir: &Path,
+    leader: usize,
+    jobs: &[&Job],
+    format: &Format,
+) -> io::Result<Vec<Staged>> {
+    if work_dir.exists() {
+        fs::remove_dir_all(work_dir)?;
+    }
+
+    let (input_dir, output_dir) = (work_dir.join("input"), work_dir.join("output"));
+    fs::create_dir_all(&input_dir)?;
+    fs::create_dir_all(&output_dir)?;
+
+    let mut staged = Vec::with_capacity(jobs.len());
+
+    for (index, job) in jobs.iter().enumerate() {
+        let name = format!("{:08}", index + 1);
+        let mut input = input_dir.join(&name);
+        if let Some(extension) = job.input.extension() {
+            input.set_extension(extension);
+        }
+
+        video::stage_file(&job.input, &input)?;
+
+        staged.push(Staged {
+            leader,
+            work_dir: work_dir.to_path_buf(),
+            input,
+            // The engine names its outputs after the inputs
+            output: output_dir.join(format!("{}.{}", name, format.extension())),
+        });
+    }
+
+    Ok(staged)
+}
+
+/// The engine arguments that upscale every file staged in `work_dir`.
+pub fn group_args(args: &Waifu2xArgs, work_dir: &Path) -> Waifu2xArgs {
+    Waifu2xArgs {
+        input: work_dir.join("input"),
+        output: work_dir.join("output"),
+        ..args.clone()
+    }
+}
@@ -0,0 +1,486 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::animation::AnimationFormat;
+use crate::args::{self, Waifu2xArgs};
+use crate::device::Device;
+use crate::engine::Engine;
+use crate::input::{self, AddSummary, FolderFilter, InputList};
+use crate::job::{Format, Job};
+use crate::model::{self, Model};
+use crate::naming::{self, FileContext, NameError};
+use crate::plan::{CollisionPolicy, Plan};
+use crate::queue::{JobQueue, QueueSettings};
+use crate::staging::BatchSettings;
+use crate::template::{Template, TemplateError};
+use crate::validate::{self, FieldErrors};
+use crate::video::{self, VideoSettings};
+
+pub struct Waifu2xState {
+    pub inputs: InputList,
+    pub output_dir: OsString,
+    pub engine: Engine,
+    /// The executables the user picked, per engine.
+    pub engine_paths: BTreeMap<Engine, PathBuf>,
+    /// The executables [`Engine::discover`] found, per engine. Discovery runs
+    /// every candidate, so it is left to the caller: the GUI does it on a
+    /// worker thread.
+    pub discovered_engines: BTreeMap<Engine, PathBuf>,
+    pub scale_level: i32,
+    pub denoise_level: i32,
+    pub tta_mode: bool,
+    pub format: Format,
+    pub thread_count: String,
+    pub gpu_id: String,
+    /// The tile size, or one per device. Empty or `auto` lets the engine
+    /// choose.
+    pub tile_size: String,
+    pub model_path: String,
+    pub filename_format: String,
+    /// The maximum number of concurrent waifu2x processes. 0 means one per GPU.
+    pub job_limit: usize,
+    /// Whether "Add folder" also searches subfolders.
+    pub recursive: bool,
+    /// Only files matching these patterns (e.g. `*.png;*.jpg`) are added from
+    /// folders.
+    pub include_patterns: String,
+    /// Files and folders matching these patterns are skipped when adding
+    /// folders.
+    pub exclude_patterns: String,
+    /// Whether outputs are placed in the same subfolder (relative to the
+    /// added folder) as their input.
+    pub mirror_structure: bool,
+    /// How videos are encoded.
+    pub video: VideoSettings,
+    /// The container animated images are written in, `None` to keep the
+    /// input's.
+    pub animation_format: Option<AnimationFormat>,
+    /// When images share an engine process in directory mode.
+    pub batching: BatchSettings,
+    /// What happens to outputs that already exist.
+    pub if_exists: CollisionPolicy,
+    /// The ffmpeg the user picked. Empty means it is discovered.
+    pub ffmpeg_path: PathBuf,
+    /// The folders that were added, used to mirror their structure.
+    pub input_roots: Vec<PathBuf>,
+    pub queue: JobQueue,
+}
+
+impl Default for Waifu2xState {
+    fn default() -> Self {
+        Self {
+            inputs: InputList::default(),
+            output_dir: OsString::new(),
+            engine: Engine::Waifu2x,
+            engine_paths: BTreeMap::new(),
+            discovered_engines: BTreeMap::new(),
+            scale_level: 1,
+            denoise_level: -1,
+            tta_mode: false,
+            format: Format::Png,
+            thread_count: String::new(),
+            gpu_id: String::new(),
+            tile_size: String::new(),
+            model_path: String::new(),
+            filename_format: String::from("{name}_{scale}x_{denoise}n"),
+            job_limit: 0,
+            recursive: false,
+            include_patterns: input::default_include(),
+            exclude_patterns: String::new(),
+            mirror_structure: false,
+            video: VideoSettings::default(),
+            animation_format: None,
+            batching: BatchSettings::default(),
+            if_exists: CollisionPolicy::default(),
+            ffmpeg_path: PathBuf::new(),
+            input_roots: Vec::new(),
+            queue: JobQueue::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PlanError {
+    /// No input files have been selected.
+    NoInput,
+    /// Neither denoising nor upscaling is enabled, so there is nothing to do.
+    NothingToDo,
+    /// An entry of the GPU ID field is not a valid device, or is listed
+    /// twice.
+    InvalidGpuId(String),
+    /// The engine does not accept the upscale ratio.
+    UnsupportedScale(Engine, i32),
+    /// The engine does not accept the denoise level.
+    UnsupportedDenoise(Engine, i32),
+    /// An advanced option is invalid; see [`Waifu2xState::field_errors`].
+    InvalidOption(String),
+    Name(NameError),
+}
+
+impl fmt::Display for PlanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlanError::NoInput => write!(f, "No input file is selected."),
+            PlanError::NothingToDo => {
+                write!(f, "A denoise level and/or upscale ratio is not selected.")
+            }
+            PlanError::InvalidGpuId(id) => write!(f, "\"{}\" is not a valid GPU ID.", id),
+            PlanError::UnsupportedScale(engine, scale) => {
+                write!(f, "{} does not support {}x upscaling.", engine, scale)
+            }
+            PlanError::UnsupportedDenoise(engine, level) => {
+                write!(f, "{} does not support denoise level {}.", engine, level)
+            }
+            PlanError::InvalidOption(e) => write!(f, "{}.", e),
+            PlanError::Name(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for PlanError {}
+
+impl From<NameError> for PlanError {
+    fn from(e: NameError) -> Self {
+        PlanError::Name(e)
+    }
+}
+
+impl Waifu2xState {
+    pub fn set_denoise_level(&mut self, level: i32) {
+        self.denoise_level = level;
+    }
+
+    pub fn set_scale_level(&mut self, level: i32) {
+        self.scale_level = level;
+    }
+
+    /// Switches to `engine`, moving the scale and denoise level to the
+    /// nearest values it supports and dropping a model of the previous
+    /// engine.
+    pub fn set_engine(&mut self, engine: Engine) {
+        if self.engine.models().contains(&self.model_path.as_str()) {
+            self.model_path.clear();
+        }
+
+        if !engine.supports_scale(&self.model_path, self.scale_level) {
+            self.scale_level = nearest(engine.scales(&self.model_path), self.scale_level);
+        }
+
+        if !engine.supports_denoise(self.denoise_level) {
+            self.denoise_level = nearest(engine.denoise_levels(), self.denoise_level);
+        }
+
+        self.engine = engine;
+    }
+
+    /// The executable of the selected engine: the one the user picked, else
+    /// a discovered one, else its bare name for the usual PATH lookup.
+    pub fn engine_exe(&self) -> PathBuf {
+        self.engine_paths
+            .get(&self.engine)
+            .cloned()
+            .or_else(|| self.discovered_engines.get(&self.engine).cloned())
+            .unwrap_or_else(|| PathBuf::from(self.engine.executable()))
+    }
+
+    /// The ffmpeg that splits and encodes videos: the one the user picked,
+    /// else the one found next to this program or on the `PATH`.
+    pub fn ffmpeg_exe(&self) -> PathBuf {
+        Some(self.ffmpeg_path.clone())
+            .filter(|p| !p.as_os_str().is_empty())
+            .or_else(video::discover_ffmpeg)
+            .unwrap_or_else(|| PathBuf::from("ffmpeg"))
+    }
+
+    pub fn folder_filter(&self) -> FolderFilter {
+        FolderFilter::new(
+            self.recursive,
+            &self.include_patterns,
+            &self.exclude_patterns,
+        )
+    }
+
+    /// Empties the input list, forgetting the added folders as well.
+    pub fn clear_inputs(&mut self) {
+        self.inputs.clear();
+        self.input_roots.clear();
+    }
+
+    /// Adds every accepted file under `root` that is not selected yet.
+    /// Subfolders that cannot be read are skipped and reported in the
+    /// summary.
+    pub fn add_folder(&mut self, root: &Path) -> io::Result<AddSummary> {
+        let scan = self.folder_filter().scan(root)?;
+        let mut summary = AddSummary {
+            filtered: scan.filtered,
+            unreadable: scan.unreadable,
+            ..Default::default()
+        };
+
+        for file in scan.files {
+            self.add_file(file, &mut summary);
+        }
+
+        if !self.input_roots.iter().any(|r| r == root) {
+            self.input_roots.push(root.to_path_buf());
+        }
+
+        Ok(summary)
+    }
+
+    /// Adds a mix of files and folders, as dropped onto the window. Folders
+    /// are expanded with the folder filter, files waifu2x cannot read are
+    /// skipped.
+    pub fn add_paths(&mut self, paths: &[PathBuf]) -> AddSummary {
+        let mut summary = AddSummary::default();
+
+        for path in paths {
+            if !path.is_dir() {
+                self.add_file(path.clone(), &mut summary);
+                continue;
+            }
+
+            match self.add_folder(path) {
+                Ok(folder) => summary.merge(folder),
+                Err(_) => summary.unreadable.push(path.clone()),
+            }
+        }
+
+        summary
+    }
+
+    /// Adds `file` unless waifu2x cannot read it or it is selected already,
+    /// counting it in `summary`.
+    fn add_file(&mut self, file: PathBuf, summary: &mut AddSummary) {
+        if !input::is_supported(&file) {
+            summary.unsupported += 1;
+        } else if self.inputs.add(file) {
+            summary.added += 1;
+        } else {
+            summary.duplicates += 1;
+        }
+    }
+
+    /// The folder `input` is written to: `output_dir` itself, or when
+    /// mirroring, the subfolder matching the input's place in its added folder.
+    fn output_dir_for(&self, output_dir: &Path, input: &Path) -> PathBuf {
+        if !self.mirror_structure {
+            return output_dir.to_path_buf();
+        }
+
+        // The innermost root wins if added folders are nested
+        let relative = self
+            .input_roots
+            .iter()
+            .filter_map(|root| input.strip_prefix(root).ok())
+            .min_by_key(|relative| relative.components().count());
+
+        match relative.and_then(Path::parent) {
+            Some(parent) => output_dir.join(parent),
+            None => output_dir.to_path_buf(),
+        }
+    }
+
+    /// Checks that the settings describe a meaningful batch, before the user
+    /// is asked anything about the output directory.
+    pub fn validate(&self) -> Result<(), PlanError> {
+        if self.inputs.is_empty() {
+            return Err(PlanError::NoInput);
+        }
+
+        if self.denoise_level == -1 && self.scale_level == 1 {
+            return Err(PlanError::NothingToDo);
+        }
+
+        if !self
+            .engine
+            .supports_scale(&self.model_path, self.scale_level)
+        {
+            return Err(PlanError::UnsupportedScale(self.engine, self.scale_level));
+        }
+
+        if !self.engine.supports_denoise(self.denoise_level) {
+            return Err(PlanError::UnsupportedDenoise(
+                self.engine,
+                self.denoise_level,
+            ));
+        }
+
+        self.devices()?;
+        self.template().map_err(NameError::from)?;
+
+        if let Some(e) = self.field_errors().messages().first() {
+            return Err(PlanError::InvalidOption(e.to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// Checks the advanced options one by one.
+    pub fn field_errors(&self) -> FieldErrors {
+        let devices = self.devices();
+        let device_count = devices.as_ref().map_or(1, |d| d.len().max(1));
+
+        FieldErrors {
+            threads: validate::check_threads(&self.thread_count, device_count).err(),
+            gpu_id: devices.err().map(|e| e.to_string()),
+            tile_size: validate::check_tile_sizes(&self.tile_size, device_count).err(),
+            model: validate::check_model(
+                self.engine,
+                &self.engine_exe(),
+                &self.model_path,
+                self.scale_level,
+                self.denoise_level,
+            )
+            .err(),
+        }
+    }
+
+    /// The selected model, or `None` if its folder cannot be found.
+    pub fn model(&self) -> Option<Model> {
+        Model::locate(self.engine, &self.engine_exe(), &self.model_path)
+    }
+
+    /// The models found next to the engine executable.
+    pub fn models(&self) -> Vec<Model> {
+        model::scan(self.engine, &self.engine_exe())
+    }
+
+    /// Parses the GPU ID field into the devices to spread the batch across.
+    /// `auto` (or nothing) yields an empty list.
+    pub fn devices(&self) -> Result<Vec<Device>, PlanError> {
+        Device::parse_list(&self.gpu_id).map_err(PlanError::InvalidGpuId)
+    }
+
+    /// The effective concurrency limit, resolving 0 to one job per device
+    /// slot.
+    pub fn effective_job_limit(&self) -> usize {
+        match self.job_limit {
+            0 => self
+                .devices()
+                .map_or(1, |d| d.iter().map(|d| d.slots).sum::<usize>().max(1)),
+            n => n,
+        }
+    }
+
+    /// How a queue runs its jobs: the job limit, devices, video encoding
+    /// and batching.
+    pub fn queue_settings(&self) -> Result<QueueSettings, PlanError> {
+        Ok(QueueSettings {
+            limit: self.effective_job_limit(),
+            devices: self.devices()?,
+            ffmpeg: self.ffmpeg_exe(),
+            video: self.video.clone(),
+            batching: self.batching.clone(),
+        })
+    }
+
+    /// Translates the settings into the waifu2x arguments for `job`.
+    pub fn args_for(&self, job: &Job) -> Result<Waifu2xArgs, PlanError> {
+        Ok(Waifu2xArgs {
+            engine: self.engine,
+            input: job.input.clone(),
+            output: job.output.clone(),
+            scale: self.scale_level,
+            denoise: self.denoise_level,
+            tile_sizes: args::parse_tile_sizes(&self.tile_size)
+                .map_err(PlanError::InvalidOption)?,
+            // The queue narrows this down to one device per job
+            gpus: self.devices()?.iter().map(|d| d.id).collect(),
+            threads: Some(self.thread_count.clone()).filter(|t| !t.is_empty()),
+            model: Some(PathBuf::from(&self.model_path)).filter(|m| !m.as_os_str().is_empty()),
+            tta: self.tta_mode,
+            format: Some(self.format.clone()),
+            // Progress is only reported in verbose mode
+            verbose: true,
+        })
+    }
+
+    /// Parses the output filename template.
+    pub fn template(&self) -> Result<Template, TemplateError> {
+        Template::parse(&self.filename_format)
+    }
+
+    /// Turns every selected file into a [`Job`] writing into `output_dir`.
+    pub fn plan_jobs(&self, output_dir: &Path) -> Result<Vec<Job>, PlanError> {
+        self.validate()?;
+
+        let template = self.template().map_err(NameError::from)?;
+        let needs_dimensions = template.uses("width") || template.uses("height");
+        let date = naming::today();
+
+        self.inputs
+            .paths()
+            .enumerate()
+            .map(|(index, input)| {
+                let input = input.to_path_buf();
+                let context = FileContext {
+                    input: &input,
+                    index,
+                    date: &date,
+                    dimensions: if needs_dimensions {
+                        naming::image_dimensions(&input)
+                    } else {
+                        None
+                    },
+                    state: self,
+                };
+                let output_dir = self.output_dir_for(output_dir, &input);
+                let output = naming::output_path(&output_dir, &template, &context)?;
+                Ok(Job { input, output })
+            })
+            .collect()
+    }
+
+    /// Plans the jobs of [`Self::plan_jobs`] with their engine arguments,
+    /// deciding what happens to outputs that already exist.
+    pub fn plan(&self, output_dir: &Path) -> Result<Plan, PlanError> {
+        let jobs = self
+            .plan_jobs(output_dir)?
+            .into_iter()
+            .map(|job| Ok((job.clone(), self.args_for(&job)?)))
+            .collect::<Result<Vec<_>, PlanError>>()?;
+
+        let mut plan = Plan::new(
+            self.engine_exe(),
+            self.queue_settings()?,
+            jobs,
+            self.if_exists,
+        );
+
+        if !self.ffmpeg_exe().is_file() {
+            for job in plan.jobs.iter_mut() {
+                if video::is_video(&job.job.input) {
+                    job.warnings
+                        .push(String::from("Videos need ffmpeg, which was not found"));
+                }
+            }
+        }
+
+        Ok(plan)
+    }
+
+    /// Replaces the queue with one that runs the runnable jobs of `plan`,
+    /// exactly as planned.
+    pub fn execute(&mut self, plan: &Plan) {
+        let mut queue = JobQueue::with_settings(plan.exe.clone(), &plan.settings);
+        for planned in plan.runnable() {
+            queue.push(planned.job.clone(), planned.args.clone());
+        }
+
+        self.queue = queue;
+    }
+}
+
+/// The value in `values` closest to `value`, preferring the smaller one on a
+/// tie.
+fn nearest(values: &[i32], value: i32) -> i32 {
+    values
+        .iter()
+        .copied()
+        .min_by_key(|v| ((v - value).abs(), *v))
+        .unwrap_or(value)
+}
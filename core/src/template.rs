@@ -0,0 +1,295 @@
+//! The "Output Name" template language.
+//!
+//! A template is literal text with `{variable}` or `{variable:spec}`
+//! placeholders in it. `{{` and `}}` stand for literal braces. The spec is
+//! either a width (`{index:04}` pads to four digits with zeros, `{scale:3}`
+//! pads with spaces) or a comma-separated list of `value=replacement` pairs
+//! (`{denoise:none=off,3=max}`), where the value `none` matches a variable
+//! that is not set.
+
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+/// Every variable a template may refer to.
+pub const VARIABLES: &[&str] = &[
+    "name", "ext", "parent", "index", "scale", "denoise", "model", "gpu", "tta", "date", "width",
+    "height",
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TemplateError {
+    pub kind: TemplateErrorKind,
+    /// The byte range of the offending part of the template.
+    pub span: Range<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TemplateErrorKind {
+    /// A `{` without a matching `}`.
+    UnclosedPlaceholder,
+    /// A lone `}` that is neither escaped nor closing a placeholder.
+    UnmatchedBrace,
+    UnknownVariable(String),
+    InvalidSpec(String),
+    /// Neither `{name}` nor `{index}` appears, so every output would collide.
+    MissingName,
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let at = self.span.start + 1;
+
+        match &self.kind {
+            TemplateErrorKind::UnclosedPlaceholder => {
+                write!(
+                    f,
+                    "Unclosed '{{' at position {} (use '{{{{' for a literal brace)",
+                    at
+                )
+            }
+            TemplateErrorKind::UnmatchedBrace => {
+                write!(
+                    f,
+                    "Unmatched '}}' at position {} (use '}}}}' for a literal brace)",
+                    at
+                )
+            }
+            TemplateErrorKind::UnknownVariable(name) => {
+                write!(f, "Unknown placeholder {{{}}} at position {}", name, at)
+            }
+            TemplateErrorKind::InvalidSpec(spec) => {
+                write!(f, "Invalid format \"{}\" at position {}", spec, at)
+            }
+            TemplateErrorKind::MissingName => {
+                write!(
+                    f,
+                    "Output filename must contain a {{name}} or {{index}} section!"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Spec {
+    None,
+    Width { width: usize, zero: bool },
+    Map(Vec<(String, String)>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Literal(String),
+    Variable { name: String, spec: Spec },
+}
+
+/// A parsed output name template.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+/// The value of a template variable.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Text(OsString),
+    /// The variable is not set. This matches `none` in a mapping spec and
+    /// is otherwise rendered as the given text.
+    Unset(String),
+}
+
+/// Supplies the values of the template variables for one output file.
+pub trait Context {
+    fn value(&self, variable: &str) -> Value;
+}
+
+impl FromStr for Template {
+    type Err = TemplateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Template::parse(s)
+    }
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Template, TemplateError> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.char_indices().peekable();
+
+        while let Some((start, c)) = chars.next() {
+            match c {
+                '{' if matches!(chars.peek(), Some((_, '{'))) => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if matches!(chars.peek(), Some((_, '}'))) => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '}' => {
+                    return Err(TemplateError {
+                        kind: TemplateErrorKind::UnmatchedBrace,
+                        span: start..start + 1,
+                    });
+                }
+                '{' => {
+                    let end = match template[start..].find('}') {
+                        Some(x) => start + x + 1,
+                        None => {
+                            return Err(TemplateError {
+                                kind: TemplateErrorKind::UnclosedPlaceholder,
+                                span: start..template.len(),
+                            })
+                        }
+                    };
+
+                    let placeholder = &template[start + 1..end - 1];
+                    let span = start..end;
+
+                    if placeholder.contains('{') {
+                        return Err(TemplateError {
+                            kind: TemplateErrorKind::UnclosedPlaceholder,
+                            span: start..start + 1,
+                        });
+                    }
+
+                    let (name, spec) = match placeholder.split_once(':') {
+                        Some((name, spec)) => (name.trim(), Some(spec)),
+                        None => (placeholder.trim(), None),
+                    };
+
+                    if !VARIABLES.contains(&name) {
+                        return Err(TemplateError {
+                            kind: TemplateErrorKind::UnknownVariable(name.to_owned()),
+                            span,
+                        });
+                    }
+
+                    let spec = match spec {
+                        Some(spec) => parse_spec(spec).ok_or_else(|| TemplateError {
+                            kind: TemplateErrorKind::InvalidSpec(spec.to_owned()),
+                            span: span.clone(),
+                        })?,
+                        None => Spec::None,
+                    };
+
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+
+                    segments.push(Segment::Variable {
+                        name: name.to_owned(),
+                        spec,
+                    });
+
+                    while chars.peek().is_some_and(|(i, _)| *i < end) {
+                        chars.next();
+                    }
+                }
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        let parsed = Template { segments };
+
+        if !parsed.uses("name") && !parsed.uses("index") {
+            return Err(TemplateError {
+                kind: TemplateErrorKind::MissingName,
+                span: 0..template.len(),
+            });
+        }
+
+        Ok(parsed)
+    }
+
+    /// Returns whether `variable` appears anywhere in the template.
+    pub fn uses(&self, variable: &str) -> bool {
+        self.segments
+            .iter()
+            .any(|s| matches!(s, Segment::Variable { name, .. } if name == variable))
+    }
+
+    pub fn render(&self, context: &dyn Context) -> OsString {
+        let mut result = OsString::new();
+
+        for segment in self.segments.iter() {
+            match segment {
+                Segment::Literal(text) => result.push(text),
+                Segment::Variable { name, spec } => {
+                    result.push(render_value(context.value(name), spec))
+                }
+            }
+        }
+
+        result
+    }
+}
+
+fn parse_spec(spec: &str) -> Option<Spec> {
+    if !spec.is_empty() && spec.bytes().all(|b| b.is_ascii_digit()) {
+        return Some(Spec::Width {
+            width: spec.parse().ok()?,
+            zero: spec.starts_with('0'),
+        });
+    }
+
+    spec.split(',')
+        .map(|pair| {
+            let (from, to) = pair.split_once('=')?;
+            Some((from.trim().to_owned(), to.to_owned()))
+        })
+        .collect::<Option<Vec<_>>>()
+        .map(Spec::Map)
+}
+
+fn render_value(value: Value, spec: &Spec) -> OsString {
+    match spec {
+        Spec::None => value_text(value),
+        Spec::Width { width, zero } => {
+            let text = value_text(value);
+            let len = text.to_string_lossy().chars().count();
+            let (sign, digits) = match text.to_str() {
+                Some(s) if *zero && s.starts_with('-') => ("-", OsStr::new(&s[1..])),
+                _ => ("", text.as_os_str()),
+            };
+
+            let mut padded = OsString::from(sign);
+            let fill = if *zero { "0" } else { " " };
+            for _ in len..*width {
+                padded.push(fill);
+            }
+            padded.push(digits);
+            padded
+        }
+        Spec::Map(pairs) => {
+            let key = match &value {
+                Value::Unset(_) => String::from("none"),
+                Value::Int(i) => i.to_string(),
+                Value::Text(t) => t.to_string_lossy().into_owned(),
+            };
+
+            match pairs.iter().find(|(from, _)| *from == key) {
+                Some((_, to)) => OsString::from(to),
+                None => value_text(value),
+            }
+        }
+    }
+}
+
+fn value_text(value: Value) -> OsString {
+    match value {
+        Value::Int(i) => OsString::from(i.to_string()),
+        Value::Text(t) => t,
+        Value::Unset(t) => OsString::from(t),
+    }
+}
@@ -0,0 +1,120 @@
+//! Checks of the advanced options, so that mistakes show up next to the
+//! field instead of as a failed engine run much later.
+
+use std::path::{Path, PathBuf};
+
+use crate::args::{self, ThreadSpec};
+use crate::engine::Engine;
+
+/// What is wrong with each advanced option, if anything.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FieldErrors {
+    pub threads: Option<String>,
+    pub gpu_id: Option<String>,
+    pub tile_size: Option<String>,
+    pub model: Option<String>,
+}
+
+impl FieldErrors {
+    pub fn is_empty(&self) -> bool {
+        self.messages().is_empty()
+    }
+
+    /// Every error, in the order the fields appear.
+    pub fn messages(&self) -> Vec<&str> {
+        [&self.threads, &self.gpu_id, &self.tile_size, &self.model]
+            .into_iter()
+            .filter_map(|e| e.as_deref())
+            .collect()
+    }
+}
+
+/// Checks a `load:proc:save` thread count. A per-GPU `proc` list must have
+/// one entry for each of the `devices` the batch runs on. Empty means the
+/// engine's default.
+pub fn check_threads(text: &str, devices: usize) -> Result<(), String> {
+    if text.trim().is_empty() {
+        return Ok(());
+    }
+
+    let spec = ThreadSpec::parse(text)?;
+
+    if spec.proc.len() > 1 && spec.proc.len() != devices {
+        return Err(format!(
+            "{} proc thread counts are given for {} device(s)",
+            spec.proc.len(),
+            devices
+        ));
+    }
+
+    Ok(())
+}
+
+/// Checks a tile size: 0 for automatic or at least 32, optionally one per
+/// device (`200,0`). Empty means automatic.
+pub fn check_tile_sizes(text: &str, devices: usize) -> Result<(), String> {
+    let sizes = args::parse_tile_sizes(text)?;
+
+    if sizes.len() > 1 && sizes.len() != devices {
+        return Err(format!(
+            "{} tile sizes are given for {} device(s)",
+            sizes.len(),
+            devices
+        ));
+    }
+
+    Ok(())
+}
+
+/// Finds a models folder the way the engines do: relative paths are looked
+/// up next to the executable first, then in the working directory.
+pub fn find_model_dir(dir: &Path, exe: &Path) -> Option<PathBuf> {
+    if dir.is_absolute() {
+        return Some(dir.to_path_buf()).filter(|d| d.is_dir());
+    }
+
+    exe.parent()
+        .map(|parent| parent.join(dir))
+        .filter(|d| d.is_dir())
+        .or_else(|| Some(dir.to_path_buf()).filter(|d| d.is_dir()))
+}
+
+/// Checks that the model `model` (empty for the default) has the files the
+/// engine at `exe` needs for `scale` and `denoise`.
+///
+/// A default model that cannot be located is not an error, since the engine
+/// may still find it in a place this cannot foresee.
+pub fn check_model(
+    engine: Engine,
+    exe: &Path,
+    model: &str,
+    scale: i32,
+    denoise: i32,
+) -> Result<(), String> {
+    let (dir, name) = engine.model_location(model);
+
+    let Some(found) = find_model_dir(&dir, exe) else {
+        if model.trim().is_empty() {
+            return Ok(());
+        }
+
+        return Err(format!("The model folder {} does not exist", dir.display()));
+    };
+
+    let missing = engine
+        .model_files(&name, scale, denoise)
+        .into_iter()
+        .flat_map(|file| [format!("{}.param", file), format!("{}.bin", file)])
+        .filter(|file| !found.join(file).is_file())
+        .collect::<Vec<_>>();
+
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} lacks {} needed for this scale and denoise level",
+            found.display(),
+            missing.join(", ")
+        ))
+    }
+}
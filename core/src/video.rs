@@ -0,0 +1,460 @@
+//! Upscaling videos: ffmpeg splits a video into frames, the engine upscales
+//! them all in its directory mode (`-i dir -o dir`), and ffmpeg puts them
+//! back together at the original frame rate, with the audio and subtitle
+//! streams copied over.
+//!
+//! The frames are kept in a work folder named after the job, so that a batch
+//! that was interrupted picks up where it stopped: extraction is skipped once
+//! it has completed, and frames that already have an upscaled version are
+//! not upscaled again.
+
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+
+use crate::args::Waifu2xArgs;
+use crate::input::Glob;
+use crate::job::{Format, Job};
+use crate::progress::Capture;
+
+/// The video types that are split into frames.
+pub const VIDEO_INCLUDE: &str = "*.mp4;*.mkv;*.webm;*.avi;*.mov";
+
+/// The name pattern of the extracted frames, as ffmpeg expects it.
+const FRAME_PATTERN: &str = "%08d.png";
+
+/// Marks a work folder whose frames have all been extracted.
+const EXTRACTED_MARKER: &str = "extracted";
+
+/// Whether the file at `path` is a video, judging by its extension.
+pub fn is_video(path: &Path) -> bool {
+    let Some(name) = path.file_name() else {
+        return false;
+    };
+
+    Glob::parse_list(VIDEO_INCLUDE)
+        .iter()
+        .any(|g| g.matches(name))
+}
+
+/// How upscaled videos are encoded.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VideoSettings {
+    /// The ffmpeg encoder of the video stream, such as `libx264`.
+    pub codec: String,
+    /// The constant rate factor, where lower is better quality.
+    pub crf: u32,
+}
+
+impl Default for VideoSettings {
+    fn default() -> Self {
+        Self {
+            codec: String::from("libx264"),
+            crf: 18,
+        }
+    }
+}
+
+/// Looks for ffmpeg next to the running program, then on the `PATH`.
+pub fn discover_ffmpeg() -> Option<PathBuf> {
+    let name = format!("ffmpeg{}", env::consts::EXE_SUFFIX);
+
+    let mut dirs = Vec::new();
+    dirs.extend(
+        env::current_exe()
+            .ok()
+            .as_deref()
+            .and_then(Path::parent)
+            .map(Path::to_path_buf),
+    );
+    dirs.extend(env::var_os("PATH").iter().flat_map(env::split_paths));
+
+    dirs.iter()
+        .map(|dir| dir.join(&name))
+        .find(|path| path.is_file())
+}
+
+/// ffprobe, which comes with ffmpeg.
+fn ffprobe(ffmpeg: &Path) -> PathBuf {
+    ffmpeg.with_file_name(format!("ffprobe{}", env::consts::EXE_SUFFIX))
+}
+
+/// What a video job is doing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    /// ffprobe reads the frame rate and frame count.
+    Probe,
+    /// ffmpeg writes every frame to an image.
+    Extract,
+    /// The engine upscales the frames.
+    Upscale,
+    /// ffmpeg encodes the upscaled frames.
+    Encode,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            Stage::Probe => "Reading video",
+            Stage::Extract => "Extracting frames",
+            Stage::Upscale => "Upscaling frames",
+            Stage::Encode => "Encoding",
+        };
+
+        f.write_str(text)
+    }
+}
+
+/// The progress of one video through the stages.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VideoJob {
+    pub stage: Stage,
+    /// Where the frames are kept until the video is encoded.
+    pub work_dir: PathBuf,
+    /// The frame rate as ffprobe reports it, such as `24000/1001`.
+    pub frame_rate: Option<String>,
+    pub frame_count: Option<usize>,
+    /// How many frames had been upscaled before the engine was started.
+    pub frames_done: usize,
+}
+
+impl VideoJob {
+    /// Plans the video `job`, with the work folder derived from everything
+    /// that affects the upscaled frames. That includes the size and
+    /// modification time of the input, so that the frames of a video that
+    /// was replaced since are not reused.
+    pub fn new(job: &Job, args: &Waifu2xArgs) -> Self {
+        let mut hasher = DefaultHasher::new();
+        job.input.hash(&mut hasher);
+        if let Ok(metadata) = fs::metadata(&job.input) {
+            metadata.len().hash(&mut hasher);
+            metadata.modified().ok().hash(&mut hasher);
+        }
+        job.output.hash(&mut hasher);
+        args.engine.hash(&mut hasher);
+        args.scale.hash(&mut hasher);
+        args.denoise.hash(&mut hasher);
+        args.model.hash(&mut hasher);
+        args.tta.hash(&mut hasher);
+
+        Self::with_work_dir(
+            env::temp_dir()
+                .join("waifu2x-gui-video")
+                .join(format!("{:016x}", hasher.finish())),
+        )
+    }
+
+    pub fn with_work_dir(work_dir: PathBuf) -> Self {
+        Self {
+            stage: Stage::Probe,
+            work_dir,
+            frame_rate: None,
+            frame_count: None,
+            frames_done: 0,
+        }
+    }
+
+    fn frames_dir(&self) -> PathBuf {
+        self.work_dir.join("frames")
+    }
+
+    fn upscaled_dir(&self) -> PathBuf {
+        self.work_dir.join("upscaled")
+    }
+
+    /// The frames the engine is run on: those that are not upscaled yet.
+    fn pending_dir(&self) -> PathBuf {
+        self.work_dir.join("pending")
+    }
+
+    /// Prepares the current stage and returns the command that runs it.
+    /// Stages that are already complete from an earlier run are skipped.
+    pub fn command(
+        &mut self,
+        job: &Job,
+        args: &Waifu2xArgs,
+        exe: &Path,
+        ffmpeg: &Path,
+        settings: &VideoSettings,
+    ) -> io::Result<Command> {
+        if self.stage == Stage::Extract && self.work_dir.join(EXTRACTED_MARKER).is_file() {
+            self.stage = Stage::Upscale;
+        }
+
+        if self.stage == Stage::Upscale && self.stage_pending_frames()? == 0 {
+            self.stage = Stage::Encode;
+        }
+
+        match self.stage {
+            Stage::Extract => {
+                // Leftovers of an interrupted extraction are redone
+                let frames = self.frames_dir();
+                if frames.exists() {
+                    fs::remove_dir_all(&frames)?;
+                }
+                fs::create_dir_all(&frames)?;
+            }
+            Stage::Upscale => fs::create_dir_all(self.upscaled_dir())?,
+            Stage::Probe | Stage::Encode => {}
+        }
+
+        Ok(self.stage_command(job, args, exe, ffmpeg, settings))
+    }
+
+    /// The commands of every stage, for a preview. The frame rate is only
+    /// known once the video has been probed, so it shows as `{frame rate}`.
+    pub fn planned_commands(
+        &self,
+        job: &Job,
+        args: &Waifu2xArgs,
+        exe: &Path,
+        ffmpeg: &Path,
+        settings: &VideoSettings,
+    ) -> Vec<Command> {
+        [Stage::Probe, Stage::Extract, Stage::Upscale, Stage::Encode]
+            .into_iter()
+            .map(|stage| {
+                let video = VideoJob {
+                    stage,
+                    frame_rate: Some(String::from("{frame rate}")),
+                    ..self.clone()
+                };
+                video.stage_command(job, args, exe, ffmpeg, settings)
+            })
+            .collect()
+    }
+
+    /// The command of the current stage, once its folders are prepared.
+    fn stage_command(
+        &self,
+        job: &Job,
+        args: &Waifu2xArgs,
+        exe: &Path,
+        ffmpeg: &Path,
+        settings: &VideoSettings,
+    ) -> Command {
+        let rate = self
+            .frame_rate
+            .clone()
+            .unwrap_or_else(|| String::from("25"));
+
+        match self.stage {
+            Stage::Probe => {
+                let mut command = Command::new(ffprobe(ffmpeg));
+                command
+                    .args(["-v", "error", "-select_streams", "v:0", "-count_packets"])
+                    .args(["-show_entries", "stream=r_frame_rate,nb_read_packets"])
+                    .args(["-of", "default=noprint_wrappers=1"])
+                    .arg(&job.input);
+                command
+            }
+            Stage::Extract => {
+                let mut command = ffmpeg_command(ffmpeg);
+                command
+                    .arg("-i")
+                    .arg(&job.input)
+                    .args(["-map", "0:v:0", "-r", &rate])
+                    .arg(self.frames_dir().join(FRAME_PATTERN));
+                command
+            }
+            Stage::Upscale => Waifu2xArgs {
+                input: self.pending_dir(),
+                output: self.upscaled_dir(),
+                format: Some(Format::Png),
+                ..args.clone()
+            }
+            .to_command(exe),
+            Stage::Encode => {
+                let mut command = ffmpeg_command(ffmpeg);
+                command
+                    .args(["-framerate", &rate, "-i"])
+                    .arg(self.upscaled_dir().join(FRAME_PATTERN))
+                    .arg("-i")
+                    .arg(&job.input)
+                    .args(["-map", "0:v:0", "-map", "1:a?", "-map", "1:s?"])
+                    .args(["-c:v", &settings.codec])
+                    .args(["-crf", &settings.crf.to_string()])
+                    .args(["-pix_fmt", "yuv420p", "-c:a", "copy", "-c:s", "copy"])
+                    .arg(&job.output);
+                command
+            }
+        }
+    }
+
+    /// Fills the pending folder with the frames that have no upscaled
+    /// version yet, and returns how many there are.
+    fn stage_pending_frames(&mut self) -> io::Result<usize> {
+        let pending = self.pending_dir();
+        if pending.exists() {
+            fs::remove_dir_all(&pending)?;
+        }
+        fs::create_dir_all(&pending)?;
+
+        let frames = list_frames(&self.frames_dir())?;
+        let upscaled = self.upscaled_dir();
+        let mut staged = 0;
+
+        for frame in &frames {
+            let name = frame.file_name().unwrap_or_default();
+
+            if !is_complete_png(&upscaled.join(name)) {
+                stage_file(frame, &pending.join(name))?;
+                staged += 1;
+            }
+        }
+
+        self.frames_done = frames.len() - staged;
+        Ok(staged)
+    }
+
+    /// Moves on to the next stage after the current one succeeded, given
+    /// what its process printed. Returns `false` once the video is done.
+    pub fn advance(&mut self, log: &str) -> Result<bool, String> {
+        match self.stage {
+            Stage::Probe => {
+                let (rate, count) = parse_probe(log);
+                self.frame_rate = Some(rate.ok_or("ffprobe found no video stream")?);
+                self.frame_count = count;
+                self.stage = Stage::Extract;
+            }
+            Stage::Extract => {
+                fs::write(self.work_dir.join(EXTRACTED_MARKER), "")
+                    .map_err(|e| format!("Unable to write to the work folder: {}", e))?;
+                self.stage = Stage::Upscale;
+            }
+            Stage::Upscale => {
+                let frames = list_frames(&self.frames_dir()).unwrap_or_default();
+                let upscaled = self.upscaled_dir();
+                let missing = frames
+                    .iter()
+                    .filter(|f| !is_complete_png(&upscaled.join(f.file_name().unwrap_or_default())))
+                    .count();
+
+                if missing > 0 {
+                    return Err(format!("{} frames were not upscaled", missing));
+                }
+
+                self.stage = Stage::Encode;
+            }
+            Stage::Encode => {
+                // The frames are worthless once the video is encoded
+                let _ = fs::remove_dir_all(&self.work_dir);
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Starts over from the first stage, keeping whatever the work folder
+    /// already holds.
+    pub fn restart(&mut self) {
+        self.stage = Stage::Probe;
+    }
+
+    /// The frames processed by the current stage so far, and how many there
+    /// are in total, if known.
+    pub fn frames(&self, capture: &Capture) -> Option<(usize, usize)> {
+        let total = self.frame_count?;
+
+        let done = match self.stage {
+            Stage::Probe => 0,
+            Stage::Extract | Stage::Encode => capture.frames,
+            Stage::Upscale => self.frames_done + capture.frames,
+        };
+
+        Some((done.min(total), total))
+    }
+
+    /// The progress of the whole video in percent, from 0 to 100. Upscaling
+    /// takes the bulk of the time, so it accounts for most of the range.
+    pub fn progress(&self, capture: &Capture) -> f32 {
+        let (start, end) = match self.stage {
+            Stage::Probe => (0.0, 0.0),
+            Stage::Extract => (0.0, 10.0),
+            Stage::Upscale => (10.0, 90.0),
+            Stage::Encode => (90.0, 100.0),
+        };
+
+        let fraction = match self.frames(capture) {
+            Some((done, total)) if total > 0 => done as f32 / total as f32,
+            _ => 0.0,
+        };
+
+        start + (end - start) * fraction
+    }
+}
+
+fn ffmpeg_command(ffmpeg: &Path) -> Command {
+    let mut command = Command::new(ffmpeg);
+    // Only errors and the `frame=` statistics, which report progress
+    command.args(["-y", "-hide_banner", "-v", "error", "-stats"]);
+    command
+}
+
+/// Reads the frame rate and frame count from ffprobe's output.
+pub fn parse_probe(output: &str) -> (Option<String>, Option<usize>) {
+    let mut rate = None;
+    let mut count = None;
+
+    for line in output.lines() {
+        match line.trim().split_once('=') {
+            Some(("r_frame_rate", value)) if value != "0/0" => rate = Some(value.to_owned()),
+            Some(("nb_read_packets", value)) => count = value.parse().ok(),
+            _ => {}
+        }
+    }
+
+    (rate, count)
+}
+
+/// The extracted frames in `dir`, in order.
+fn list_frames(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut frames = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.extension().is_some_and(|e| e == "png") {
+            frames.push(path);
+        }
+    }
+
+    frames.sort();
+    Ok(frames)
+}
+
+/// Puts `from` at `to` as a hard link, or a copy where links are not
+/// possible.
+pub fn stage_file(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::hard_link(from, to).is_err() {
+        fs::copy(from, to)?;
+    }
+
+    Ok(())
+}
+
+/// Whether the PNG at `path` was written completely, which is told by the
+/// `IEND` chunk at its very end. An engine that crashed mid-write leaves a
+/// truncated file behind.
+pub fn is_complete_png(path: &Path) -> bool {
+    const IEND: [u8; 12] = [0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82];
+
+    let read_end = || -> io::Result<[u8; 12]> {
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::End(-12))?;
+        let mut end = [0; 12];
+        file.read_exact(&mut end)?;
+        Ok(end)
+    };
+
+    read_end().is_ok_and(|end| end == IEND)
+}
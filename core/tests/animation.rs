@@ -0,0 +1,240 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+
+use waifu2x_gui_core::animation::{
+    self, Animation, AnimationError, AnimationFormat, Delay, Frame, Loops,
+};
+
+use common::test_dir;
+
+/// Three 4x2 frames, one opaque color each, with a transparent corner.
+fn sample(loops: Loops) -> Animation {
+    let colors = [[255, 0, 0], [0, 255, 0], [0, 0, 255]];
+    let delays = [
+        Delay::new(10, 100),
+        Delay::new(25, 100),
+        Delay::new(100, 100),
+    ];
+
+    let frames = colors
+        .iter()
+        .zip(delays)
+        .map(|(color, delay)| {
+            let mut rgba = color
+                .iter()
+                .copied()
+                .chain([255])
+                .cycle()
+                .take(4 * 2 * 4)
+                .collect::<Vec<_>>();
+            rgba[..4].copy_from_slice(&[0, 0, 0, 0]);
+            Frame { delay, rgba }
+        })
+        .collect();
+
+    Animation {
+        width: 4,
+        height: 2,
+        loops,
+        frames,
+    }
+}
+
+fn write_sample(path: &Path, format: AnimationFormat, loops: Loops) {
+    sample(loops).write(path, format).unwrap();
+}
+
+#[test]
+fn round_trips_every_format() {
+    let dir = test_dir("waifu2x-gui-core-animation-test");
+
+    for format in AnimationFormat::ALL {
+        for loops in [Loops::Forever, Loops::Times(1), Loops::Times(3)] {
+            let path = dir.join(format!("sample.{}", format.extension()));
+            write_sample(&path, format, loops);
+
+            assert_eq!(AnimationFormat::detect(&path), Some(format));
+
+            let read = Animation::read(&path, format).unwrap();
+            let expected = sample(loops);
+            assert_eq!((read.width, read.height), (4, 2));
+            assert_eq!(read.loops, loops, "{}", format);
+            assert_eq!(read.frames.len(), 3);
+
+            for (read, expected) in read.frames.iter().zip(&expected.frames) {
+                assert_eq!(read.delay.millis(), expected.delay.millis(), "{}", format);
+                // GIF quantizes colors, so only compare what it keeps exactly
+                assert_eq!(read.rgba[3], 0, "{}", format);
+                assert_eq!(read.rgba[7], 255, "{}", format);
+                if format != AnimationFormat::Gif {
+                    assert_eq!(read.rgba, expected.rgba, "{}", format);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn leaves_still_images_to_the_engine() {
+    let dir = test_dir("waifu2x-gui-core-animation-still-test");
+
+    let still = sample(Loops::Forever);
+    let path = dir.join("still.png");
+    // A plain PNG has no animation control chunk
+    let mut encoder = png::Encoder::new(fs::File::create(&path).unwrap(), 4, 2);
+    encoder.set_color(png::ColorType::Rgba);
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(&still.frames[0].rgba).unwrap();
+    writer.finish().unwrap();
+
+    assert_eq!(AnimationFormat::detect(&path), None);
+    assert_eq!(AnimationFormat::detect(Path::new("missing.webp")), None);
+}
+
+#[test]
+fn finds_the_animation_behind_large_metadata() {
+    let dir = test_dir("waifu2x-gui-core-animation-metadata-test");
+
+    let path = dir.join("sample.png");
+    write_sample(&path, AnimationFormat::Apng, Loops::Forever);
+
+    // A comment larger than a few kilobytes between the header and acTL
+    let mut text = b"Comment\0".to_vec();
+    text.resize(8 * 1024, b'x');
+    let mut chunk = (text.len() as u32).to_be_bytes().to_vec();
+    chunk.extend(b"tEXt");
+    chunk.extend(&text);
+    chunk.extend([0; 4]);
+
+    let mut png = fs::read(&path).unwrap();
+    // The signature and IHDR come first
+    png.splice(33..33, chunk);
+    fs::write(&path, png).unwrap();
+
+    assert_eq!(AnimationFormat::detect(&path), Some(AnimationFormat::Apng));
+}
+
+#[test]
+fn split_and_assemble_stop_when_cancelled() {
+    let dir = test_dir("waifu2x-gui-core-animation-cancel-test");
+    let input = dir.join("cat.png");
+    write_sample(&input, AnimationFormat::Apng, Loops::Forever);
+
+    let (running, cancelled) = (AtomicBool::new(false), AtomicBool::new(true));
+    let frames = dir.join("frames");
+    assert!(matches!(
+        animation::split(&input, &frames, &cancelled),
+        Err(AnimationError::Cancelled)
+    ));
+    let timing = animation::split(&input, &frames, &running).unwrap();
+    assert_eq!(timing.delays.len(), 3);
+
+    for format in AnimationFormat::ALL {
+        let output = dir.join(format!("cat_2x.{}", format.extension()));
+
+        assert!(matches!(
+            animation::assemble(&frames, &timing, &output, format, &cancelled),
+            Err(AnimationError::Cancelled)
+        ));
+        assert!(!output.exists(), "{}", format);
+
+        animation::assemble(&frames, &timing, &output, format, &running).unwrap();
+        let read = Animation::read(&output, format).unwrap();
+        assert_eq!(read.frames.len(), 3, "{}", format);
+        assert_eq!(read.loops, Loops::Forever, "{}", format);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn upscales_every_frame() {
+    use std::thread;
+    use std::time::Duration;
+
+    use waifu2x_gui_core::{Job, JobQueue, JobState, Waifu2xArgs};
+
+    // Copies every frame of `-i dir` to `-o dir` unchanged
+    let (dir, engine) = common::engine_with(
+        "waifu2x-gui-core-animation-queue-test",
+        "cp \"$2\"/*.png \"$4\"/\n\
+         for f in \"$2\"/*.png; do echo \"$f -> $f done\" >&2; done\n",
+    );
+
+    let input = dir.join("cat.gif");
+    write_sample(&input, AnimationFormat::Gif, Loops::Times(3));
+
+    let mut queue = JobQueue::new(engine, 1);
+    // Written as the GIF came, and converted to WebP
+    for output in ["out/cat_2x.gif", "out/cat_2x.webp"] {
+        let job = Job {
+            input: input.clone(),
+            output: dir.join(output),
+        };
+        queue.push(job, Waifu2xArgs::default());
+    }
+
+    while !queue.is_finished() {
+        queue.tick();
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    for job in queue.jobs() {
+        assert_eq!(
+            job.state,
+            JobState::Succeeded,
+            "{:?}",
+            job.failure_message()
+        );
+        assert!(!job.animation.as_ref().unwrap().work_dir.exists());
+
+        let format = AnimationFormat::from_path(&job.job.output).unwrap();
+        let output = Animation::read(&job.job.output, format).unwrap();
+        let delays = output
+            .frames
+            .iter()
+            .map(|f| f.delay.millis())
+            .collect::<Vec<_>>();
+
+        assert_eq!(delays, [100, 250, 1000]);
+        assert_eq!(output.loops, Loops::Times(3));
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn reports_a_failed_assembly() {
+    use std::thread;
+    use std::time::Duration;
+
+    use waifu2x_gui_core::{Exit, Job, JobQueue, JobState, QueueEvent, Waifu2xArgs};
+
+    let (dir, engine) = common::engine_with(
+        "waifu2x-gui-core-animation-failure-test",
+        "cp \"$2\"/*.png \"$4\"/\n",
+    );
+
+    let input = dir.join("cat.gif");
+    write_sample(&input, AnimationFormat::Gif, Loops::Forever);
+
+    // A folder is in the way of the output
+    let output = dir.join("out/cat_2x.gif");
+    fs::create_dir_all(&output).unwrap();
+
+    let mut queue = JobQueue::new(engine, 1);
+    queue.push(Job { input, output }, Waifu2xArgs::default());
+
+    let mut events = Vec::new();
+    while !queue.is_finished() {
+        events.extend(queue.tick());
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    assert_eq!(queue.jobs()[0].state, JobState::Failed);
+    assert!(matches!(events.last(), Some(QueueEvent::Failed(0, _))));
+    assert!(!events
+        .iter()
+        .any(|e| matches!(e, QueueEvent::Finished(_, Exit::Success))));
+}
@@ -0,0 +1,122 @@
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+use waifu2x_gui_core::{Engine, Format, Waifu2xArgs};
+
+fn argv(args: &Waifu2xArgs) -> Vec<String> {
+    args.to_argv()
+        .into_iter()
+        .map(|a: OsString| a.into_string().unwrap())
+        .collect()
+}
+
+#[test]
+fn minimal_args() {
+    let args = Waifu2xArgs {
+        input: PathBuf::from("in.png"),
+        output: PathBuf::from("out.png"),
+        ..Default::default()
+    };
+
+    assert_eq!(
+        argv(&args),
+        ["-i", "in.png", "-o", "out.png", "-s", "2", "-n", "0"]
+    );
+}
+
+#[test]
+fn every_option() {
+    let args = Waifu2xArgs {
+        engine: Engine::Waifu2x,
+        input: PathBuf::from("in.png"),
+        output: PathBuf::from("out.webp"),
+        scale: 4,
+        denoise: -1,
+        tile_sizes: vec![200],
+        gpus: vec![0, 1, -1],
+        threads: Some(String::from("1:2,2:2")),
+        model: Some(PathBuf::from("models-cunet")),
+        tta: true,
+        format: Some(Format::Webp),
+        verbose: true,
+    };
+
+    assert_eq!(
+        argv(&args),
+        [
+            "-i",
+            "in.png",
+            "-o",
+            "out.webp",
+            "-s",
+            "4",
+            "-n",
+            "-1",
+            "-t",
+            "200",
+            "-g",
+            "0,1,-1",
+            "-j",
+            "1:2,2:2",
+            "-m",
+            "models-cunet",
+            "-x",
+            "-f",
+            "webp",
+            "-v",
+        ]
+    );
+}
+
+#[test]
+fn command_uses_argv() {
+    let args = Waifu2xArgs {
+        input: PathBuf::from("a.jpg"),
+        output: PathBuf::from("b.png"),
+        tta: true,
+        ..Default::default()
+    };
+
+    let command = args.to_command("waifu2x".as_ref());
+    assert_eq!(command.get_program(), "waifu2x");
+    assert_eq!(command.get_args().collect::<Vec<_>>(), args.to_argv());
+}
+
+#[test]
+fn realesrgan_takes_a_model_name() {
+    let args = Waifu2xArgs {
+        engine: Engine::RealEsrgan,
+        input: PathBuf::from("in.png"),
+        output: PathBuf::from("out.png"),
+        scale: 4,
+        denoise: -1,
+        model: Some(PathBuf::from("realesrgan-x4plus-anime")),
+        ..Default::default()
+    };
+
+    assert_eq!(
+        argv(&args),
+        [
+            "-i",
+            "in.png",
+            "-o",
+            "out.png",
+            "-s",
+            "4",
+            "-n",
+            "realesrgan-x4plus-anime"
+        ]
+    );
+}
+
+#[test]
+fn realesrgan_scales_depend_on_the_model() {
+    let engine = Engine::RealEsrgan;
+
+    // The default is an animevideov3 model
+    assert!(engine.supports_scale("", 2));
+    assert!(engine.supports_scale("realesr-animevideov3", 3));
+    assert!(!engine.supports_scale("realesrgan-x4plus", 2));
+    assert!(!engine.supports_scale("realesrgan-x4plus-anime", 3));
+    assert!(engine.supports_scale("realesrgan-x4plus", 4));
+}
@@ -0,0 +1,213 @@
+mod common;
+
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use waifu2x_gui_core::batch::{self, BatchError, BatchOptions};
+use waifu2x_gui_core::config::Settings;
+use waifu2x_gui_core::engine::Engine;
+use waifu2x_gui_core::job::Format;
+use waifu2x_gui_core::preset::{PresetError, PresetStore};
+
+use common::test_dir;
+
+fn parse(args: &[&str]) -> Result<BatchOptions, BatchError> {
+    BatchOptions::parse(args.iter().map(OsString::from))
+}
+
+fn usage_error(args: &[&str]) -> String {
+    match parse(args) {
+        Err(BatchError::Usage(message)) => message,
+        other => panic!("expected a usage error, got {:?}", other),
+    }
+}
+
+#[test]
+fn parses_flags_and_switches() {
+    let options = parse(&[
+        "-o",
+        "out",
+        "-s",
+        "4",
+        "-n",
+        "-1",
+        "-f",
+        "jpeg",
+        "-e",
+        "realesrgan",
+        "-j",
+        "3",
+        "--name",
+        "{stem}_x{scale}",
+        "-m",
+        "models-cunet",
+        "--threads",
+        "1:2:2",
+        "-x",
+        "-r",
+        "--mirror",
+        "a.png",
+        "scans",
+    ])
+    .unwrap();
+
+    assert_eq!(options.output_dir, PathBuf::from("out"));
+    assert_eq!(
+        options.inputs,
+        [PathBuf::from("a.png"), PathBuf::from("scans")]
+    );
+    assert_eq!(options.scale, Some(4));
+    assert_eq!(options.denoise, Some(-1));
+    assert_eq!(options.format, Some(Format::Jpg));
+    assert_eq!(options.engine, Some(Engine::RealEsrgan));
+    assert_eq!(options.jobs, Some(3));
+    assert_eq!(options.filename_format.as_deref(), Some("{stem}_x{scale}"));
+    assert_eq!(options.model.as_deref(), Some("models-cunet"));
+    assert_eq!(options.threads.as_deref(), Some("1:2:2"));
+    assert!(options.tta && options.recursive && options.mirror && !options.help);
+
+    // Help needs nothing else
+    assert!(parse(&["--help"]).unwrap().help);
+}
+
+#[test]
+fn rejects_bad_command_lines() {
+    assert!(usage_error(&["a.png"]).contains("--out is required"));
+    assert!(usage_error(&["-o", "out"]).contains("no input files"));
+    assert!(usage_error(&["a.png", "-o"]).contains("missing value for -o"));
+    assert!(
+        usage_error(&["-o", "out", "--bogus", "x", "a.png"]).contains("unknown option: --bogus")
+    );
+    assert!(usage_error(&["-o", "out", "-s", "two", "a.png"]).contains("invalid value for -s"));
+    assert!(usage_error(&["-o", "out", "-f", "bmp", "a.png"]).contains("unknown format"));
+}
+
+#[test]
+fn applies_a_preset_under_the_options() {
+    let dir = test_dir("waifu2x-gui-core-batch-test");
+
+    let mut presets = PresetStore::default();
+    let settings = Settings {
+        scale_level: 4,
+        denoise_level: 3,
+        filename_format: String::from("{stem}_preset"),
+        ..Default::default()
+    };
+    presets.insert("scan cleanup", settings).unwrap();
+    let pack = dir.join("presets.toml");
+    presets.export(&pack).unwrap();
+
+    let input = dir.join("a.png");
+    fs::write(&input, "").unwrap();
+
+    let pack = pack.to_str().unwrap();
+    let input = input.to_str().unwrap();
+    let options = parse(&[
+        "--presets",
+        pack,
+        "-p",
+        "scan cleanup",
+        "-n",
+        "1",
+        "-x",
+        "--exe",
+        "/opt/w2x",
+        "-o",
+        "out",
+        input,
+    ])
+    .unwrap();
+
+    let state = options.to_state(|_| {}).unwrap();
+    assert_eq!(state.scale_level, 4);
+    // The option wins over the preset
+    assert_eq!(state.denoise_level, 1);
+    assert!(state.tta_mode);
+    assert_eq!(state.filename_format, "{stem}_preset");
+    assert_eq!(state.engine_exe(), Path::new("/opt/w2x"));
+    assert_eq!(state.inputs.paths().collect::<Vec<_>>(), [Path::new(input)]);
+    assert_eq!(state.output_dir, "out");
+
+    let options = parse(&["--presets", pack, "-p", "missing", "-o", "out", input]).unwrap();
+    assert!(matches!(
+        options.to_state(|_| {}),
+        Err(BatchError::Preset(PresetError::NotFound(name))) if name == "missing"
+    ));
+}
+
+#[test]
+fn skips_files_the_engine_cannot_read() {
+    let dir = test_dir("waifu2x-gui-core-batch-filter-test");
+
+    let inputs = ["a.png", "notes.txt"].map(|name| dir.join(name));
+    for input in &inputs {
+        fs::write(input, "").unwrap();
+    }
+
+    let mut args = vec!["-o", "out"];
+    args.extend(inputs.iter().map(|i| i.to_str().unwrap()));
+    let options = parse(&args).unwrap();
+
+    let mut reported = Vec::new();
+    let state = options
+        .to_state(|line| reported.push(line.to_owned()))
+        .unwrap();
+
+    assert_eq!(
+        state.inputs.paths().collect::<Vec<_>>(),
+        [inputs[0].as_path()]
+    );
+    assert_eq!(reported.len(), 1);
+    assert!(reported[0].contains("notes.txt"));
+}
+
+#[cfg(unix)]
+#[test]
+fn asks_the_caller_about_existing_outputs() {
+    let (dir, exe) = common::engine_with("waifu2x-gui-core-batch-ask-test", "echo new > \"$4\"\n");
+    fs::create_dir_all(dir.join("out")).unwrap();
+
+    let inputs = ["a.png", "b.png"].map(|name| dir.join(name));
+    for input in &inputs {
+        fs::write(input, "").unwrap();
+    }
+
+    let (exe, out) = (exe.to_str().unwrap(), dir.join("out"));
+    let mut args = vec![
+        "--exe",
+        exe,
+        "-s",
+        "2",
+        "--if-exists",
+        "ask",
+        "-o",
+        out.to_str().unwrap(),
+    ];
+    args.extend(inputs.iter().map(|i| i.to_str().unwrap()));
+    let options = parse(&args).unwrap();
+
+    let plan = batch::preview(&options, |_| {}).unwrap();
+    let outputs = plan
+        .jobs
+        .iter()
+        .map(|j| j.job.output.clone())
+        .collect::<Vec<_>>();
+    fs::write(&outputs[0], "old").unwrap();
+
+    let mut asked = None;
+    let summary = batch::run(
+        &options,
+        |count| {
+            asked = Some(count);
+            false
+        },
+        |_| {},
+    )
+    .unwrap();
+
+    assert_eq!(asked, Some(1));
+    assert_eq!((summary.succeeded, summary.skipped), (1, 1));
+    assert_eq!(fs::read_to_string(&outputs[0]).unwrap(), "old");
+    assert_eq!(fs::read_to_string(&outputs[1]).unwrap(), "new\n");
+}
@@ -0,0 +1,37 @@
+//! Helpers shared by the integration tests.
+
+// Every test file uses only some of them
+#![allow(dead_code)]
+
+use std::fs;
+#[cfg(unix)]
+use std::path::Path;
+use std::path::PathBuf;
+
+/// A fresh, empty folder named `name` in the temporary directory.
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Writes a shell script running `body` to `path`, and makes it executable.
+#[cfg(unix)]
+pub fn script(path: &Path, body: &str) {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::write(path, format!("#!/bin/sh\n{}", body)).unwrap();
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+/// A fresh folder named `name` with a stand-in engine, `engine.sh`, that
+/// runs `body`. Returns the folder and the engine.
+#[cfg(unix)]
+pub fn engine_with(name: &str, body: &str) -> (PathBuf, PathBuf) {
+    let dir = test_dir(name);
+    let exe = dir.join("engine.sh");
+    script(&exe, body);
+
+    (dir, exe)
+}
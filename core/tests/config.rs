@@ -0,0 +1,44 @@
+use waifu2x_gui_core::config::{ConfigError, CONFIG_VERSION};
+use waifu2x_gui_core::{Config, Engine, Format, Settings};
+
+#[test]
+fn round_trip() {
+    let config = Config {
+        output_dir: "out".into(),
+        engine_paths: [(Engine::RealCugan, "tools/realcugan-ncnn-vulkan".into())].into(),
+        settings: Settings {
+            engine: Engine::RealCugan,
+            scale_level: 4,
+            denoise_level: 2,
+            format: Format::Webp,
+            filename_format: String::from("{name}_{index:04}"),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let text = config.to_toml().unwrap();
+    assert_eq!(Config::from_toml(&text).unwrap(), config);
+}
+
+#[test]
+fn missing_fields_take_defaults() {
+    let config = Config::from_toml("[settings]\nscale_level = 2\n").unwrap();
+
+    assert_eq!(config.version, CONFIG_VERSION);
+    assert_eq!(config.settings.scale_level, 2);
+    assert_eq!(config.settings.denoise_level, -1);
+    assert_eq!(
+        config.settings.filename_format,
+        "{name}_{scale}x_{denoise}n"
+    );
+}
+
+#[test]
+fn rejects_newer_versions() {
+    let text = format!("version = {}\n", CONFIG_VERSION + 1);
+    assert!(matches!(
+        Config::from_toml(&text),
+        Err(ConfigError::TooNew(_))
+    ));
+}
@@ -0,0 +1,76 @@
+use std::path::PathBuf;
+
+use waifu2x_gui_core::{Device, Job, JobQueue, Waifu2xArgs};
+
+#[test]
+fn parses_slots_and_weights() {
+    let devices = Device::parse_list("0:2, 1, -1:1:3").unwrap();
+
+    assert_eq!(
+        devices,
+        [
+            Device {
+                id: 0,
+                slots: 2,
+                weight: 1
+            },
+            Device::new(1),
+            Device {
+                id: -1,
+                slots: 1,
+                weight: 3
+            },
+        ]
+    );
+    assert_eq!(Device::parse_list(" auto "), Ok(Vec::new()));
+}
+
+#[test]
+fn rejects_bad_entries() {
+    assert_eq!(Device::parse_list("0,x"), Err(String::from("x")));
+    assert_eq!(Device::parse_list("0:0"), Err(String::from("0:0")));
+    assert_eq!(Device::parse_list("1,1"), Err(String::from("1")));
+    assert_eq!(Device::parse_list("-2"), Err(String::from("-2")));
+}
+
+#[test]
+fn narrows_args_to_one_device() {
+    let args = Waifu2xArgs {
+        gpus: vec![0, 1],
+        threads: Some(String::from("1:2,4:2")),
+        tile_sizes: vec![400, 100],
+        ..Default::default()
+    };
+
+    let on_second = args.on_device(1, 1);
+    assert_eq!(on_second.gpus, [1]);
+    assert_eq!(on_second.threads.as_deref(), Some("1:4:2"));
+    assert_eq!(on_second.tile_sizes, [100]);
+}
+
+#[cfg(unix)]
+#[test]
+fn spreads_jobs_across_free_slots() {
+    let dir = std::env::temp_dir().join("waifu2x-gui-core-device-test");
+    let mut queue = JobQueue::new(PathBuf::from("true"), 10);
+    queue.set_devices(Device::parse_list("0:2,1").unwrap());
+
+    for i in 0..5 {
+        let job = Job {
+            input: dir.join(format!("{}.png", i)),
+            output: dir.join(format!("{}_2x.png", i)),
+        };
+        queue.push(job, Waifu2xArgs::default());
+    }
+
+    queue.tick();
+
+    let devices = queue
+        .jobs()
+        .iter()
+        .map(|j| j.device.map(|d| d.id))
+        .collect::<Vec<_>>();
+    assert_eq!(devices, [Some(0), Some(1), Some(0), None, None]);
+
+    queue.cancel();
+}
@@ -0,0 +1,60 @@
+mod common;
+
+use waifu2x_gui_core::Engine;
+
+const WAIFU2X_USAGE: &str = "\
+Usage: waifu2x-ncnn-vulkan -i infile -o outfile [options]...
+
+  -h                   show this help
+  -v                   verbose output
+  -i input-path        input image path (jpg/png/webp) or directory
+";
+
+#[test]
+fn usage_identifies_the_engine() {
+    assert!(Engine::Waifu2x.is_usage(WAIFU2X_USAGE));
+    assert!(!Engine::RealEsrgan.is_usage(WAIFU2X_USAGE));
+    assert!(!Engine::Waifu2x.is_usage("waifu2x-ncnn-vulkan-gui 0.1.0"));
+}
+
+#[test]
+fn engines_parse_by_id_or_name() {
+    assert_eq!("realesrgan".parse(), Ok(Engine::RealEsrgan));
+    assert_eq!("Real-CUGAN".parse(), Ok(Engine::RealCugan));
+    assert!("esrgan".parse::<Engine>().is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn probes_and_discovers_engines() {
+    use std::fs;
+
+    use waifu2x_gui_core::ProbeError;
+
+    let dir = common::test_dir("waifu2x-gui-core-engine-test");
+
+    // A stale file under the engine's name, then the real one
+    let name = &Engine::Waifu2x.file_names()[0];
+    let stale = dir.join("stale");
+    let valid = dir.join("valid");
+    for (folder, output) in [(&stale, "not an engine"), (&valid, WAIFU2X_USAGE)] {
+        fs::create_dir_all(folder).unwrap();
+        common::script(&folder.join(name), &format!("cat <<'EOF'\n{}EOF\n", output));
+    }
+
+    assert!(Engine::Waifu2x.probe(&valid.join(name)).is_ok());
+    assert!(matches!(
+        Engine::Waifu2x.probe(&stale.join(name)),
+        Err(ProbeError::NotEngine(Engine::Waifu2x))
+    ));
+    assert!(matches!(
+        Engine::Waifu2x.probe(&dir.join("missing")),
+        Err(ProbeError::Spawn(_))
+    ));
+
+    assert_eq!(
+        Engine::Waifu2x.discover_in(&[dir.join("missing"), stale.clone(), valid.clone()]),
+        Some(valid.join(name))
+    );
+    assert_eq!(Engine::Waifu2x.discover_in(&[stale]), None);
+}
@@ -0,0 +1,96 @@
+mod common;
+
+use waifu2x_gui_core::device;
+use waifu2x_gui_core::gpu::{parse_gpu_list, GpuInfo};
+
+// What waifu2x-ncnn-vulkan 20220728 prints with -v on a laptop with two GPUs
+const CURRENT_OUTPUT: &str = "\
+[0 NVIDIA GeForce RTX 3060 Laptop GPU]  queueC=2[8]  queueG=0[16]  queueT=1[2]
+[0 NVIDIA GeForce RTX 3060 Laptop GPU]  bugsbn1=0  bugbilz=0  bugcopc=0  bugihfa=0
+[0 NVIDIA GeForce RTX 3060 Laptop GPU]  fp16-p/s/a=1/1/1  int8-p/s/a=1/1/1
+[0 NVIDIA GeForce RTX 3060 Laptop GPU]  subgroup=32  basic=1  vote=1  ballot=1  shuffle=1
+[1 Intel(R) UHD Graphics [0x9bc4]]  queueC=0[1]  queueG=0[1]  queueT=0[1]
+[1 Intel(R) UHD Graphics [0x9bc4]]  bugsbn1=0  bugbilz=0  bugcopc=0  bugihfa=0
+[1 Intel(R) UHD Graphics [0x9bc4]]  fp16-p/s/a=1/1/0  int8-p/s/a=1/1/1
+probe.png -> probe_out.png done
+";
+
+// Builds from 2020 print the fp16 flags one by one
+const OLD_OUTPUT: &str = "\
+[0 GeForce GTX 1060 6GB]  queueC=2[8]  queueG=0[16]  queueT=1[2]
+[0 GeForce GTX 1060 6GB]  buglssc=0  bugsbn1=0  buglbia=0  bugihfa=0
+[0 GeForce GTX 1060 6GB]  fp16p=1  fp16s=1  fp16a=0  int8s=1  int8a=1
+";
+
+#[test]
+fn parses_current_output() {
+    let gpus = parse_gpu_list(CURRENT_OUTPUT);
+
+    assert_eq!(
+        gpus,
+        [
+            GpuInfo {
+                index: 0,
+                name: String::from("NVIDIA GeForce RTX 3060 Laptop GPU"),
+                compute_queues: 8,
+                graphics_queues: 16,
+                transfer_queues: 2,
+                fp16_storage: true,
+                fp16_arithmetic: true,
+            },
+            GpuInfo {
+                index: 1,
+                name: String::from("Intel(R) UHD Graphics [0x9bc4]"),
+                compute_queues: 1,
+                graphics_queues: 1,
+                transfer_queues: 1,
+                fp16_storage: true,
+                fp16_arithmetic: false,
+            },
+        ]
+    );
+}
+
+#[test]
+fn parses_old_output() {
+    let gpus = parse_gpu_list(OLD_OUTPUT);
+
+    assert_eq!(gpus.len(), 1);
+    assert_eq!(gpus[0].name, "GeForce GTX 1060 6GB");
+    assert!(gpus[0].fp16_storage);
+    assert!(!gpus[0].fp16_arithmetic);
+}
+
+#[test]
+fn no_devices() {
+    assert!(parse_gpu_list("vkCreateInstance failed -9\ninvalid gpu device\n").is_empty());
+}
+
+#[test]
+fn selection_keeps_slots() {
+    assert_eq!(device::select_ids("0:2,1", &[0, -1]), "0:2,-1");
+    assert_eq!(device::select_ids("auto", &[1]), "1");
+    assert_eq!(device::select_ids("0", &[]), "auto");
+}
+
+#[cfg(unix)]
+#[test]
+fn lists_gpus_of_a_chatty_engine() {
+    use waifu2x_gui_core::gpu::list_gpus;
+
+    // More output than a pipe holds, on both streams, before the devices
+    let (_, exe) = common::engine_with(
+        "waifu2x-gui-core-gpu-test",
+        &format!(
+            "head -c 200000 /dev/zero | tr '\\0' x\n\
+             head -c 200000 /dev/zero | tr '\\0' x >&2\n\
+             echo >&2\n\
+             cat >&2 <<'EOF'\n{}EOF\n",
+            OLD_OUTPUT
+        ),
+    );
+
+    let gpus = list_gpus(&exe).unwrap();
+    assert_eq!(gpus.len(), 1);
+    assert_eq!(gpus[0].name, "GeForce GTX 1060 6GB");
+}
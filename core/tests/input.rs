@@ -0,0 +1,211 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use waifu2x_gui_core::input::{AddSummary, FolderFilter, Glob, InputList, InputStatus};
+use waifu2x_gui_core::Waifu2xState;
+
+fn list(names: &[&str]) -> InputList {
+    let mut inputs = InputList::default();
+    for name in names {
+        inputs.add(PathBuf::from(name));
+    }
+    inputs
+}
+
+fn names(inputs: &InputList) -> Vec<String> {
+    inputs
+        .paths()
+        .map(|p| p.to_string_lossy().into_owned())
+        .collect()
+}
+
+#[test]
+fn duplicates_are_ignored() {
+    let mut inputs = list(&["a.png", "b.png"]);
+
+    assert!(!inputs.add(PathBuf::from("a.png")));
+    assert_eq!(names(&inputs), ["a.png", "b.png"]);
+    assert_eq!(inputs.files()[0].status, InputStatus::Missing);
+}
+
+#[test]
+fn remove_and_reorder() {
+    let mut inputs = list(&["a.png", "b.png", "c.png", "d.png", "e.png"]);
+
+    inputs.remove(&[1, 3, 9]);
+    assert_eq!(names(&inputs), ["a.png", "c.png", "e.png"]);
+
+    // The first input is blocked by the top, so only the third moves
+    assert_eq!(inputs.move_up(&[0, 2]), [0, 1]);
+    assert_eq!(names(&inputs), ["a.png", "e.png", "c.png"]);
+
+    assert_eq!(inputs.move_down(&[0, 1]), [1, 2]);
+    assert_eq!(names(&inputs), ["c.png", "a.png", "e.png"]);
+}
+
+#[test]
+fn status_follows_the_path() {
+    let mut inputs = list(&["a.png", "b.png"]);
+
+    let index = inputs.set_status(Path::new("b.png"), InputStatus::Ready);
+    assert_eq!(index, Some(1));
+    assert_eq!(inputs.files()[1].status, InputStatus::Ready);
+
+    inputs.refresh();
+    assert_eq!(inputs.files()[1].status, InputStatus::Missing);
+}
+
+#[test]
+fn globs_match_names() {
+    let matches = |pattern: &str, name: &str| Glob::new(pattern).matches(OsStr::new(name));
+
+    assert!(matches("*.png", "Scan.PNG"));
+    assert!(!matches("*.png", "scan.png.bak"));
+    assert!(matches("img_??.jpg", "img_01.jpg"));
+    assert!(!matches("img_??.jpg", "img_1.jpg"));
+    // The first `*` has to give up some of what it took
+    assert!(matches("a*b*c", "aXbYbc"));
+    assert!(matches("*_2x*", "cat_2x.png"));
+
+    assert_eq!(Glob::parse_list(" *.png; *.jpg,,").len(), 2);
+}
+
+/// `root/{a.png, notes.txt, sub/{c.jpg, c_2x.png}, done_2x/d.png}`
+fn tree(name: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(name);
+    let _ = fs::remove_dir_all(&root);
+
+    for file in [
+        "a.png",
+        "notes.txt",
+        "sub/c.jpg",
+        "sub/c_2x.png",
+        "done_2x/d.png",
+    ] {
+        let path = root.join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "").unwrap();
+    }
+
+    root
+}
+
+#[test]
+fn folder_filter_recurses_and_excludes() {
+    let root = tree("waifu2x-gui-core-input-scan-test");
+    let relative = |filter: FolderFilter| {
+        let scan = filter.scan(&root).unwrap();
+        assert!(scan.unreadable.is_empty());
+        scan.files
+            .iter()
+            .map(|f| f.strip_prefix(&root).unwrap().to_path_buf())
+            .collect::<Vec<_>>()
+    };
+
+    let flat = FolderFilter::new(false, "*.png;*.jpg", "");
+    assert_eq!(relative(flat), [Path::new("a.png")]);
+
+    // Excluded folders are not searched at all
+    let deep = FolderFilter::new(true, "*.png;*.jpg", "*_2x*");
+    assert_eq!(relative(deep), [Path::new("a.png"), Path::new("sub/c.jpg")]);
+
+    let everything = FolderFilter::new(true, "", "");
+    assert_eq!(relative(everything).len(), 5);
+
+    // By default, everything that can be added is found
+    for file in ["clip.MP4", "loop.gif", "loop.apng"] {
+        fs::write(root.join(file), "").unwrap();
+    }
+    let mut found = relative(FolderFilter::default());
+    found.sort();
+    assert_eq!(
+        found,
+        ["a.png", "clip.MP4", "loop.apng", "loop.gif"].map(PathBuf::from)
+    );
+
+    assert!(FolderFilter::new(true, "", "")
+        .scan(&root.join("missing"))
+        .is_err());
+}
+
+#[test]
+fn mirrors_the_folder_structure() {
+    let root = tree("waifu2x-gui-core-input-mirror-test");
+
+    let mut state = Waifu2xState {
+        scale_level: 2,
+        denoise_level: 0,
+        recursive: true,
+        include_patterns: String::from("*.png;*.jpg"),
+        mirror_structure: true,
+        ..Default::default()
+    };
+    state.add_folder(&root).unwrap();
+    // A nested folder added by itself is its own root
+    state.add_folder(&root.join("sub")).unwrap();
+
+    let out = Path::new("out");
+    let folders = state
+        .plan_jobs(out)
+        .unwrap()
+        .into_iter()
+        .map(|job| job.output.parent().unwrap().to_path_buf())
+        .collect::<Vec<_>>();
+
+    // a.png, done_2x/d.png, sub/c.jpg, sub/c_2x.png
+    assert_eq!(folders, [out, &out.join("done_2x"), out, out]);
+
+    state.mirror_structure = false;
+    assert!(state
+        .plan_jobs(out)
+        .unwrap()
+        .iter()
+        .all(|j| j.output.parent() == Some(out)));
+}
+
+#[test]
+fn add_summary_counts_what_was_skipped() {
+    let root = tree("waifu2x-gui-core-input-add-test");
+
+    let mut state = Waifu2xState {
+        recursive: true,
+        include_patterns: String::from("*.png;*.jpg"),
+        exclude_patterns: String::from("c_2x*"),
+        ..Default::default()
+    };
+
+    let summary = state.add_paths(&[
+        root.clone(),
+        root.join("a.png"),
+        root.join("notes.txt"),
+        root.join("missing"),
+    ]);
+    // notes.txt and c_2x.png are filtered out of the folder, and notes.txt
+    // added by itself is unsupported
+    assert_eq!(
+        summary,
+        AddSummary {
+            added: 3,
+            duplicates: 1,
+            unsupported: 2,
+            filtered: 2,
+            unreadable: Vec::new(),
+        }
+    );
+    assert_eq!(
+        summary.to_string(),
+        "Added 3 files, skipped 2 unsupported, skipped 1 already selected, \
+         skipped 2 excluded by the folder filter"
+    );
+
+    // Without patterns the folder's own text file is unsupported
+    let mut state = Waifu2xState {
+        recursive: true,
+        include_patterns: String::new(),
+        ..Default::default()
+    };
+    let summary = state.add_paths(&[root.clone(), root]);
+    assert_eq!((summary.added, summary.unsupported), (4, 2));
+    assert_eq!((summary.duplicates, summary.filtered), (4, 0));
+}
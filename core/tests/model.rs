@@ -0,0 +1,66 @@
+use std::fs;
+use std::path::Path;
+
+use waifu2x_gui_core::model::{self, Model};
+use waifu2x_gui_core::Engine;
+
+fn write_model(dir: &Path, files: &[&str]) {
+    fs::create_dir_all(dir).unwrap();
+    for file in files {
+        fs::write(dir.join(format!("{}.param", file)), "").unwrap();
+        fs::write(dir.join(format!("{}.bin", file)), "").unwrap();
+    }
+}
+
+#[test]
+fn detects_levels() {
+    let dir = std::env::temp_dir().join("waifu2x-gui-core-model-test");
+    let _ = fs::remove_dir_all(&dir);
+    write_model(
+        &dir.join("models-cunet"),
+        &["noise0_model", "noise0_scale2.0x_model", "scale2.0x_model"],
+    );
+    write_model(
+        &dir.join("models-upconv_7_photo"),
+        &["noise0_scale2.0x_model", "scale2.0x_model"],
+    );
+    write_model(&dir.join("models-empty"), &[]);
+
+    let models = model::scan(Engine::Waifu2x, &dir.join("waifu2x-ncnn-vulkan"));
+    let names = models.iter().map(|m| m.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(names, ["models-cunet", "models-upconv_7_photo"]);
+
+    let cunet = &models[0];
+    assert!(cunet.supports(1, 0));
+    assert!(cunet.supports(2, 0));
+    assert!(cunet.supports(4, -1));
+    assert!(!cunet.supports(2, 3));
+
+    // upconv_7 lacks the denoise-only models
+    let upconv = &models[1];
+    assert!(!upconv.supports(1, 0));
+    assert!(upconv.supports(2, 0));
+}
+
+#[test]
+fn groups_per_scale_files() {
+    let dir = std::env::temp_dir().join("waifu2x-gui-core-model-esrgan-test");
+    let _ = fs::remove_dir_all(&dir);
+    write_model(
+        &dir.join("models"),
+        &[
+            "realesr-animevideov3-x2",
+            "realesr-animevideov3-x4",
+            "realesrgan-x4plus",
+        ],
+    );
+
+    let exe = dir.join("realesrgan-ncnn-vulkan");
+    let models = model::scan(Engine::RealEsrgan, &exe);
+    let names = models.iter().map(|m| m.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(names, ["realesr-animevideov3", "realesrgan-x4plus"]);
+
+    let video = Model::locate(Engine::RealEsrgan, &exe, "realesr-animevideov3").unwrap();
+    assert!(video.supports(2, -1));
+    assert!(!video.supports(3, -1));
+}
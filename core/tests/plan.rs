@@ -0,0 +1,246 @@
+mod common;
+
+use std::fs::File;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use waifu2x_gui_core::plan::{Collision, CollisionPolicy, Plan};
+use waifu2x_gui_core::queue::QueueSettings;
+use waifu2x_gui_core::staging::{self, BatchSettings, Strategy};
+use waifu2x_gui_core::{Device, Job, Waifu2xArgs};
+
+use common::test_dir;
+
+/// Three jobs in a fresh folder: the first output is missing, the second is
+/// older than its input, the third newer.
+fn jobs(name: &str) -> (PathBuf, Vec<Job>) {
+    let dir = test_dir(name);
+
+    let now = SystemTime::now();
+    let touch = |name: &str, age: u64| {
+        let path = dir.join(name);
+        let file = File::create(&path).unwrap();
+        file.set_modified(now - Duration::from_secs(age)).unwrap();
+        path
+    };
+
+    let jobs = vec![
+        Job {
+            input: touch("a.png", 60),
+            output: dir.join("a_2x.png"),
+        },
+        Job {
+            input: touch("b.png", 60),
+            output: touch("b_2x.png", 120),
+        },
+        Job {
+            input: touch("c.png", 60),
+            output: touch("c_2x.png", 0),
+        },
+    ];
+
+    (dir, jobs)
+}
+
+fn plan_of(jobs: &[Job], policy: CollisionPolicy) -> Plan {
+    let jobs = jobs
+        .iter()
+        .map(|job| {
+            let args = Waifu2xArgs {
+                input: job.input.clone(),
+                output: job.output.clone(),
+                ..Default::default()
+            };
+            (job.clone(), args)
+        })
+        .collect();
+
+    Plan::new(
+        PathBuf::from("waifu2x-ncnn-vulkan"),
+        QueueSettings::default(),
+        jobs,
+        policy,
+    )
+}
+
+fn collisions(plan: &Plan) -> Vec<Collision> {
+    plan.jobs.iter().map(|j| j.collision).collect()
+}
+
+#[test]
+fn applies_each_policy() {
+    let (_, jobs) = jobs("waifu2x-gui-core-plan-test");
+    use Collision::*;
+
+    let expected = [
+        (CollisionPolicy::Overwrite, [Free, Overwrite, Overwrite]),
+        (CollisionPolicy::Skip, [Free, Skip, Skip]),
+        (CollisionPolicy::SkipIfNewer, [Free, Overwrite, UpToDate]),
+        (CollisionPolicy::Ask, [Free, Ask, Ask]),
+    ];
+
+    for (policy, collisions_of) in expected {
+        let plan = plan_of(&jobs, policy);
+        assert_eq!(collisions(&plan), collisions_of, "{:?}", policy);
+    }
+
+    let plan = plan_of(&jobs, CollisionPolicy::SkipIfNewer);
+    assert_eq!(plan.runnable().count(), 2);
+    assert_eq!(
+        plan.to_string(),
+        "3 files: 1 new, 1 overwritten, 1 skipped as up to date"
+    );
+
+    let mut plan = plan_of(&jobs, CollisionPolicy::Ask);
+    assert_eq!(plan.runnable().count(), 1);
+    plan.answer(false);
+    assert_eq!(collisions(&plan), [Free, Skip, Skip]);
+}
+
+#[test]
+fn renames_to_free_names() {
+    let (dir, mut jobs) = jobs("waifu2x-gui-core-plan-rename-test");
+    File::create(dir.join("b_2x_1.png")).unwrap();
+    // Writes to the name the first rename would pick
+    jobs.push(Job {
+        input: dir.join("d.png"),
+        output: dir.join("c_2x_1.png"),
+    });
+
+    let plan = plan_of(&jobs, CollisionPolicy::Rename);
+    let outputs = plan
+        .runnable()
+        .map(|j| j.job.output.file_name().unwrap().to_owned())
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        outputs,
+        ["a_2x.png", "b_2x_2.png", "c_2x_2.png", "c_2x_1.png"]
+    );
+    assert_eq!(plan.count(Collision::Rename), 2);
+    // The renamed output is what the engine is told to write
+    assert!(plan.runnable().all(|j| j.args.output == j.job.output));
+}
+
+#[cfg(unix)]
+#[test]
+fn renames_keep_non_utf8_names() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = test_dir("waifu2x-gui-core-plan-raw-rename-test");
+    let input = dir.join(OsStr::from_bytes(b"caf\xe9.png"));
+    let output = dir.join(OsStr::from_bytes(b"caf\xe9_2x.png"));
+    File::create(&input).unwrap();
+    File::create(&output).unwrap();
+
+    let plan = plan_of(&[Job { input, output }], CollisionPolicy::Rename);
+    let renamed = &plan.runnable().next().unwrap().job.output;
+
+    assert_eq!(renamed.file_name().unwrap().as_bytes(), b"caf\xe9_2x_1.png");
+}
+
+#[test]
+fn previews_as_csv() {
+    let (dir, mut jobs) = jobs("waifu2x-gui-core-plan-csv-test");
+    jobs[2].output = jobs[1].output.clone();
+    jobs.push(Job {
+        input: dir.join("missing, really.png"),
+        output: dir.join("d_2x.png"),
+    });
+
+    let plan = plan_of(&jobs, CollisionPolicy::Skip);
+    let warnings = plan
+        .jobs
+        .iter()
+        .map(|j| j.warnings.len())
+        .collect::<Vec<_>>();
+    assert_eq!(warnings, [0, 1, 1, 1]);
+    assert_eq!(plan.warning_count(), 3);
+
+    let csv = plan.to_csv();
+    let lines = csv.lines().collect::<Vec<_>>();
+    assert_eq!(lines[0], "Input,Output,Command,If output exists,Warnings");
+    assert_eq!(lines.len(), 5);
+
+    // The command quotes the path with a space, and CSV the whole field
+    let (input, output) = (jobs[3].input.display(), jobs[3].output.display());
+    assert_eq!(
+        lines[4],
+        format!(
+            "\"{0}\",{1},\"waifu2x-ncnn-vulkan -i \"\"{0}\"\" -o {1} -s 2 -n 0\",new,\
+             The input no longer exists",
+            input, output
+        )
+    );
+}
+
+#[test]
+fn plans_the_commands_the_queue_runs() {
+    let dir = std::env::temp_dir().join("waifu2x-gui-core-plan-steps-test");
+    let jobs = ["a.png", "b.png", "c.png", "clip.mp4"]
+        .map(|name| Job {
+            input: dir.join(name),
+            output: dir.join("out").join(name),
+        })
+        .to_vec();
+    let planned = |settings: QueueSettings| {
+        let jobs = jobs
+            .iter()
+            .map(|job| {
+                let args = Waifu2xArgs {
+                    input: job.input.clone(),
+                    output: job.output.clone(),
+                    gpus: settings.devices.iter().map(|d| d.id).collect(),
+                    ..Default::default()
+                };
+                (job.clone(), args)
+            })
+            .collect();
+        let exe = PathBuf::from("waifu2x-ncnn-vulkan");
+        Plan::new(exe, settings, jobs, CollisionPolicy::Overwrite)
+    };
+
+    // The images share a directory mode process on the only device
+    let plan = planned(QueueSettings {
+        devices: Device::parse_list("1").unwrap(),
+        batching: BatchSettings {
+            strategy: Strategy::Directory,
+            threshold: 2,
+        },
+        ..Default::default()
+    });
+    let work_dir = staging::work_dir(0, &jobs[0]);
+    let group = format!(
+        "waifu2x-ncnn-vulkan -i {} -o {} -s 2 -n 0 -g 1",
+        work_dir.join("input").display(),
+        work_dir.join("output").display()
+    );
+    for job in &plan.jobs[..3] {
+        assert_eq!(job.steps.commands, [group.as_str()]);
+        assert!(!job.steps.template);
+    }
+
+    // The video runs every stage, with the frame rate left to its probe
+    let video = &plan.jobs[3];
+    assert!(video.steps.template);
+    assert_eq!(video.steps.commands.len(), 4);
+    assert!(video.steps.commands[1].contains("{frame rate}"));
+    assert!(video.steps.commands[2].starts_with("waifu2x-ncnn-vulkan -i "));
+    assert!(plan.command_line(video).starts_with("Template: ffprobe "));
+
+    // With several devices, each image gets one that is only known later
+    let plan = planned(QueueSettings {
+        devices: Device::parse_list("0,1").unwrap(),
+        ..Default::default()
+    });
+    assert!(plan.has_templates());
+    assert_eq!(
+        plan.command_line(&plan.jobs[0]),
+        format!(
+            "Template: waifu2x-ncnn-vulkan -i {} -o {} -s 2 -n 0 -g {{gpu}}",
+            jobs[0].input.display(),
+            jobs[0].output.display()
+        )
+    );
+}
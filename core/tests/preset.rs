@@ -0,0 +1,108 @@
+mod common;
+
+use std::fs;
+
+use waifu2x_gui_core::config::{ConfigError, Settings};
+use waifu2x_gui_core::preset::{PresetError, PresetStore};
+
+use common::test_dir;
+
+fn settings(scale_level: i32) -> Settings {
+    Settings {
+        scale_level,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn round_trips_through_a_pack() {
+    let dir = test_dir("waifu2x-gui-core-preset-test");
+
+    let mut store = PresetStore::default();
+    store.insert(" scan cleanup ", settings(4)).unwrap();
+    store.insert("sprites", settings(2)).unwrap();
+    // Saving under an existing name replaces its settings
+    store.insert("sprites", settings(8)).unwrap();
+    assert!(matches!(
+        store.insert("  ", settings(2)),
+        Err(PresetError::EmptyName)
+    ));
+    assert_eq!(store.names(), ["scan cleanup", "sprites"]);
+
+    let path = dir.join("presets.toml");
+    store.export(&path).unwrap();
+    let loaded = PresetStore::load(&path).unwrap();
+    assert_eq!(loaded, store);
+    assert_eq!(loaded.get("sprites").unwrap().settings, settings(8));
+
+    assert_eq!(
+        PresetStore::load(&dir.join("missing.toml")).unwrap(),
+        PresetStore::default()
+    );
+}
+
+#[test]
+fn rename_refuses_taken_names() {
+    let mut store = PresetStore::default();
+    store.insert("a", settings(2)).unwrap();
+    store.insert("b", settings(4)).unwrap();
+
+    assert!(matches!(
+        store.rename("a", "b"),
+        Err(PresetError::AlreadyExists(name)) if name == "b"
+    ));
+    assert!(matches!(
+        store.rename("missing", "c"),
+        Err(PresetError::NotFound(_))
+    ));
+    assert!(matches!(store.rename("a", ""), Err(PresetError::EmptyName)));
+    assert_eq!(store.names(), ["a", "b"]);
+
+    store.rename("a", "a").unwrap();
+    store.rename("a", "c").unwrap();
+    assert_eq!(store.names(), ["c", "b"]);
+    assert_eq!(store.get("c").unwrap().settings, settings(2));
+
+    assert_eq!(store.remove("b").unwrap().settings, settings(4));
+    assert!(matches!(store.remove("b"), Err(PresetError::NotFound(_))));
+    assert_eq!(store.names(), ["c"]);
+}
+
+#[test]
+fn imports_all_or_nothing() {
+    let dir = test_dir("waifu2x-gui-core-preset-import-test");
+
+    let mut store = PresetStore::default();
+    store.insert("mine", settings(2)).unwrap();
+    let before = store.clone();
+
+    // The second "shared" conflicts with the first one of the same pack
+    let mut pack = PresetStore::default();
+    pack.insert("new", settings(4)).unwrap();
+    pack.insert("shared", settings(8)).unwrap();
+    let conflicting = pack.to_toml().unwrap() + "\n[[preset]]\nname = \"shared\"\n";
+    let path = dir.join("conflicting.toml");
+    fs::write(&path, conflicting).unwrap();
+
+    assert!(matches!(
+        store.import(&path),
+        Err(PresetError::AlreadyExists(name)) if name == "shared"
+    ));
+    assert_eq!(store, before);
+
+    // A mistyped path is not an empty pack
+    assert!(matches!(
+        store.import(&dir.join("missing.toml")),
+        Err(PresetError::File(ConfigError::Io(e))) if e.kind() == std::io::ErrorKind::NotFound
+    ));
+    assert_eq!(store, before);
+
+    // A valid pack overwrites presets of the same name
+    pack.insert("mine", settings(16)).unwrap();
+    let path = dir.join("pack.toml");
+    pack.export(&path).unwrap();
+
+    assert_eq!(store.import(&path).unwrap(), 3);
+    assert_eq!(store.names(), ["mine", "new", "shared"]);
+    assert_eq!(store.get("mine").unwrap().settings, settings(16));
+}
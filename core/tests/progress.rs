@@ -0,0 +1,98 @@
+use std::io::{Cursor, Read};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+use waifu2x_gui_core::progress::{self, parse_progress, Capture, SharedCapture};
+
+/// Captured stderr of `waifu2x-ncnn-vulkan -v` upscaling a single image.
+const VERBOSE_STDERR: &str = "\
+[0 NVIDIA GeForce GTX 1060 6GB]  queueC=2[8]  queueG=0[16]  queueT=1[2]
+[0 NVIDIA GeForce GTX 1060 6GB]  bugsbn1=0  bugbilz=0  bugcopc=0  bugihfa=0
+[0 NVIDIA GeForce GTX 1060 6GB]  fp16-p/s/a=1/1/0  int8-p/s/a=1/1/0
+[0 NVIDIA GeForce GTX 1060 6GB]  subgroup=32  basic=1  vote=1  ballot=1  shuffle=1
+0.00%
+16.67%
+33.33%
+50.00%
+66.67%
+83.33%
+cat.png -> cat_2x_0n.png done
+";
+
+#[test]
+fn parses_percentages() {
+    assert_eq!(parse_progress("0.00%"), Some(0.0));
+    assert_eq!(parse_progress("83.33%\r"), Some(83.33));
+    assert_eq!(parse_progress("100.00%"), Some(100.0));
+    assert_eq!(parse_progress("120.00%"), None);
+    assert_eq!(parse_progress("decode image failed"), None);
+    assert_eq!(parse_progress("%"), None);
+}
+
+#[test]
+fn captures_verbose_output() {
+    let mut capture = Capture::default();
+    for line in VERBOSE_STDERR.lines() {
+        capture.feed(line);
+    }
+
+    assert_eq!(capture.progress, 83.33);
+    assert_eq!(capture.log.lines().count(), 5);
+    assert!(!capture.log.contains('%'));
+    assert!(capture.log.ends_with("cat.png -> cat_2x_0n.png done\n"));
+    assert!(capture.done.contains("cat_2x_0n.png"));
+}
+
+#[test]
+fn reader_handles_carriage_returns() {
+    let capture = SharedCapture::default();
+    let output = b"12.50%\r25.00%\r\ndecode image failed\r\n".to_vec();
+
+    progress::spawn_reader(Cursor::new(output), capture.clone())
+        .join()
+        .unwrap();
+
+    let capture = capture.lock().unwrap();
+    assert_eq!(capture.progress, 25.0);
+    assert_eq!(capture.log, "decode image failed\n");
+}
+
+/// Hands out the chunks sent to it, and blocks until the next one arrives,
+/// like a pipe from a running process.
+struct ChunkReader(Receiver<Vec<u8>>);
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let Ok(chunk) = self.0.recv() else {
+            return Ok(0);
+        };
+        buf[..chunk.len()].copy_from_slice(&chunk);
+        Ok(chunk.len())
+    }
+}
+
+#[test]
+fn reader_updates_before_the_line_ends() {
+    let capture = SharedCapture::default();
+    let (sender, receiver) = mpsc::channel();
+    let reader = progress::spawn_reader(ChunkReader(receiver), capture.clone());
+
+    // ffmpeg -stats redraws its line with a bare '\r'
+    sender
+        .send(b"frame=   12 fps=6.0 q=-0.0 size=N/A\r".to_vec())
+        .unwrap();
+    sender.send(b"frame=   24 fps=6.0".to_vec()).unwrap();
+
+    for _ in 0..100 {
+        if capture.lock().unwrap().frames == 12 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(capture.lock().unwrap().frames, 12);
+
+    drop(sender);
+    reader.join().unwrap();
+    assert_eq!(capture.lock().unwrap().frames, 24);
+}
@@ -0,0 +1,150 @@
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use waifu2x_gui_core::{Job, JobQueue, JobState, QueueEvent, Waifu2xArgs};
+
+use common::engine_with;
+
+/// A fresh folder with an engine that marks itself as running for a moment,
+/// then fails for inputs named `bad*` and succeeds for the rest.
+fn stub_engine(name: &str) -> (PathBuf, PathBuf) {
+    let (dir, exe) = engine_with(
+        name,
+        "running=\"$(dirname \"$0\")/running/$$\"\n\
+         touch \"$running\"\n\
+         sleep 0.1\n\
+         rm \"$running\"\n\
+         case \"$(basename \"$2\")\" in bad*) exit 1;; esac\n",
+    );
+    fs::create_dir_all(dir.join("running")).unwrap();
+
+    (dir, exe)
+}
+
+fn push(queue: &mut JobQueue, dir: &Path, name: &str) {
+    let job = Job {
+        input: dir.join(name),
+        output: dir.join(format!("out_{}", name)),
+    };
+    let args = Waifu2xArgs {
+        input: job.input.clone(),
+        output: job.output.clone(),
+        ..Default::default()
+    };
+    queue.push(job, args);
+}
+
+fn states(queue: &JobQueue) -> Vec<JobState> {
+    queue.jobs().iter().map(|j| j.state).collect()
+}
+
+#[test]
+fn never_runs_more_than_the_limit() {
+    let (dir, exe) = stub_engine("waifu2x-gui-core-queue-limit-test");
+
+    let mut queue = JobQueue::new(exe, 2);
+    for i in 0..6 {
+        push(&mut queue, &dir, &format!("{}.png", i));
+    }
+
+    let mut most = 0;
+    while !queue.is_finished() {
+        queue.tick();
+
+        let running = fs::read_dir(dir.join("running")).unwrap().count();
+        assert!(queue.count(JobState::Running) <= 2);
+        assert!(running <= 2, "{} processes at once", running);
+        most = most.max(queue.count(JobState::Running));
+
+        thread::sleep(Duration::from_millis(5));
+    }
+
+    assert_eq!(most, 2);
+    assert_eq!(queue.count(JobState::Succeeded), 6);
+}
+
+#[test]
+fn retries_failed_and_cancels_pending_jobs() {
+    let (dir, exe) = stub_engine("waifu2x-gui-core-queue-retry-test");
+
+    let mut queue = JobQueue::new(exe, 1);
+    for name in ["a.png", "bad.png", "c.png"] {
+        push(&mut queue, &dir, name);
+    }
+
+    use JobState::*;
+    assert_eq!(states(&queue), [Pending, Pending, Pending]);
+
+    while !queue.is_finished() {
+        queue.tick();
+        thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(states(&queue), [Succeeded, Failed, Succeeded]);
+    assert_eq!(queue.jobs()[1].exit_code, Some(1));
+
+    assert_eq!(queue.retry_failed(), 1);
+    assert_eq!(states(&queue), [Succeeded, Pending, Succeeded]);
+    assert_eq!(queue.jobs()[1].exit_code, None);
+
+    queue.cancel_pending();
+    assert_eq!(states(&queue), [Succeeded, Cancelled, Succeeded]);
+    assert!(queue.is_finished());
+    assert_eq!(queue.retry_failed(), 0);
+}
+
+#[test]
+fn cancel_keeps_finished_outputs() {
+    // Writes its output, then takes long unless the input is `fast*`
+    let (dir, exe) = engine_with(
+        "waifu2x-gui-core-queue-cancel-test",
+        "echo done > \"$4\"\n\
+         case \"$(basename \"$2\")\" in fast*) exit 0;; esac\n\
+         exec sleep 10\n",
+    );
+
+    let mut queue = JobQueue::new(exe, 2);
+    for name in ["fast.png", "slow.png", "later.png"] {
+        push(&mut queue, &dir, name);
+    }
+
+    queue.tick();
+    // The fast one exits, but is not polled before the cancellation
+    thread::sleep(Duration::from_millis(300));
+    queue.cancel();
+
+    use JobState::*;
+    assert_eq!(states(&queue), [Succeeded, Cancelled, Cancelled]);
+    assert!(queue.jobs()[0].job.output.exists());
+    assert!(!queue.jobs()[1].job.output.exists());
+}
+
+#[test]
+fn missing_ffmpeg_fails_only_the_video() {
+    let (dir, exe) = stub_engine("waifu2x-gui-core-queue-ffmpeg-test");
+
+    let mut queue = JobQueue::new(exe, 1);
+    queue.set_video(dir.join("missing/ffmpeg"), Default::default());
+    for name in ["clip.mkv", "a.png"] {
+        push(&mut queue, &dir, name);
+    }
+
+    let mut events = Vec::new();
+    while !queue.is_finished() {
+        events.extend(queue.tick());
+        thread::sleep(Duration::from_millis(5));
+    }
+
+    assert!(matches!(events[0], QueueEvent::Failed(0, _)));
+    assert!(!events
+        .iter()
+        .any(|e| matches!(e, QueueEvent::SpawnFailed(..))));
+
+    use JobState::*;
+    assert_eq!(states(&queue), [Failed, Succeeded]);
+}
@@ -0,0 +1,148 @@
+mod common;
+
+use waifu2x_gui_core::staging::{BatchSettings, Strategy};
+
+#[test]
+fn splits_groups_across_processes() {
+    let batching = BatchSettings {
+        strategy: Strategy::Directory,
+        threshold: 8,
+    };
+
+    assert_eq!(batching.group_size(7, 1), None);
+    assert_eq!(batching.group_size(8, 1), Some(8));
+    assert_eq!(batching.group_size(100, 4), Some(25));
+    // Groups do not shrink below the threshold to fill every process
+    assert_eq!(batching.group_size(10, 4), Some(8));
+
+    let per_file = BatchSettings {
+        strategy: Strategy::PerFile,
+        ..batching
+    };
+    assert_eq!(per_file.group_size(100, 1), None);
+}
+
+#[cfg(unix)]
+#[test]
+fn shares_one_process_between_images() {
+    use std::fs;
+    use std::thread;
+    use std::time::Duration;
+
+    use waifu2x_gui_core::{Job, JobQueue, JobState, Waifu2xArgs};
+
+    // Copies every file of `-i dir` to `-o dir`, except the third one
+    let (dir, engine) = common::engine_with(
+        "waifu2x-gui-core-staging-test",
+        "echo run >> \"$(dirname \"$0\")/runs\"\n\
+         for f in \"$2\"/*; do\n\
+         name=$(basename \"$f\")\n\
+         case \"$name\" in 00000003.*) echo \"decode image $f failed\" >&2; continue ;; esac\n\
+         cp \"$f\" \"$4/$name\"\n\
+         echo \"$f -> $4/$name done\" >&2\n\
+         done\n",
+    );
+
+    let mut queue = JobQueue::new(engine, 1);
+    queue.set_batching(BatchSettings {
+        strategy: Strategy::Directory,
+        threshold: 4,
+    });
+
+    // The same file name in two folders
+    for i in 0..6 {
+        let folder = dir.join(if i % 2 == 0 { "a" } else { "b" });
+        fs::create_dir_all(&folder).unwrap();
+
+        let input = folder.join(format!("{}.png", i / 2));
+        fs::write(&input, format!("image {}", i)).unwrap();
+
+        let job = Job {
+            input,
+            output: dir.join(format!("out/{}/{}_2x.png", i % 2, i / 2)),
+        };
+        queue.push(job, Waifu2xArgs::default());
+    }
+
+    while !queue.is_finished() {
+        queue.tick();
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    assert_eq!(fs::read_to_string(dir.join("runs")).unwrap(), "run\n");
+
+    for (i, job) in queue.jobs().iter().enumerate() {
+        if i == 2 {
+            assert_eq!(job.state, JobState::Failed);
+            assert!(job.failure_message().unwrap().contains("failed"));
+            assert!(!job.job.output.exists());
+            continue;
+        }
+
+        assert_eq!(
+            job.state,
+            JobState::Succeeded,
+            "{:?}",
+            job.failure_message()
+        );
+        assert_eq!(
+            fs::read_to_string(&job.job.output).unwrap(),
+            format!("image {}", i)
+        );
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn cancel_keeps_files_a_shared_process_finished() {
+    use std::fs;
+    use std::thread;
+    use std::time::Duration;
+
+    use waifu2x_gui_core::{Job, JobQueue, JobState, Waifu2xArgs};
+
+    // Finishes the first file, then hangs
+    let (dir, engine) = common::engine_with(
+        "waifu2x-gui-core-staging-cancel-test",
+        "cp \"$2/00000001.png\" \"$4/00000001.png\"\n\
+         echo \"$2/00000001.png -> $4/00000001.png done\" >&2\n\
+         exec sleep 10\n",
+    );
+
+    let mut queue = JobQueue::new(engine, 1);
+    queue.set_batching(BatchSettings {
+        strategy: Strategy::Directory,
+        threshold: 2,
+    });
+
+    for i in 0..3 {
+        let input = dir.join(format!("{}.png", i));
+        fs::write(&input, format!("image {}", i)).unwrap();
+
+        let job = Job {
+            input,
+            output: dir.join(format!("out/{}_2x.png", i)),
+        };
+        queue.push(job, Waifu2xArgs::default());
+    }
+
+    queue.tick();
+    for _ in 0..200 {
+        if queue.jobs()[0].progress() == 100.0 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    let work_dir = queue.jobs()[0].staged.as_ref().unwrap().work_dir.clone();
+    queue.cancel();
+
+    let states = queue.jobs().iter().map(|j| j.state).collect::<Vec<_>>();
+    use JobState::*;
+    assert_eq!(states, [Succeeded, Cancelled, Cancelled]);
+    assert_eq!(
+        fs::read_to_string(dir.join("out/0_2x.png")).unwrap(),
+        "image 0"
+    );
+    assert!(!dir.join("out/1_2x.png").exists());
+    assert!(!work_dir.exists());
+}
@@ -0,0 +1,115 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use waifu2x_gui_core::naming::{self, FileContext, NameError};
+use waifu2x_gui_core::state::{PlanError, Waifu2xState};
+use waifu2x_gui_core::template::{Context, Template, TemplateErrorKind, Value};
+
+struct Vars;
+
+impl Context for Vars {
+    fn value(&self, variable: &str) -> Value {
+        match variable {
+            "name" => Value::Text("cat".into()),
+            "index" => Value::Int(7),
+            "scale" => Value::Int(2),
+            "denoise" => Value::Unset(String::from("-1")),
+            _ => Value::Unset(String::new()),
+        }
+    }
+}
+
+fn render(template: &str) -> OsString {
+    Template::parse(template).unwrap().render(&Vars)
+}
+
+#[test]
+fn default_template() {
+    assert_eq!(render("{name}_{scale}x_{denoise}n"), "cat_2x_-1n");
+}
+
+#[test]
+fn repeated_and_escaped() {
+    assert_eq!(render("{name}-{name}"), "cat-cat");
+    assert_eq!(render("{{{name}}}"), "{cat}");
+}
+
+#[test]
+fn format_specs() {
+    assert_eq!(render("{name}_{index:04}"), "cat_0007");
+    assert_eq!(render("{name}_{denoise:none=off}"), "cat_off");
+    assert_eq!(render("{name}_{scale:2=double,4=quad}"), "cat_double");
+}
+
+#[test]
+fn errors_point_at_span() {
+    let e = Template::parse("{name}_{nmae}").unwrap_err();
+    assert_eq!(
+        e.kind,
+        TemplateErrorKind::UnknownVariable(String::from("nmae"))
+    );
+    assert_eq!(e.span, 7..13);
+
+    let e = Template::parse("{name}_{scale").unwrap_err();
+    assert_eq!(e.kind, TemplateErrorKind::UnclosedPlaceholder);
+    assert_eq!(e.span.start, 7);
+
+    let e = Template::parse("{name}}").unwrap_err();
+    assert_eq!(e.kind, TemplateErrorKind::UnmatchedBrace);
+
+    let e = Template::parse("{scale}x").unwrap_err();
+    assert_eq!(e.kind, TemplateErrorKind::MissingName);
+}
+
+#[test]
+fn broken_templates_fail_validation() {
+    let mut state = Waifu2xState {
+        scale_level: 2,
+        filename_format: String::from("{name}_{nmae}"),
+        ..Default::default()
+    };
+    state.inputs.add(PathBuf::from("/in/a.png"));
+
+    assert!(matches!(
+        state.validate(),
+        Err(PlanError::Name(NameError::Template(_)))
+    ));
+}
+
+fn output_path(template: &str, input: &str) -> PathBuf {
+    let state = Waifu2xState::default();
+    let context = FileContext {
+        input: Path::new(input),
+        index: 0,
+        date: "2024-01-01",
+        dimensions: None,
+        state: &state,
+    };
+
+    naming::output_path(
+        Path::new("/out"),
+        &Template::parse(template).unwrap(),
+        &context,
+    )
+    .unwrap()
+}
+
+#[test]
+fn dotted_names_keep_their_suffix() {
+    assert_eq!(
+        output_path("{name}_{scale}x_{denoise}n", "/in/photo.v2.png"),
+        Path::new("/out/photo.v2_1x_-1n.png")
+    );
+    assert_eq!(
+        output_path("{name}.{ext}", "/in/photo.v2.jpg"),
+        Path::new("/out/photo.v2.jpg.png")
+    );
+}
+
+#[test]
+fn ext_variable_is_kept() {
+    assert_eq!(
+        output_path("{name}.{ext}", "/in/a.jpg"),
+        Path::new("/out/a.jpg.png")
+    );
+}
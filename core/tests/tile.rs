@@ -0,0 +1,98 @@
+mod common;
+
+use waifu2x_gui_core::args::parse_tile_sizes;
+use waifu2x_gui_core::progress::is_allocation_error;
+use waifu2x_gui_core::queue::smaller_tile_size;
+
+#[test]
+fn parses_tile_sizes() {
+    assert_eq!(parse_tile_sizes("auto"), Ok(vec![]));
+    assert_eq!(parse_tile_sizes("200, 0"), Ok(vec![200, 0]));
+    assert!(parse_tile_sizes("16").is_err());
+}
+
+#[test]
+fn shrinks_tiles_after_running_out_of_memory() {
+    assert!(is_allocation_error(
+        "[0 GeForce GTX 1050]  queueC=2[8]\nvkAllocateMemory failed\n"
+    ));
+    assert!(!is_allocation_error("decode image a.png failed\n"));
+
+    // The engine may have picked 100 by itself
+    assert_eq!(smaller_tile_size(None), Some(64));
+    assert_eq!(smaller_tile_size(Some(400)), Some(200));
+    assert_eq!(smaller_tile_size(Some(150)), Some(100));
+    assert_eq!(smaller_tile_size(Some(64)), Some(32));
+    assert_eq!(smaller_tile_size(Some(32)), None);
+}
+
+#[cfg(unix)]
+#[test]
+fn retries_with_a_smaller_tile_size() {
+    use std::thread;
+    use std::time::Duration;
+
+    use waifu2x_gui_core::{Job, JobQueue, JobState, Waifu2xArgs};
+
+    // Only fits into memory with tiles of 64 or less
+    let (dir, exe) = common::engine_with(
+        "waifu2x-gui-core-tile-test",
+        "case \"$*\" in *\"-t 64\"*) exit 0;; esac\necho vkAllocateMemory failed >&2\n",
+    );
+
+    let mut queue = JobQueue::new(exe, 1);
+    let job = Job {
+        input: dir.join("a.png"),
+        output: dir.join("a_2x.png"),
+    };
+    let args = Waifu2xArgs {
+        tile_sizes: vec![400],
+        ..Default::default()
+    };
+    queue.push(job, args);
+
+    while !queue.is_finished() {
+        queue.tick();
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let job = &queue.jobs()[0];
+    assert_eq!(job.state, JobState::Succeeded);
+    assert_eq!(job.tile_size, Some(64));
+}
+
+#[cfg(unix)]
+#[test]
+fn retries_an_automatic_tile_size_below_the_engines_pick() {
+    use std::fs;
+    use std::thread;
+    use std::time::Duration;
+
+    use waifu2x_gui_core::{Job, JobQueue, JobState, Waifu2xArgs};
+
+    // Runs out of memory with whatever it picks by itself
+    let (dir, exe) = common::engine_with(
+        "waifu2x-gui-core-tile-auto-test",
+        "echo run >> \"$(dirname \"$0\")/runs\"\n\
+         case \"$*\" in *\"-t \"*) exit 0;; esac\n\
+         echo vkAllocateMemory failed >&2\n",
+    );
+
+    let mut queue = JobQueue::new(exe, 1);
+    let job = Job {
+        input: dir.join("a.png"),
+        output: dir.join("a_2x.png"),
+    };
+    queue.push(job, Waifu2xArgs::default());
+
+    while !queue.is_finished() {
+        queue.tick();
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let job = &queue.jobs()[0];
+    assert_eq!(job.state, JobState::Succeeded);
+    assert_eq!(job.tile_size, Some(64));
+    // Straight from the engine's choice to 64, not through 200 and 100
+    assert_eq!(fs::read_to_string(dir.join("runs")).unwrap(), "run\nrun\n");
+}
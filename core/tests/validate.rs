@@ -0,0 +1,46 @@
+use std::fs;
+
+use waifu2x_gui_core::validate::{check_model, check_threads, check_tile_sizes};
+use waifu2x_gui_core::Engine;
+
+#[test]
+fn thread_counts() {
+    assert_eq!(check_threads("", 1), Ok(()));
+    assert_eq!(check_threads("1:2:2", 3), Ok(()));
+    assert_eq!(check_threads("1:2,2,2:2", 3), Ok(()));
+    assert!(check_threads("1:2", 1).is_err());
+    assert!(check_threads("1:abc:2", 1).is_err());
+    assert!(check_threads("1:0:2", 1).is_err());
+    assert!(check_threads("1:2,2:2", 3).is_err());
+}
+
+#[test]
+fn tile_sizes() {
+    assert_eq!(check_tile_sizes("auto", 1), Ok(()));
+    assert_eq!(check_tile_sizes("0", 2), Ok(()));
+    assert_eq!(check_tile_sizes("400,0", 2), Ok(()));
+    assert!(check_tile_sizes("16", 1).is_err());
+    assert!(check_tile_sizes("200,200", 3).is_err());
+}
+
+#[test]
+fn model_files() {
+    let dir = std::env::temp_dir().join("waifu2x-gui-core-validate-test");
+    let model = dir.join("models-upconv_7_photo");
+    fs::create_dir_all(&model).unwrap();
+    for file in ["noise1_scale2.0x_model", "scale2.0x_model"] {
+        fs::write(model.join(format!("{}.param", file)), "").unwrap();
+        fs::write(model.join(format!("{}.bin", file)), "").unwrap();
+    }
+
+    let exe = dir.join("waifu2x-ncnn-vulkan");
+    let model = model.to_str().unwrap();
+
+    assert_eq!(check_model(Engine::Waifu2x, &exe, model, 2, 1), Ok(()));
+    assert_eq!(check_model(Engine::Waifu2x, &exe, model, 4, -1), Ok(()));
+    // upconv_7 has no denoise-only model
+    assert!(check_model(Engine::Waifu2x, &exe, model, 1, 1).is_err());
+    assert!(check_model(Engine::Waifu2x, &exe, "models-nonexistent", 2, 1).is_err());
+    // The default model is not required to be found
+    assert_eq!(check_model(Engine::Waifu2x, &exe, "", 2, 1), Ok(()));
+}
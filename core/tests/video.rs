@@ -0,0 +1,178 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use waifu2x_gui_core::progress::{parse_frame_count, Capture};
+use waifu2x_gui_core::video::{self, is_complete_png, parse_probe, Stage, VideoJob};
+use waifu2x_gui_core::{Job, Waifu2xArgs};
+
+use common::test_dir;
+
+/// The end of every complete PNG.
+const IEND: [u8; 12] = [0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82];
+
+fn write_frame(path: &Path, complete: bool) {
+    let mut bytes = b"\x89PNG\r\n\x1a\n".to_vec();
+    if complete {
+        bytes.extend(IEND);
+    }
+    fs::write(path, bytes).unwrap();
+}
+
+#[test]
+fn reads_ffmpeg_output() {
+    assert!(video::is_video(Path::new("clip.MKV")));
+    assert!(!video::is_video(Path::new("cat.png")));
+
+    let probe = "r_frame_rate=24000/1001\nnb_read_packets=2400\n";
+    assert_eq!(
+        parse_probe(probe),
+        (Some(String::from("24000/1001")), Some(2400))
+    );
+    assert_eq!(parse_probe("r_frame_rate=0/0\n"), (None, None));
+
+    assert_eq!(
+        parse_frame_count("frame=  120 fps= 30 q=-0.0 size=N/A time=00:00:05.00"),
+        Some(120)
+    );
+    assert_eq!(parse_frame_count("frame=9"), Some(9));
+    assert_eq!(parse_frame_count("Stream mapping:"), None);
+}
+
+#[test]
+fn resumes_from_upscaled_frames() {
+    let dir = std::env::temp_dir().join("waifu2x-gui-core-video-resume-test");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("frames")).unwrap();
+    fs::create_dir_all(dir.join("upscaled")).unwrap();
+    fs::write(dir.join("extracted"), "").unwrap();
+
+    for i in 1..=3 {
+        write_frame(&dir.join(format!("frames/{:08}.png", i)), true);
+    }
+    write_frame(&dir.join("upscaled/00000001.png"), true);
+    // Cut short by a crash
+    write_frame(&dir.join("upscaled/00000002.png"), false);
+
+    let mut video = VideoJob::with_work_dir(dir.clone());
+    video.stage = Stage::Extract;
+    video.frame_count = Some(3);
+
+    let job = Job {
+        input: dir.join("clip.mp4"),
+        output: dir.join("clip_2x.mp4"),
+    };
+    let command = video
+        .command(
+            &job,
+            &Waifu2xArgs::default(),
+            Path::new("waifu2x-ncnn-vulkan"),
+            Path::new("ffmpeg"),
+            &Default::default(),
+        )
+        .unwrap();
+
+    assert_eq!(video.stage, Stage::Upscale);
+    assert_eq!(video.frames_done, 1);
+    assert!(command.get_args().any(|a| a == dir.join("pending")));
+
+    let mut pending = fs::read_dir(dir.join("pending"))
+        .unwrap()
+        .map(|e| e.unwrap().file_name())
+        .collect::<Vec<_>>();
+    pending.sort();
+    assert_eq!(pending, ["00000002.png", "00000003.png"]);
+
+    assert!(is_complete_png(&dir.join("upscaled/00000001.png")));
+    assert!(!is_complete_png(&dir.join("upscaled/00000002.png")));
+
+    let mut capture = Capture::default();
+    capture.feed("00000002.png -> 00000002.png done");
+    assert_eq!(video.frames(&capture), Some((2, 3)));
+}
+
+#[test]
+fn starts_over_for_a_replaced_video() {
+    let dir = test_dir("waifu2x-gui-core-video-key-test");
+
+    let job = Job {
+        input: dir.join("clip.mp4"),
+        output: dir.join("clip_2x.mp4"),
+    };
+    let args = Waifu2xArgs::default();
+
+    fs::write(&job.input, "first take").unwrap();
+    let first = VideoJob::new(&job, &args).work_dir;
+    assert_eq!(VideoJob::new(&job, &args).work_dir, first);
+
+    fs::write(&job.input, "a longer second take").unwrap();
+    assert_ne!(VideoJob::new(&job, &args).work_dir, first);
+}
+
+#[cfg(unix)]
+#[test]
+fn runs_every_stage() {
+    use std::thread;
+    use std::time::Duration;
+
+    use waifu2x_gui_core::{JobQueue, JobState};
+
+    let dir = test_dir("waifu2x-gui-core-video-test");
+
+    let frame = dir.join("frame.png");
+    write_frame(&frame, true);
+
+    common::script(
+        &dir.join("ffprobe"),
+        "echo r_frame_rate=24/1\necho nb_read_packets=3\n",
+    );
+    // Extracts three frames, or "encodes" by touching the output
+    let ffmpeg = dir.join("ffmpeg");
+    common::script(
+        &ffmpeg,
+        &format!(
+            "for last; do :; done\n\
+             case \"$last\" in\n\
+             *%08d.png) for i in 1 2 3; do cp {} \"$(dirname \"$last\")/0000000$i.png\"; done ;;\n\
+             *) touch \"$last\" ;;\n\
+             esac\n",
+            frame.display()
+        ),
+    );
+    // Copies every frame of `-i dir` to `-o dir`
+    let engine = dir.join("engine");
+    common::script(
+        &engine,
+        "cp \"$2\"/*.png \"$4\"/\n\
+         for f in \"$2\"/*.png; do echo \"$f -> $f done\" >&2; done\n",
+    );
+
+    let mut queue = JobQueue::new(engine, 1);
+    queue.set_video(ffmpeg, Default::default());
+
+    let job = Job {
+        input: dir.join("clip.mkv"),
+        output: dir.join("out/clip_2x.mkv"),
+    };
+    queue.push(job, Waifu2xArgs::default());
+
+    while !queue.is_finished() {
+        queue.tick();
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let job = &queue.jobs()[0];
+    assert_eq!(
+        job.state,
+        JobState::Succeeded,
+        "{:?}",
+        job.failure_message()
+    );
+    assert!(dir.join("out/clip_2x.mkv").is_file());
+
+    let video = job.video.as_ref().unwrap();
+    assert_eq!(video.frame_rate.as_deref(), Some("24/1"));
+    // The frames are gone once the video is encoded
+    assert!(!video.work_dir.exists());
+}
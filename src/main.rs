@@ -3,34 +3,60 @@
 extern crate native_windows_derive as nwd;
 extern crate native_windows_gui as nwg;
 
-use std::cell::RefCell;
-use std::ffi::OsString;
-use std::os::windows::prelude::{OsStrExt, OsStringExt};
-use std::path::PathBuf;
-use std::process::{Child, Command};
+use std::cell::{Cell, RefCell};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::thread;
 
 use nwd::NwgUi;
 use nwg::{
     AnimationTimer, CheckBox, CheckBoxState, EventData, Font, MessageChoice, MessageIcons,
-    MessageParams, NativeUi, RadioButton, Tab, TabsContainer, TextInput,
+    MessageParams, NativeUi, RadioButton, RadioButtonState, Tab, TabsContainer, TextInput,
+};
+use waifu2x_gui_core::animation::{self, AnimationFormat};
+use waifu2x_gui_core::device::{self, Device};
+use waifu2x_gui_core::gpu::{self, GpuInfo};
+use waifu2x_gui_core::input::{self, InputFile, InputList, InputStatus};
+use waifu2x_gui_core::naming::NameError;
+use waifu2x_gui_core::plan::{Collision, CollisionPolicy, Plan};
+use waifu2x_gui_core::queue::QueuedJob;
+use waifu2x_gui_core::staging::Strategy;
+use waifu2x_gui_core::video;
+use waifu2x_gui_core::{
+    Config, Engine, Exit, Format, JobQueue, JobState, PlanError, PresetStore, ProbeError,
+    QueueEvent, Settings, Waifu2xState,
 };
 
 const WHITE: Option<[u8; 3]> = Some([255, 255, 255]);
 
-#[derive(Clone, Debug, Default, PartialEq)]
-enum Format {
-    #[default]
-    Png,
-    Jpg,
-    Webp,
+// Indices of the tabs in `tabs`
+const PROCESSING_TAB: usize = 0;
+const OUTPUT_TAB: usize = 1;
+const JOBS_TAB: usize = 3;
+const PREVIEW_TAB: usize = 4;
+
+/// Work that ran on a worker thread because it starts other programs, which
+/// may take seconds to answer.
+enum Finished {
+    /// The executable [`Engine::discover`] found for the engine, if any.
+    Discovered(Engine, Option<PathBuf>),
+    /// Whether the executable the user picked for the engine is one.
+    Picked(Engine, PathBuf, Result<(), ProbeError>),
+    /// Whether the plan's executable is the engine, checked before running
+    /// the plan.
+    Checked(Engine, Plan, Result<(), ProbeError>),
+    /// The GPUs the engine reported.
+    Detected(Engine, Result<Vec<GpuInfo>, ProbeError>),
 }
 
 #[derive(Default, NwgUi)]
 pub struct Waifu2xApp {
-    #[nwg_control(size: (700, 430), title: "waifu2x-ncnn-vulkan")]
+    #[nwg_control(size: (700, 560), title: "waifu2x-ncnn-vulkan", accept_files: true)]
     #[nwg_events(
         OnInit: [Waifu2xApp::on_init],
         OnMinMaxInfo: [Waifu2xApp::on_minmax(SELF, EVT_DATA)],
+        OnFileDrop: [Waifu2xApp::on_file_drop(SELF, EVT_DATA)],
         OnWindowClose: [Waifu2xApp::on_quit]
     )]
     window: nwg::Window,
@@ -38,40 +64,102 @@ pub struct Waifu2xApp {
     #[nwg_layout(parent: window, spacing: 3)]
     grid: nwg::GridLayout,
 
-    #[nwg_control(text: "Input path:")]
-    #[nwg_layout_item(layout: grid, row: 0, col: 0, col_span: 2)]
-    input_label: nwg::Label,
-
-    #[nwg_control(text: "")]
-    #[nwg_layout_item(layout: grid, row: 0, col: 2, col_span: 12)]
-    input_path: nwg::TextInput,
+    #[nwg_control(
+        list_style: nwg::ListViewStyle::Detailed,
+        ex_flags: nwg::ListViewExFlags::FULL_ROW_SELECT | nwg::ListViewExFlags::GRID
+    )]
+    #[nwg_layout_item(layout: grid, row: 0, col: 0, col_span: 15, row_span: 4)]
+    input_list: nwg::ListView,
 
-    #[nwg_control(text: "...")]
+    #[nwg_control(text: "Add files...")]
     #[nwg_events(OnButtonClick: [Waifu2xApp::select_input_file])]
-    #[nwg_layout_item(layout: grid, row: 0, col: 14)]
+    #[nwg_layout_item(layout: grid, row: 4, col: 0, col_span: 2)]
     input_button: nwg::Button,
 
+    #[nwg_control(text: "Add folder...")]
+    #[nwg_events(OnButtonClick: [Waifu2xApp::add_input_folder])]
+    #[nwg_layout_item(layout: grid, row: 4, col: 2, col_span: 2)]
+    input_folder_button: nwg::Button,
+
+    #[nwg_control(text: "Remove")]
+    #[nwg_events(OnButtonClick: [Waifu2xApp::remove_inputs_clicked])]
+    #[nwg_layout_item(layout: grid, row: 4, col: 4, col_span: 2)]
+    remove_inputs_button: nwg::Button,
+
+    #[nwg_control(text: "Clear")]
+    #[nwg_events(OnButtonClick: [Waifu2xApp::clear_inputs_clicked])]
+    #[nwg_layout_item(layout: grid, row: 4, col: 6, col_span: 2)]
+    clear_inputs_button: nwg::Button,
+
+    #[nwg_control(text: "Move up")]
+    #[nwg_events(OnButtonClick: [Waifu2xApp::move_up_clicked])]
+    #[nwg_layout_item(layout: grid, row: 4, col: 8, col_span: 2)]
+    move_up_button: nwg::Button,
+
+    #[nwg_control(text: "Move down")]
+    #[nwg_events(OnButtonClick: [Waifu2xApp::move_down_clicked])]
+    #[nwg_layout_item(layout: grid, row: 4, col: 10, col_span: 2)]
+    move_down_button: nwg::Button,
+
+    #[nwg_control(text: "Open folder")]
+    #[nwg_events(OnButtonClick: [Waifu2xApp::open_folder_clicked])]
+    #[nwg_layout_item(layout: grid, row: 4, col: 12, col_span: 3)]
+    open_folder_button: nwg::Button,
+
     #[nwg_control(text: "Output path:")]
-    #[nwg_layout_item(layout: grid, row: 1, col: 0, col_span: 2)]
+    #[nwg_layout_item(layout: grid, row: 5, col: 0, col_span: 2)]
     output_label: nwg::Label,
 
     #[nwg_control(text: "")]
-    #[nwg_layout_item(layout: grid, row: 1, col: 2, col_span: 12)]
+    #[nwg_layout_item(layout: grid, row: 5, col: 2, col_span: 12)]
     output_path: nwg::TextInput,
 
     #[nwg_control(text: "...")]
-    #[nwg_layout_item(layout: grid, row: 1, col: 14)]
+    #[nwg_layout_item(layout: grid, row: 5, col: 14)]
     #[nwg_events(OnButtonClick: [Waifu2xApp::select_output_file])]
     output_button: nwg::Button,
 
     #[nwg_control(text: "Start")]
-    #[nwg_layout_item(layout: grid, col: 0, row: 2, row_span: 1, col_span: 15)]
+    #[nwg_layout_item(layout: grid, col: 0, row: 6, row_span: 1, col_span: 12)]
     #[nwg_events( OnButtonClick: [Waifu2xApp::start_clicked] )]
     start_button: nwg::Button,
 
+    #[nwg_control(text: "Preview")]
+    #[nwg_layout_item(layout: grid, col: 12, row: 6, col_span: 3)]
+    #[nwg_events(OnButtonClick: [Waifu2xApp::preview_clicked])]
+    preview_button: nwg::Button,
+
+    #[nwg_control(parent: window, text: "Preset:")]
+    #[nwg_layout_item(layout: grid, col: 0, row: 7, col_span: 2)]
+    preset_label: nwg::Label,
+
+    #[nwg_control(parent: window)]
+    #[nwg_layout_item(layout: grid, col: 2, row: 7, col_span: 5)]
+    #[nwg_events(OnComboxBoxSelection: [Waifu2xApp::preset_selected])]
+    preset_combo: nwg::ComboBox<String>,
+
+    #[nwg_control(parent: window, text: "", placeholder_text: Some("Preset name"))]
+    #[nwg_layout_item(layout: grid, col: 7, row: 7, col_span: 4)]
+    preset_name: nwg::TextInput,
+
+    #[nwg_control(parent: window, text: "Save")]
+    #[nwg_layout_item(layout: grid, col: 11, row: 7)]
+    #[nwg_events(OnButtonClick: [Waifu2xApp::save_preset_clicked])]
+    save_preset_button: nwg::Button,
+
+    #[nwg_control(parent: window, text: "Rename")]
+    #[nwg_layout_item(layout: grid, col: 12, row: 7, col_span: 2)]
+    #[nwg_events(OnButtonClick: [Waifu2xApp::rename_preset_clicked])]
+    rename_preset_button: nwg::Button,
+
+    #[nwg_control(parent: window, text: "Delete")]
+    #[nwg_layout_item(layout: grid, col: 14, row: 7)]
+    #[nwg_events(OnButtonClick: [Waifu2xApp::delete_preset_clicked])]
+    delete_preset_button: nwg::Button,
+
     // `tabs` begin here
-    #[nwg_control]
-    #[nwg_layout_item(layout: grid, col: 0, row: 3, row_span: 9, col_span: 15)]
+    #[nwg_control(parent: window)]
+    #[nwg_layout_item(layout: grid, col: 0, row: 8, row_span: 9, col_span: 15)]
     tabs: TabsContainer,
 
     // `tabs::processing_tab` begins here
@@ -81,8 +169,27 @@ pub struct Waifu2xApp {
     #[nwg_layout(parent: processing_tab, spacing: 2, margin: [1, 5, 1, 5])]
     tab_grid: nwg::GridLayout,
 
-    #[nwg_control(text: "Denoise Level", background_color: WHITE)]
+    #[nwg_control(text: "Engine", background_color: WHITE)]
     #[nwg_layout_item(layout: tab_grid, col: 0, row: 0, col_span: 2)]
+    engine_label: nwg::Label,
+
+    #[nwg_control(collection: Engine::ALL.to_vec(), selected_index: Some(0))]
+    #[nwg_layout_item(layout: tab_grid, col: 2, row: 0, col_span: 2)]
+    #[nwg_events(OnComboxBoxSelection: [Waifu2xApp::engine_selected])]
+    engine: nwg::ComboBox<Engine>,
+
+    #[nwg_control(text: "", placeholder_text: Some("Engine path (auto-detect)"), background_color: WHITE)]
+    #[nwg_layout_item(layout: tab_grid, col: 4, row: 0, col_span: 4)]
+    #[nwg_events(OnTextInput: [Waifu2xApp::engine_path_changed])]
+    engine_path: TextInput,
+
+    #[nwg_control(text: "...")]
+    #[nwg_layout_item(layout: tab_grid, col: 8, row: 0)]
+    #[nwg_events(OnButtonClick: [Waifu2xApp::select_engine_path])]
+    engine_path_button: nwg::Button,
+
+    #[nwg_control(text: "Denoise Level", background_color: WHITE)]
+    #[nwg_layout_item(layout: tab_grid, col: 0, row: 1, col_span: 2)]
     denoise_label: nwg::Label,
 
     #[nwg_control(
@@ -91,32 +198,32 @@ pub struct Waifu2xApp {
         flags: "VISIBLE|GROUP", 
         check_state: RadioButtonState::Checked
     )]
-    #[nwg_layout_item(layout: tab_grid, col: 2, row: 0, col_span: 1)]
+    #[nwg_layout_item(layout: tab_grid, col: 2, row: 1, col_span: 1)]
     #[nwg_events( OnButtonClick: [Waifu2xApp::denoise_clicked(SELF, CTRL)] )]
     denoise_disable: RadioButton,
 
     #[nwg_control(text: "Level 0", background_color: WHITE)]
-    #[nwg_layout_item(layout: tab_grid, col: 3, row: 0, col_span: 1)]
+    #[nwg_layout_item(layout: tab_grid, col: 3, row: 1, col_span: 1)]
     #[nwg_events( OnButtonClick: [Waifu2xApp::denoise_clicked(SELF, CTRL)] )]
     denoise_level0: RadioButton,
 
     #[nwg_control(text: "Level 1", background_color: WHITE)]
-    #[nwg_layout_item(layout: tab_grid, col: 4, row: 0, col_span: 1)]
+    #[nwg_layout_item(layout: tab_grid, col: 4, row: 1, col_span: 1)]
     #[nwg_events( OnButtonClick: [Waifu2xApp::denoise_clicked(SELF, CTRL)] )]
     denoise_level1: RadioButton,
 
     #[nwg_control(text: "Level 2", background_color: WHITE)]
-    #[nwg_layout_item(layout: tab_grid, col: 5, row: 0, col_span: 1)]
+    #[nwg_layout_item(layout: tab_grid, col: 5, row: 1, col_span: 1)]
     #[nwg_events( OnButtonClick: [Waifu2xApp::denoise_clicked(SELF, CTRL)] )]
     denoise_level2: RadioButton,
 
     #[nwg_control(text: "Level 3", background_color: WHITE)]
-    #[nwg_layout_item(layout: tab_grid, col: 6, row: 0, col_span: 1)]
+    #[nwg_layout_item(layout: tab_grid, col: 6, row: 1, col_span: 1)]
     #[nwg_events( OnButtonClick: [Waifu2xApp::denoise_clicked(SELF, CTRL)] )]
     denoise_level3: RadioButton,
 
     #[nwg_control(text: "Upscale Ratio", background_color: WHITE)]
-    #[nwg_layout_item(layout: tab_grid, col: 0, row: 1, col_span: 2)]
+    #[nwg_layout_item(layout: tab_grid, col: 0, row: 2, col_span: 2)]
     upscale_label: nwg::Label,
 
     #[nwg_control(
@@ -125,72 +232,114 @@ pub struct Waifu2xApp {
         flags: "VISIBLE|GROUP", 
         check_state: RadioButtonState::Checked
     )]
-    #[nwg_layout_item(layout: tab_grid, col: 2, row: 1, col_span: 1)]
+    #[nwg_layout_item(layout: tab_grid, col: 2, row: 2, col_span: 1)]
     #[nwg_events(OnButtonClick: [Waifu2xApp::upscale_clicked(SELF, CTRL)])]
     upscale_level1: RadioButton,
 
     #[nwg_control(text: "2x", background_color: WHITE)]
-    #[nwg_layout_item(layout: tab_grid, col: 3, row: 1, col_span: 1)]
+    #[nwg_layout_item(layout: tab_grid, col: 3, row: 2, col_span: 1)]
     #[nwg_events(OnButtonClick: [Waifu2xApp::upscale_clicked(SELF, CTRL)])]
     upscale_level2: RadioButton,
 
+    #[nwg_control(text: "3x", background_color: WHITE)]
+    #[nwg_layout_item(layout: tab_grid, col: 4, row: 2, col_span: 1)]
+    #[nwg_events(OnButtonClick: [Waifu2xApp::upscale_clicked(SELF, CTRL)])]
+    upscale_level3: RadioButton,
+
     #[nwg_control(text: "4x", background_color: WHITE)]
-    #[nwg_layout_item(layout: tab_grid, col: 4, row: 1, col_span: 1)]
+    #[nwg_layout_item(layout: tab_grid, col: 5, row: 2, col_span: 1)]
     #[nwg_events(OnButtonClick: [Waifu2xApp::upscale_clicked(SELF, CTRL)])]
     upscale_level4: RadioButton,
 
     #[nwg_control(text: "8x", background_color: WHITE)]
-    #[nwg_layout_item(layout: tab_grid, col: 5, row: 1, col_span: 1)]
+    #[nwg_layout_item(layout: tab_grid, col: 6, row: 2, col_span: 1)]
     #[nwg_events(OnButtonClick: [Waifu2xApp::upscale_clicked(SELF, CTRL)])]
     upscale_level8: RadioButton,
 
     #[nwg_control(text: "16x", background_color: WHITE)]
-    #[nwg_layout_item(layout: tab_grid, col: 6, row: 1, col_span: 1)]
+    #[nwg_layout_item(layout: tab_grid, col: 7, row: 2, col_span: 1)]
     #[nwg_events(OnButtonClick: [Waifu2xApp::upscale_clicked(SELF, CTRL)])]
     upscale_level16: RadioButton,
 
     #[nwg_control(text: "32x", background_color: WHITE)]
-    #[nwg_layout_item(layout: tab_grid, col: 7, row: 1, col_span: 1)]
+    #[nwg_layout_item(layout: tab_grid, col: 8, row: 2, col_span: 1)]
     #[nwg_events(OnButtonClick: [Waifu2xApp::upscale_clicked(SELF, CTRL)])]
     upscale_level32: RadioButton,
 
     #[nwg_control(text: "Enable TTA Mode (performance intensive)", background_color: WHITE)]
-    #[nwg_layout_item(layout: tab_grid, col: 0, row: 2, col_span: 5)]
+    #[nwg_layout_item(layout: tab_grid, col: 0, row: 3, col_span: 5)]
     #[nwg_events(OnButtonClick: [Waifu2xApp::tta_mode_clicked])]
     tta_mode: CheckBox,
 
     #[nwg_control(text: "Advanced Options", background_color: WHITE)]
-    #[nwg_layout_item(layout: tab_grid, col: 0, row: 3, col_span: 5)]
+    #[nwg_layout_item(layout: tab_grid, col: 0, row: 4, col_span: 5)]
     #[nwg_events(OnButtonClick: [Waifu2xApp::advanced_options_clicked])]
     advanced_options: CheckBox,
 
     #[nwg_control(text: "Thread Count", background_color: WHITE)]
-    #[nwg_layout_item(layout: tab_grid, col: 0, row: 4, col_span: 2)]
+    #[nwg_layout_item(layout: tab_grid, col: 0, row: 5, col_span: 2)]
     thread_label: nwg::Label,
 
-    #[nwg_control(text: "1:2:2", background_color: WHITE, readonly: true)]
-    #[nwg_layout_item(layout: tab_grid, col: 2, row: 4, col_span: 7)]
+    #[nwg_control(text: "", placeholder_text: Some("1:2:2"), background_color: WHITE, readonly: true)]
+    #[nwg_layout_item(layout: tab_grid, col: 2, row: 5, col_span: 5)]
     #[nwg_events(OnTextInput: [Waifu2xApp::thread_count_changed])]
     thread_count: TextInput,
 
     #[nwg_control(text: "GPU ID", background_color: WHITE)]
-    #[nwg_layout_item(layout: tab_grid, col: 0, row: 5, col_span: 2)]
+    #[nwg_layout_item(layout: tab_grid, col: 0, row: 6, col_span: 2)]
     gpu_id_label: nwg::Label,
 
-    #[nwg_control(text: "auto", background_color: WHITE, readonly: true)]
-    #[nwg_layout_item(layout: tab_grid, col: 2, row: 5, col_span: 7)]
+    #[nwg_control(text: "", placeholder_text: Some("auto"), background_color: WHITE, readonly: true)]
+    #[nwg_layout_item(layout: tab_grid, col: 2, row: 6, col_span: 5)]
     #[nwg_events(OnTextInput: [Waifu2xApp::gpu_id_changed])]
     gpu_id: TextInput,
 
     #[nwg_control(text: "Waifu2x Model", background_color: WHITE)]
-    #[nwg_layout_item(layout: tab_grid, col: 0, row: 6, col_span: 2)]
+    #[nwg_layout_item(layout: tab_grid, col: 0, row: 7, col_span: 2)]
     model_label: nwg::Label,
 
-    #[nwg_control(text: "models-cunet", background_color: WHITE, readonly: true)]
-    #[nwg_layout_item(layout: tab_grid, col: 2, row: 6, col_span: 7)]
+    #[nwg_control(text: "", placeholder_text: Some("models-cunet"), background_color: WHITE, readonly: true)]
+    #[nwg_layout_item(layout: tab_grid, col: 2, row: 7, col_span: 3)]
     #[nwg_events(OnTextInput: [Waifu2xApp::model_path_changed])]
     model_path: TextInput,
 
+    #[nwg_control(enabled: false)]
+    #[nwg_layout_item(layout: tab_grid, col: 5, row: 7, col_span: 2)]
+    #[nwg_events(OnComboxBoxSelection: [Waifu2xApp::model_selected])]
+    model_list: nwg::ComboBox<String>,
+
+    #[nwg_control(text: "Parallel Jobs", background_color: WHITE)]
+    #[nwg_layout_item(layout: tab_grid, col: 0, row: 8, col_span: 2)]
+    job_limit_label: nwg::Label,
+
+    #[nwg_control(text: "auto", background_color: WHITE, readonly: true)]
+    #[nwg_layout_item(layout: tab_grid, col: 2, row: 8, col_span: 5)]
+    #[nwg_events(OnTextInput: [Waifu2xApp::job_limit_changed])]
+    job_limit: TextInput,
+
+    #[nwg_control(text: "Tile Size", background_color: WHITE)]
+    #[nwg_layout_item(layout: tab_grid, col: 0, row: 9, col_span: 2)]
+    tile_size_label: nwg::Label,
+
+    #[nwg_control(text: "", placeholder_text: Some("auto"), background_color: WHITE, readonly: true)]
+    #[nwg_layout_item(layout: tab_grid, col: 2, row: 9, col_span: 5)]
+    #[nwg_events(OnTextInput: [Waifu2xApp::tile_size_changed])]
+    tile_size: TextInput,
+
+    #[nwg_control(text: "Detect GPUs", enabled: false)]
+    #[nwg_layout_item(layout: tab_grid, col: 7, row: 4, col_span: 2)]
+    #[nwg_events(OnButtonClick: [Waifu2xApp::detect_gpus_clicked])]
+    detect_gpus_button: nwg::Button,
+
+    #[nwg_control(flags: "VISIBLE | MULTI_SELECT", enabled: false)]
+    #[nwg_layout_item(layout: tab_grid, col: 7, row: 5, col_span: 2, row_span: 4)]
+    #[nwg_events(OnListBoxSelect: [Waifu2xApp::gpu_list_changed])]
+    gpu_list: nwg::ListBox<String>,
+
+    #[nwg_control(text: "", background_color: WHITE)]
+    #[nwg_layout_item(layout: tab_grid, col: 0, row: 10, col_span: 9)]
+    advanced_error_label: nwg::Label,
+
     // `tabs::processing_tab` ends here
     // `tabs::output_tab` begins here
     #[nwg_control(parent: tabs, text: "Output")]
@@ -229,18 +378,187 @@ pub struct Waifu2xApp {
     #[nwg_events(OnTextInput: [Waifu2xApp::filename_changed])]
     filename_format: TextInput,
 
-    #[nwg_control(text: "{name}, {scale}, {denoise}, {model} will be replaced with specific values.\nAn extension will be automatically appended to the filename.", background_color: WHITE)]
-    #[nwg_layout_item(layout: tab_grid, col: 0, row: 2, col_span: 8, row_span: 2)]
+    #[nwg_control(text: "", background_color: WHITE)]
+    #[nwg_layout_item(layout: tab_grid, col: 2, row: 2, col_span: 7)]
+    filename_error_label: nwg::Label,
+
+    #[nwg_control(text: "{name}, {ext}, {parent}, {index}, {scale}, {denoise}, {model}, {gpu}, {tta}, {date}, {width}, {height} will be replaced with specific values.\nFormats such as {index:04} or {denoise:none=off} are accepted; write {{ and }} for literal braces.\nAn extension will be automatically appended to the filename.", background_color: WHITE)]
+    #[nwg_layout_item(layout: tab_grid, col: 0, row: 3, col_span: 8, row_span: 2)]
     filename_advice_label: nwg::Label,
 
+    #[nwg_control(text: "Video Codec", background_color: WHITE)]
+    #[nwg_layout_item(layout: tab_grid, col: 0, row: 5, col_span: 2)]
+    video_codec_label: nwg::Label,
+
+    #[nwg_control(text: "libx264", background_color: WHITE)]
+    #[nwg_layout_item(layout: tab_grid, col: 2, row: 5, col_span: 2)]
+    #[nwg_events(OnTextInput: [Waifu2xApp::video_codec_changed])]
+    video_codec: TextInput,
+
+    #[nwg_control(text: "CRF", background_color: WHITE)]
+    #[nwg_layout_item(layout: tab_grid, col: 4, row: 5)]
+    video_crf_label: nwg::Label,
+
+    #[nwg_control(text: "18", background_color: WHITE)]
+    #[nwg_layout_item(layout: tab_grid, col: 5, row: 5)]
+    #[nwg_events(OnTextInput: [Waifu2xApp::video_crf_changed])]
+    video_crf: TextInput,
+
+    #[nwg_control(text: "ffmpeg", background_color: WHITE)]
+    #[nwg_layout_item(layout: tab_grid, col: 0, row: 6, col_span: 2)]
+    ffmpeg_label: nwg::Label,
+
+    #[nwg_control(text: "", placeholder_text: Some("ffmpeg path (auto-detect)"), background_color: WHITE)]
+    #[nwg_layout_item(layout: tab_grid, col: 2, row: 6, col_span: 6)]
+    #[nwg_events(OnTextInput: [Waifu2xApp::ffmpeg_path_changed])]
+    ffmpeg_path: TextInput,
+
+    #[nwg_control(text: "...")]
+    #[nwg_layout_item(layout: tab_grid, col: 8, row: 6)]
+    #[nwg_events(OnButtonClick: [Waifu2xApp::select_ffmpeg_path])]
+    ffmpeg_path_button: nwg::Button,
+
+    #[nwg_control(text: "Animations", background_color: WHITE)]
+    #[nwg_layout_item(layout: tab_grid, col: 0, row: 7, col_span: 2)]
+    animation_format_label: nwg::Label,
+
+    #[nwg_control(collection: animation_choices(), selected_index: Some(0))]
+    #[nwg_layout_item(layout: tab_grid, col: 2, row: 7, col_span: 2)]
+    #[nwg_events(OnComboxBoxSelection: [Waifu2xApp::animation_format_selected])]
+    animation_format: nwg::ComboBox<String>,
+
+    #[nwg_control(text: "Batching", background_color: WHITE)]
+    #[nwg_layout_item(layout: tab_grid, col: 0, row: 8, col_span: 2)]
+    batching_label: nwg::Label,
+
+    #[nwg_control(collection: vec![String::from("One process per file"), String::from("Directory mode")], selected_index: Some(0))]
+    #[nwg_layout_item(layout: tab_grid, col: 2, row: 8, col_span: 3)]
+    #[nwg_events(OnComboxBoxSelection: [Waifu2xApp::batching_selected])]
+    batching: nwg::ComboBox<String>,
+
+    #[nwg_control(text: "from", background_color: WHITE)]
+    #[nwg_layout_item(layout: tab_grid, col: 5, row: 8)]
+    batch_threshold_label: nwg::Label,
+
+    #[nwg_control(text: "8", background_color: WHITE, enabled: false)]
+    #[nwg_layout_item(layout: tab_grid, col: 6, row: 8)]
+    #[nwg_events(OnTextInput: [Waifu2xApp::batch_threshold_changed])]
+    batch_threshold: TextInput,
+
+    #[nwg_control(text: "files", background_color: WHITE)]
+    #[nwg_layout_item(layout: tab_grid, col: 7, row: 8)]
+    batch_threshold_unit_label: nwg::Label,
+
+    #[nwg_control(text: "If output exists", background_color: WHITE)]
+    #[nwg_layout_item(layout: tab_grid, col: 0, row: 9, col_span: 2)]
+    if_exists_label: nwg::Label,
+
+    #[nwg_control(collection: CollisionPolicy::ALL.to_vec(), selected_index: Some(0))]
+    #[nwg_layout_item(layout: tab_grid, col: 2, row: 9, col_span: 3)]
+    #[nwg_events(OnComboxBoxSelection: [Waifu2xApp::if_exists_selected])]
+    if_exists: nwg::ComboBox<CollisionPolicy>,
+
     // `tabs::output_tab` ends here
-    #[nwg_resource(
-        title: "Open File",
-        action: nwg::FileDialogAction::Open,
-        multiselect: true,
-        filters: "PNG(*.png)|JPEG(*.jpg;*.jpeg)|WebP(*.webp)|>Supported image files(*.png;*.jpg;*.jpeg;*.webp)"
+    // `tabs::folders_tab` begins here
+    #[nwg_control(parent: tabs, text: "Folders")]
+    folders_tab: Tab,
+
+    #[nwg_control(text: "Include subfolders", background_color: WHITE)]
+    #[nwg_layout_item(layout: tab_grid, col: 0, row: 0, col_span: 5)]
+    #[nwg_events(OnButtonClick: [Waifu2xApp::recursive_clicked])]
+    recursive: CheckBox,
+
+    #[nwg_control(text: "Include", background_color: WHITE)]
+    #[nwg_layout_item(layout: tab_grid, col: 0, row: 1, col_span: 2)]
+    include_label: nwg::Label,
+
+    #[nwg_control(text: "*.png;*.jpg;*.jpeg;*.webp;*.mp4;*.mkv;*.webm;*.avi;*.mov;*.gif;*.apng", background_color: WHITE)]
+    #[nwg_layout_item(layout: tab_grid, col: 2, row: 1, col_span: 7)]
+    #[nwg_events(OnTextInput: [Waifu2xApp::include_changed])]
+    include_patterns: TextInput,
+
+    #[nwg_control(text: "Exclude", background_color: WHITE)]
+    #[nwg_layout_item(layout: tab_grid, col: 0, row: 2, col_span: 2)]
+    exclude_label: nwg::Label,
+
+    #[nwg_control(text: "", background_color: WHITE)]
+    #[nwg_layout_item(layout: tab_grid, col: 2, row: 2, col_span: 7)]
+    #[nwg_events(OnTextInput: [Waifu2xApp::exclude_changed])]
+    exclude_patterns: TextInput,
+
+    #[nwg_control(text: "Recreate the folder structure under the output path", background_color: WHITE)]
+    #[nwg_layout_item(layout: tab_grid, col: 0, row: 3, col_span: 8)]
+    #[nwg_events(OnButtonClick: [Waifu2xApp::mirror_clicked])]
+    mirror_structure: CheckBox,
+
+    #[nwg_control(text: "Patterns are separated by ';', e.g. *.png;*.jpg. Excluded patterns also skip subfolders.", background_color: WHITE)]
+    #[nwg_layout_item(layout: tab_grid, col: 0, row: 4, col_span: 8)]
+    folder_advice_label: nwg::Label,
+
+    // `tabs::folders_tab` ends here
+    // `tabs::jobs_tab` begins here
+    #[nwg_control(parent: tabs, text: "Jobs")]
+    jobs_tab: Tab,
+
+    #[nwg_control(
+        list_style: nwg::ListViewStyle::Detailed,
+        ex_flags: nwg::ListViewExFlags::FULL_ROW_SELECT | nwg::ListViewExFlags::GRID
+    )]
+    #[nwg_layout_item(layout: tab_grid, col: 0, row: 0, col_span: 9, row_span: 7)]
+    job_list: nwg::ListView,
+
+    #[nwg_control(text: "Retry failed", enabled: false)]
+    #[nwg_layout_item(layout: tab_grid, col: 0, row: 7, col_span: 2)]
+    #[nwg_events(OnButtonClick: [Waifu2xApp::retry_failed_clicked])]
+    retry_button: nwg::Button,
+
+    #[nwg_control(text: "Copy log", enabled: false)]
+    #[nwg_layout_item(layout: tab_grid, col: 2, row: 7, col_span: 2)]
+    #[nwg_events(OnButtonClick: [Waifu2xApp::copy_log_clicked])]
+    copy_log_button: nwg::Button,
+
+    /// What happens to the outputs that already exist, for the running batch.
+    #[nwg_control(text: "", background_color: WHITE)]
+    #[nwg_layout_item(layout: tab_grid, col: 4, row: 7, col_span: 5)]
+    job_summary_label: nwg::Label,
+
+    // `tabs::jobs_tab` ends here
+    // `tabs::preview_tab` begins here
+    #[nwg_control(parent: tabs, text: "Preview")]
+    preview_tab: Tab,
+
+    #[nwg_control(
+        list_style: nwg::ListViewStyle::Detailed,
+        ex_flags: nwg::ListViewExFlags::FULL_ROW_SELECT | nwg::ListViewExFlags::GRID
     )]
-    open_file_dialog: nwg::FileDialog,
+    #[nwg_layout_item(layout: tab_grid, col: 0, row: 0, col_span: 9, row_span: 7)]
+    preview_list: nwg::ListView,
+
+    #[nwg_control(text: "Export CSV...", enabled: false)]
+    #[nwg_layout_item(layout: tab_grid, col: 0, row: 7, col_span: 2)]
+    #[nwg_events(OnButtonClick: [Waifu2xApp::export_plan_clicked])]
+    export_plan_button: nwg::Button,
+
+    #[nwg_control(text: "", background_color: WHITE)]
+    #[nwg_layout_item(layout: tab_grid, col: 2, row: 7, col_span: 7)]
+    preview_summary_label: nwg::Label,
+
+    // `tabs::preview_tab` ends here
+    // `tabs` end here
+    #[nwg_control(parent: window, range: 0..1000)]
+    #[nwg_layout_item(layout: grid, col: 0, row: 17, col_span: 15)]
+    progress_bar: nwg::ProgressBar,
+
+    #[nwg_control(parent: window, text: "&Presets")]
+    presets_menu: nwg::Menu,
+
+    #[nwg_control(parent: presets_menu, text: "&Import...")]
+    #[nwg_events(OnMenuItemSelected: [Waifu2xApp::import_presets_clicked])]
+    import_presets_item: nwg::MenuItem,
+
+    #[nwg_control(parent: presets_menu, text: "&Export...")]
+    #[nwg_events(OnMenuItemSelected: [Waifu2xApp::export_presets_clicked])]
+    export_presets_item: nwg::MenuItem,
 
     #[nwg_resource(
         title: "Save File",
@@ -248,224 +566,1429 @@ pub struct Waifu2xApp {
     )]
     save_file_dialog: nwg::FileDialog,
 
+    #[nwg_resource(
+        title: "Add Folder",
+        action: nwg::FileDialogAction::OpenDirectory
+    )]
+    open_folder_dialog: nwg::FileDialog,
+
+    #[nwg_resource(
+        title: "Select Engine",
+        action: nwg::FileDialogAction::Open,
+        filters: "Executable(*.exe)"
+    )]
+    engine_path_dialog: nwg::FileDialog,
+
+    #[nwg_resource(
+        title: "Select ffmpeg",
+        action: nwg::FileDialogAction::Open,
+        filters: "Executable(*.exe)"
+    )]
+    ffmpeg_path_dialog: nwg::FileDialog,
+
+    #[nwg_resource(
+        title: "Import Presets",
+        action: nwg::FileDialogAction::Open,
+        filters: "Preset pack(*.toml)"
+    )]
+    import_presets_dialog: nwg::FileDialog,
+
+    #[nwg_resource(
+        title: "Export Presets",
+        action: nwg::FileDialogAction::Save,
+        filters: "Preset pack(*.toml)"
+    )]
+    export_presets_dialog: nwg::FileDialog,
+
+    #[nwg_resource(
+        title: "Export Plan",
+        action: nwg::FileDialogAction::Save,
+        filters: "CSV(*.csv)"
+    )]
+    export_plan_dialog: nwg::FileDialog,
+
     #[nwg_control(parent: window, interval: std::time::Duration::from_millis(100))]
     #[nwg_events(OnTimerTick: [Waifu2xApp::timer_ticked])]
     timer: AnimationTimer,
 
+    #[nwg_control(parent: window)]
+    #[nwg_events(OnNotice: [Waifu2xApp::worker_finished])]
+    worker_notice: nwg::Notice,
+
     #[nwg_resource(family: "Segoe UI", size: 16)]
     advice_font: Font,
 
     state: RefCell<Waifu2xState>,
-}
-
-pub struct Waifu2xState {
-    selected_files: Vec<OsString>,
-    output_dir: OsString,
-    scale_level: i32,
-    denoise_level: i32,
-    tta_mode: bool,
-    format: Format,
-    thread_count: String,
-    gpu_id: String,
-    model_path: String,
-    filename_format: String,
-    children: Vec<Child>,
-}
-
-impl Default for Waifu2xState {
-    fn default() -> Self {
-        Self {
-            selected_files: Vec::new(),
-            output_dir: OsString::new(),
-            scale_level: 1,
-            denoise_level: -1,
-            tta_mode: false,
-            format: Format::Png,
-            thread_count: String::new(),
-            gpu_id: String::new(),
-            model_path: String::new(),
-            filename_format: String::from("{name}_{scale}x_{denoise}n"),
-            children: Vec::new(),
-        }
-    }
-}
-
-impl Waifu2xState {
-    fn set_denoise_level(&mut self, level: i32) {
-        self.denoise_level = level;
-    }
-
-    fn set_scale_level(&mut self, level: i32) {
-        self.scale_level = level;
-    }
+    /// Set when the settings file could not be read, so that it is left
+    /// alone instead of being overwritten with the defaults on exit.
+    keep_config: Cell<bool>,
+    presets: RefCell<PresetStore>,
+    /// Set when the preset file could neither be read nor moved aside, so
+    /// that it is not overwritten with the presets of this session.
+    keep_presets: Cell<bool>,
+    /// The device IDs of the entries in `gpu_list`.
+    gpu_ids: RefCell<Vec<i32>>,
+    /// The plan shown in `preview_list`, which Start runs unless it has
+    /// changed since.
+    preview: RefCell<Option<Plan>>,
+    /// What the worker threads finished, waiting for `worker_notice`.
+    finished: Arc<Mutex<Vec<Finished>>>,
 }
 
 impl Waifu2xApp {
     fn on_init(&self) {
         self.filename_advice_label.set_font(Some(&self.advice_font));
-    }
 
-    fn on_minmax(&self, data: &EventData) {
-        data.on_min_max().set_min_size(700, 450);
+        let columns = [
+            ("File", 260),
+            ("Device", 60),
+            ("Tile", 40),
+            ("Status", 70),
+            ("Progress", 60),
+            ("Message", 180),
+        ];
+        for (text, width) in columns {
+            self.job_list.insert_column(nwg::InsertListViewColumn {
+                index: None,
+                fmt: None,
+                width: Some(width),
+                text: Some(text.into()),
+            });
+        }
+        self.job_list.set_headers_enabled(true);
+
+        let columns = [
+            ("Input", 180),
+            ("Output", 180),
+            ("Command", 260),
+            ("If output exists", 100),
+            ("Warnings", 180),
+        ];
+        for (text, width) in columns {
+            self.preview_list.insert_column(nwg::InsertListViewColumn {
+                index: None,
+                fmt: None,
+                width: Some(width),
+                text: Some(text.into()),
+            });
+        }
+        self.preview_list.set_headers_enabled(true);
+
+        let columns = [
+            ("File", 420),
+            ("Dimensions", 90),
+            ("Size", 70),
+            ("Status", 80),
+        ];
+        for (text, width) in columns {
+            self.input_list.insert_column(nwg::InsertListViewColumn {
+                index: None,
+                fmt: None,
+                width: Some(width),
+                text: Some(text.into()),
+            });
+        }
+        self.input_list.set_headers_enabled(true);
+
+        self.load_config();
+        self.load_presets();
+        self.check_options(&self.state.borrow());
+        self.discover_engine();
     }
 
-    fn on_quit(&self) {
-        nwg::stop_thread_dispatch();
+    /// Runs `work` on a worker thread and hands its result to
+    /// `worker_finished`.
+    fn spawn_worker(&self, work: impl FnOnce() -> Finished + Send + 'static) {
+        let finished = Arc::clone(&self.finished);
+        let notice = self.worker_notice.sender();
+
+        thread::spawn(move || {
+            let result = work();
+            finished.lock().unwrap().push(result);
+            notice.notice();
+        });
     }
 
-    fn select_input_file(&self) {
-        if self.open_file_dialog.run(Some(&self.window)) {
-            self.input_path.set_text("");
-            if let Ok(paths) = self.open_file_dialog.get_selected_items() {
-                let viewable_paths = paths
-                    .iter()
-                    .take(10)
-                    .map(|p| p.to_string_lossy().into_owned())
-                    .collect::<Vec<_>>()
-                    .join(";");
-
-                self.state.borrow_mut().selected_files = paths.clone();
-                self.input_path.set_text(&viewable_paths);
+    fn worker_finished(&self) {
+        let finished = std::mem::take(&mut *self.finished.lock().unwrap());
+
+        for result in finished {
+            match result {
+                Finished::Discovered(engine, exe) => self.engine_discovered(engine, exe),
+                Finished::Picked(engine, path, result) => {
+                    self.engine_path_probed(engine, path, result)
+                }
+                Finished::Checked(engine, plan, result) => self.plan_checked(engine, plan, result),
+                Finished::Detected(engine, result) => self.gpus_detected(engine, result),
             }
         }
     }
 
-    fn denoise_clicked(&self, control: &RadioButton) {
-        let level = *control.text().as_bytes().last().unwrap();
+    /// Looks for the selected engine in the background, unless the user
+    /// picked its executable.
+    fn discover_engine(&self) {
+        let engine = {
+            let state = self.state.borrow();
+            if state.engine_paths.contains_key(&state.engine)
+                || state.discovered_engines.contains_key(&state.engine)
+            {
+                return;
+            }
+            state.engine
+        };
 
-        if level == b'e' {
-            // "None"
-            self.state.borrow_mut().set_denoise_level(-1);
-        } else {
-            self.state
-                .borrow_mut()
-                .set_denoise_level((level - b'0') as i32);
-        }
+        self.spawn_worker(move || Finished::Discovered(engine, engine.discover()));
     }
 
-    fn format_clicked(&self, control: &RadioButton) {
-        let format = match control.text().as_ref() {
-            "PNG" => Format::Png,
-            "JPG" => Format::Jpg,
-            "WebP" => Format::Webp,
-            _ => unreachable!("invalid format detected"),
+    fn engine_discovered(&self, engine: Engine, exe: Option<PathBuf>) {
+        let Some(exe) = exe else {
+            return;
         };
 
-        self.state.borrow_mut().format = format;
-    }
+        let mut state = self.state.borrow_mut();
+        state.discovered_engines.insert(engine, exe);
+        let current = state.engine == engine;
+        drop(state);
 
-    fn upscale_clicked(&self, control: &RadioButton) {
-        let text = control.text();
-        let level = text.trim_end_matches('x').parse::<i32>().unwrap();
-        self.state.borrow_mut().set_scale_level(level);
+        // The models are listed from the folder of the executable
+        if current {
+            self.sync_model_list();
+            self.check_options(&self.state.borrow());
+        }
     }
 
-    fn tta_mode_clicked(&self) {
-        self.state.borrow_mut().tta_mode = self.tta_mode.check_state() == CheckBoxState::Checked;
-    }
+    /// Disables the levels the engine and model cannot handle, marks the
+    /// advanced options that are invalid, and disables Start until they and
+    /// the output name are fixed.
+    fn check_options(&self, state: &Waifu2xState) {
+        // Without a model folder to inspect, leave the choice to the engine
+        let model = state.model();
+        let supports =
+            |scale: i32, denoise: i32| model.as_ref().is_none_or(|m| m.supports(scale, denoise));
+
+        let denoise = [
+            (&self.denoise_disable, -1),
+            (&self.denoise_level0, 0),
+            (&self.denoise_level1, 1),
+            (&self.denoise_level2, 2),
+            (&self.denoise_level3, 3),
+        ];
+        for (radio, level) in denoise {
+            radio.set_enabled(
+                state.engine.supports_denoise(level) && supports(state.scale_level, level),
+            );
+        }
 
-    fn advanced_options_clicked(&self) {
-        let advanced = self.advanced_options.check_state() == CheckBoxState::Checked;
+        let upscale = [
+            (&self.upscale_level1, 1),
+            (&self.upscale_level2, 2),
+            (&self.upscale_level3, 3),
+            (&self.upscale_level4, 4),
+            (&self.upscale_level8, 8),
+            (&self.upscale_level16, 16),
+            (&self.upscale_level32, 32),
+        ];
+        for (radio, level) in upscale {
+            radio.set_enabled(
+                state.engine.supports_scale(&state.model_path, level)
+                    && supports(level, state.denoise_level),
+            );
+        }
 
-        self.gpu_id.set_readonly(!advanced);
-        self.thread_count.set_readonly(!advanced);
-        self.model_path.set_readonly(!advanced);
-    }
+        let errors = state.field_errors();
 
-    fn select_output_file(&self) {
-        if self.save_file_dialog.run(Some(&self.window)) {
-            self.output_path.set_text("");
-            if let Ok(path) = self.save_file_dialog.get_selected_item() {
-                self.state.borrow_mut().output_dir = path.clone();
-                self.output_path.set_text(path.to_string_lossy().as_ref());
+        let mark = |label: &nwg::Label, text: &str, error: &Option<String>| {
+            if error.is_some() {
+                label.set_text(&format!("{} (!)", text));
+            } else {
+                label.set_text(text);
             }
+        };
+
+        mark(&self.thread_label, "Thread Count", &errors.threads);
+        mark(&self.gpu_id_label, "GPU ID", &errors.gpu_id);
+        mark(&self.tile_size_label, "Tile Size", &errors.tile_size);
+        mark(&self.model_label, "Waifu2x Model", &errors.model);
+        self.advanced_error_label
+            .set_text(&errors.messages().join("; "));
+
+        // While processing, the button stops the batch instead
+        if state.queue.is_finished() {
+            self.start_button
+                .set_enabled(errors.is_empty() && state.template().is_ok());
         }
     }
 
-    fn thread_count_changed(&self) {
-        self.state.borrow_mut().thread_count = self.thread_count.text();
-    }
+    /// Restores the settings of the previous session.
+    fn load_config(&self) {
+        let Some(path) = Config::default_path() else {
+            return;
+        };
 
-    fn gpu_id_changed(&self) {
-        self.state.borrow_mut().gpu_id = self.gpu_id.text();
+        match Config::load(&path) {
+            Ok(config) => {
+                config.apply_to(&mut self.state.borrow_mut());
+                self.sync_widgets();
+            }
+            Err(e) => {
+                self.keep_config.set(true);
+                nwg::modal_error_message(
+                    &self.window,
+                    "Error",
+                    &format!(
+                        "{}\n\nThe default settings will be used instead, and changes to them will not be saved over {}.",
+                        e,
+                        path.display()
+                    ),
+                );
+            }
+        }
     }
 
-    fn model_path_changed(&self) {
-        self.state.borrow_mut().model_path = self.model_path.text();
-    }
+    fn save_config(&self) {
+        if self.keep_config.get() {
+            return;
+        }
 
-    fn filename_changed(&self) {
-        self.state.borrow_mut().filename_format = self.filename_format.text();
+        let Some(path) = Config::default_path() else {
+            return;
+        };
+
+        let config = Config::from_state(&self.state.borrow());
+
+        if let Err(e) = config.save(&path) {
+            nwg::modal_error_message(&self.window, "Error", &e.to_string());
+        }
     }
 
-    fn timer_ticked(&self) {
-        // It is possible for the timer tick event to fire while an error
-        // message is being shown in the following while loop, which leads to
-        // a panic 
-        let state = &mut *match self.state.try_borrow_mut() {
-            Ok(x) => x,
-            Err(_) => return,
+    /// Makes every widget reflect the settings in the state.
+    fn sync_widgets(&self) {
+        // Setting the text of an input fires its change handler, which borrows
+        // the state mutably, so work on a copy of the settings
+        let (settings, output_dir, engine_path, ffmpeg_path) = {
+            let state = self.state.borrow();
+            (
+                Settings::from_state(&state),
+                PathBuf::from(&state.output_dir),
+                state.engine_paths.get(&state.engine).cloned(),
+                state.ffmpeg_path.clone(),
+            )
         };
 
-        let mut i = 0;
-        while i < state.children.len() {
-            let c = &mut state.children[i];
+        let check = |radio: &RadioButton, checked: bool| {
+            radio.set_check_state(if checked {
+                RadioButtonState::Checked
+            } else {
+                RadioButtonState::Unchecked
+            });
+        };
 
-            let should_remove = match c.try_wait() {
-                Ok(None) => false,
-                Ok(Some(status)) => {
-                    if !status.success() {
-                        self.start_button.set_text("Processing... (error occured!)");
-                        true
-                    } else {
-                        true
-                    }
-                }
-                Err(e) => {
-                    nwg::modal_error_message(
-                        &self.window,
-                        "Error",
-                        &format!("Unexpected error occured while running Waifu2x: {}", e),
-                    );
-                    true
-                }
-            };
+        let denoise = [
+            (&self.denoise_disable, -1),
+            (&self.denoise_level0, 0),
+            (&self.denoise_level1, 1),
+            (&self.denoise_level2, 2),
+            (&self.denoise_level3, 3),
+        ];
+        for (radio, level) in denoise {
+            check(radio, settings.denoise_level == level);
+        }
 
-            if should_remove {
-                state.children.remove(i);
-            } else {
-                i += 1;
-            }
+        let upscale = [
+            (&self.upscale_level1, 1),
+            (&self.upscale_level2, 2),
+            (&self.upscale_level3, 3),
+            (&self.upscale_level4, 4),
+            (&self.upscale_level8, 8),
+            (&self.upscale_level16, 16),
+            (&self.upscale_level32, 32),
+        ];
+        for (radio, level) in upscale {
+            check(radio, settings.scale_level == level);
         }
 
-        if state.children.is_empty() {
-            self.timer.stop();
-            if self.start_button.text().contains("error") {
-                self.start_button.set_text("Start (error occured!)");
+        let engine = Engine::ALL.iter().position(|e| *e == settings.engine);
+        self.engine.set_selection(engine);
+        self.engine_path.set_text(
+            &engine_path
+                .map(|p| p.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        );
+
+        check(&self.format_png, settings.format == Format::Png);
+        check(&self.format_jpg, settings.format == Format::Jpg);
+        check(&self.format_webp, settings.format == Format::Webp);
+
+        let tick = |checkbox: &CheckBox, checked: bool| {
+            checkbox.set_check_state(if checked {
+                CheckBoxState::Checked
             } else {
-                self.start_button.set_text("Start")
-            }
-            self.start_button.set_enabled(true);
-        }
-    }
+                CheckBoxState::Unchecked
+            });
+        };
 
-    fn start_clicked(&self) {
-        let mut state = self.state.borrow_mut();
-        let mut children = Vec::new();
+        tick(&self.tta_mode, settings.tta_mode);
+        tick(&self.recursive, settings.recursive);
+        tick(&self.mirror_structure, settings.mirror_structure);
+
+        // Empty values mean "let waifu2x decide", so they stay empty and the
+        // placeholder texts show what the engine picks
+        self.thread_count.set_text(&settings.thread_count);
+        self.gpu_id.set_text(&settings.gpu_id);
+        self.tile_size.set_text(&settings.tile_size);
+        self.model_path.set_text(&settings.model_path);
+        self.model_path
+            .set_placeholder_text(Some(settings.engine.default_model()));
+        self.job_limit.set_text(&match settings.job_limit {
+            0 => String::from("auto"),
+            n => n.to_string(),
+        });
+        self.filename_format.set_text(&settings.filename_format);
+        self.video_codec.set_text(&settings.video.codec);
+        self.video_crf.set_text(&settings.video.crf.to_string());
+        self.ffmpeg_path.set_text(&ffmpeg_path.to_string_lossy());
+        // The formats come after "Same as input"
+        let animation = settings
+            .animation_format
+            .and_then(|format| AnimationFormat::ALL.iter().position(|f| *f == format))
+            .map_or(0, |i| i + 1);
+        self.animation_format.set_selection(Some(animation));
+
+        let directory = settings.batching.strategy == Strategy::Directory;
+        self.batching.set_selection(Some(usize::from(directory)));
+        self.batch_threshold
+            .set_text(&settings.batching.threshold.to_string());
+        self.batch_threshold.set_enabled(directory);
+
+        let if_exists = CollisionPolicy::ALL
+            .iter()
+            .position(|p| *p == settings.if_exists);
+        self.if_exists.set_selection(if_exists);
+        self.include_patterns.set_text(&settings.include_patterns);
+        self.exclude_patterns.set_text(&settings.exclude_patterns);
+        self.output_path
+            .set_text(output_dir.to_string_lossy().trim_start_matches("\\\\?\\"));
+
+        self.sync_model_list();
+        self.check_options(&self.state.borrow());
+    }
 
-        if state.selected_files.is_empty() {
+    fn load_presets(&self) {
+        let Some(path) = PresetStore::default_path() else {
             return;
+        };
+
+        match PresetStore::load(&path) {
+            Ok(presets) => *self.presets.borrow_mut() = presets,
+            Err(e) => {
+                // Keep the unreadable pack rather than saving over it
+                let backup = path.with_extension("toml.bak");
+                let outcome = match std::fs::rename(&path, &backup) {
+                    Ok(()) => format!("The file was moved to {}.", backup.display()),
+                    Err(_) => {
+                        self.keep_presets.set(true);
+                        String::from("Changes to presets will not be saved.")
+                    }
+                };
+
+                nwg::modal_error_message(
+                    &self.window,
+                    "Error",
+                    &format!("Unable to load the presets.\n\n{}\n\n{}", e, outcome),
+                );
+            }
         }
 
-        if state.denoise_level == -1 && state.scale_level == 1 {
-            nwg::modal_info_message(
+        self.refresh_presets(None);
+    }
+
+    /// Writes the presets back to disk and refreshes the dropdown, selecting
+    /// `selected` if given.
+    fn store_presets(&self, selected: Option<&str>) {
+        if self.keep_presets.get() {
+            nwg::modal_error_message(
                 &self.window,
                 "Error",
-                "A denoise level and/or upscale ratio is not selected.",
+                "The preset file could not be read at startup, so changes to presets are not saved.",
             );
+        } else if let Some(path) = PresetStore::default_path() {
+            if let Err(e) = self.presets.borrow().save(&path) {
+                nwg::modal_error_message(&self.window, "Error", &e.to_string());
+            }
+        }
+
+        self.refresh_presets(selected);
+    }
+
+    fn refresh_presets(&self, selected: Option<&str>) {
+        let names = self.presets.borrow().names();
+        let index = selected.and_then(|s| names.iter().position(|n| n == s));
+
+        self.preset_combo.set_collection(names);
+        self.preset_combo.set_selection(index);
+        self.preset_name.set_text(selected.unwrap_or(""));
+    }
+
+    fn preset_selected(&self) {
+        let Some(name) = self.preset_combo.selection_string() else {
+            return;
+        };
+
+        let settings = match self.presets.borrow().get(&name) {
+            Some(preset) => preset.settings.clone(),
+            None => return,
+        };
+
+        settings.apply_to(&mut self.state.borrow_mut());
+        self.sync_widgets();
+        self.preset_name.set_text(&name);
+    }
+
+    fn save_preset_clicked(&self) {
+        let name = self.preset_name.text();
+        let settings = Settings::from_state(&self.state.borrow());
+
+        let result = self.presets.borrow_mut().insert(&name, settings);
+        match result {
+            Ok(()) => self.store_presets(Some(name.trim())),
+            Err(e) => nwg::modal_info_message(&self.window, "Error", &e.to_string()),
+        }
+    }
+
+    fn rename_preset_clicked(&self) {
+        let Some(from) = self.preset_combo.selection_string() else {
+            return;
+        };
+        let to = self.preset_name.text();
+
+        let result = self.presets.borrow_mut().rename(&from, &to);
+        match result {
+            Ok(()) => self.store_presets(Some(to.trim())),
+            Err(e) => nwg::modal_info_message(&self.window, "Error", &e.to_string()),
+        }
+    }
+
+    fn delete_preset_clicked(&self) {
+        let Some(name) = self.preset_combo.selection_string() else {
+            return;
+        };
+
+        let params = MessageParams {
+            title: "Delete preset",
+            content: &format!("Do you really want to delete the preset \"{}\"?", name),
+            buttons: nwg::MessageButtons::YesNo,
+            icons: MessageIcons::Question,
+        };
+
+        if nwg::modal_message(&self.window, &params) != MessageChoice::Yes {
+            return;
+        }
+
+        let result = self.presets.borrow_mut().remove(&name);
+        match result {
+            Ok(_) => self.store_presets(None),
+            Err(e) => nwg::modal_info_message(&self.window, "Error", &e.to_string()),
+        }
+    }
+
+    fn import_presets_clicked(&self) {
+        if !self.import_presets_dialog.run(Some(&self.window)) {
+            return;
+        }
+
+        let Ok(path) = self.import_presets_dialog.get_selected_item() else {
+            return;
+        };
+
+        let result = self.presets.borrow_mut().import(Path::new(&path));
+        match result {
+            Ok(count) => {
+                self.store_presets(None);
+                nwg::modal_info_message(
+                    &self.window,
+                    "Presets imported",
+                    &format!("Imported {} preset(s).", count),
+                );
+            }
+            Err(e) => nwg::modal_error_message(&self.window, "Error", &e.to_string()),
+        }
+    }
+
+    fn export_presets_clicked(&self) {
+        if !self.export_presets_dialog.run(Some(&self.window)) {
+            return;
+        }
+
+        let Ok(path) = self.export_presets_dialog.get_selected_item() else {
+            return;
+        };
+
+        let mut path = PathBuf::from(path);
+        if path.extension().is_none() {
+            path.set_extension("toml");
+        }
+
+        if let Err(e) = self.presets.borrow().export(&path) {
+            nwg::modal_error_message(&self.window, "Error", &e.to_string());
+        }
+    }
+
+    fn on_minmax(&self, data: &EventData) {
+        data.on_min_max().set_min_size(700, 580);
+    }
+
+    fn on_quit(&self) {
+        self.state.borrow_mut().queue.cancel();
+        self.save_config();
+        nwg::stop_thread_dispatch();
+    }
+
+    fn on_file_drop(&self, data: &EventData) {
+        let paths = data
+            .on_file_drop()
+            .files()
+            .into_iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>();
+
+        let summary = self.state.borrow_mut().add_paths(&paths);
+        self.populate_input_list(&self.state.borrow().inputs);
+
+        let mut message = format!("{}.", summary);
+        for folder in summary.unreadable.iter() {
+            message.push_str(&format!("\nUnable to read {}", folder.display()));
+        }
+
+        nwg::modal_info_message(&self.window, "Files added", &message);
+    }
+
+    fn set_input_cell(&self, index: usize, column: i32, text: String) {
+        self.input_list.update_item(
+            index,
+            nwg::InsertListViewItem {
+                index: Some(index as i32),
+                column_index: column,
+                text: Some(text),
+                image: None,
+            },
+        );
+    }
+
+    fn update_input_row(&self, index: usize, file: &InputFile) {
+        let dimensions = file
+            .dimensions
+            .map(|(width, height)| format!("{}x{}", width, height));
+
+        self.set_input_cell(index, 1, dimensions.unwrap_or_default());
+        self.set_input_cell(index, 2, file.size.map(format_size).unwrap_or_default());
+        self.set_input_cell(index, 3, file.status.to_string());
+    }
+
+    fn populate_input_list(&self, inputs: &InputList) {
+        self.input_list.clear();
+
+        for (index, file) in inputs.files().iter().enumerate() {
+            self.input_list.insert_item(nwg::InsertListViewItem {
+                index: Some(index as i32),
+                column_index: 0,
+                text: Some(file.path.to_string_lossy().into_owned()),
+                image: None,
+            });
+            self.update_input_row(index, file);
+        }
+    }
+
+    /// Shows the state of `job` next to its input.
+    fn update_input_status(&self, inputs: &mut InputList, job: &QueuedJob) {
+        if let Some(index) = inputs.set_status(&job.job.input, InputStatus::Job(job.state)) {
+            self.set_input_cell(index, 3, job.state.to_string());
+        }
+    }
+
+    fn select_input_file(&self) {
+        // Built here because its filters are not a literal
+        let mut dialog = nwg::FileDialog::default();
+        let built = nwg::FileDialog::builder()
+            .title("Open File")
+            .action(nwg::FileDialogAction::Open)
+            .multiselect(true)
+            .filters(&input_filters())
+            .build(&mut dialog);
+
+        if built.is_err() || !dialog.run(Some(&self.window)) {
+            return;
+        }
+
+        let Ok(paths) = dialog.get_selected_items() else {
+            return;
+        };
+
+        let paths = paths.into_iter().map(PathBuf::from).collect::<Vec<_>>();
+        let summary = self.state.borrow_mut().add_paths(&paths);
+        self.populate_input_list(&self.state.borrow().inputs);
+
+        if summary.added < paths.len() {
+            nwg::modal_info_message(&self.window, "Files added", &format!("{}.", summary));
+        }
+    }
+
+    fn add_input_folder(&self) {
+        if !self.open_folder_dialog.run(Some(&self.window)) {
             return;
         }
 
+        let Ok(folder) = self.open_folder_dialog.get_selected_item() else {
+            return;
+        };
+
+        let summary = self.state.borrow_mut().add_paths(&[PathBuf::from(&folder)]);
+
+        if !summary.unreadable.is_empty() {
+            nwg::modal_error_message(
+                &self.window,
+                "Error",
+                &format!("Unable to read {}", folder.to_string_lossy()),
+            );
+            return;
+        }
+
+        self.populate_input_list(&self.state.borrow().inputs);
+        nwg::modal_info_message(&self.window, "Folder added", &format!("{}.", summary));
+    }
+
+    fn remove_inputs_clicked(&self) {
+        let selected = self.input_list.selected_items();
+
+        let mut state = self.state.borrow_mut();
+        state.inputs.remove(&selected);
+        self.populate_input_list(&state.inputs);
+    }
+
+    fn clear_inputs_clicked(&self) {
+        let mut state = self.state.borrow_mut();
+        state.clear_inputs();
+        self.populate_input_list(&state.inputs);
+    }
+
+    fn move_up_clicked(&self) {
+        let selected = self.input_list.selected_items();
+
+        let mut state = self.state.borrow_mut();
+        let moved = state.inputs.move_up(&selected);
+        self.populate_input_list(&state.inputs);
+        self.select_inputs(&moved);
+    }
+
+    fn move_down_clicked(&self) {
+        let selected = self.input_list.selected_items();
+
+        let mut state = self.state.borrow_mut();
+        let moved = state.inputs.move_down(&selected);
+        self.populate_input_list(&state.inputs);
+        self.select_inputs(&moved);
+    }
+
+    fn select_inputs(&self, indices: &[usize]) {
+        for &index in indices {
+            self.input_list.select_item(index, true);
+        }
+        self.input_list.set_focus();
+    }
+
+    /// Shows the first selected input in Explorer.
+    fn open_folder_clicked(&self) {
+        let Some(index) = self.input_list.selected_item() else {
+            return;
+        };
+
+        let path = match self.state.borrow().inputs.files().get(index) {
+            Some(file) => file.path.clone(),
+            None => return,
+        };
+
+        let result = Command::new("explorer").arg("/select,").arg(&path).spawn();
+
+        if let Err(e) = result {
+            nwg::modal_error_message(
+                &self.window,
+                "Error",
+                &format!("Unable to open the folder of {}:\n{}", path.display(), e),
+            );
+        }
+    }
+
+    fn recursive_clicked(&self) {
+        self.state.borrow_mut().recursive = self.recursive.check_state() == CheckBoxState::Checked;
+    }
+
+    fn mirror_clicked(&self) {
+        self.state.borrow_mut().mirror_structure =
+            self.mirror_structure.check_state() == CheckBoxState::Checked;
+    }
+
+    fn include_changed(&self) {
+        self.state.borrow_mut().include_patterns = self.include_patterns.text();
+    }
+
+    fn exclude_changed(&self) {
+        self.state.borrow_mut().exclude_patterns = self.exclude_patterns.text();
+    }
+
+    fn engine_selected(&self) {
+        let Some(engine) = self.engine.selection().map(|i| Engine::ALL[i]) else {
+            return;
+        };
+
+        self.state.borrow_mut().set_engine(engine);
+        self.clear_gpu_list();
+        self.sync_widgets();
+        self.discover_engine();
+    }
+
+    fn engine_path_changed(&self) {
+        let path = self.engine_path.text();
+        let mut state = self.state.borrow_mut();
+        let engine = state.engine;
+
+        if path.trim().is_empty() {
+            state.engine_paths.remove(&engine);
+        } else {
+            state
+                .engine_paths
+                .insert(engine, PathBuf::from(path.trim()));
+        }
+
+        drop(state);
+        self.sync_model_list();
+        self.check_options(&self.state.borrow());
+    }
+
+    fn select_engine_path(&self) {
+        if !self.engine_path_dialog.run(Some(&self.window)) {
+            return;
+        }
+
+        let Ok(path) = self.engine_path_dialog.get_selected_item() else {
+            return;
+        };
+
+        let engine = self.state.borrow().engine;
+        let path = PathBuf::from(path);
+        self.spawn_worker(move || {
+            let result = engine.probe(&path);
+            Finished::Picked(engine, path, result)
+        });
+    }
+
+    fn engine_path_probed(&self, engine: Engine, path: PathBuf, result: Result<(), ProbeError>) {
+        if let Err(e) = result {
+            nwg::modal_error_message(&self.window, "Error", &e.to_string());
+            return;
+        }
+
+        // The user may have switched engines meanwhile
+        if self.state.borrow().engine == engine {
+            self.engine_path.set_text(&path.to_string_lossy());
+        }
+    }
+
+    fn video_codec_changed(&self) {
+        self.state.borrow_mut().video.codec = self.video_codec.text().trim().to_owned();
+    }
+
+    fn video_crf_changed(&self) {
+        // Keep the last valid value while a number is being typed
+        if let Ok(crf) = self.video_crf.text().trim().parse() {
+            self.state.borrow_mut().video.crf = crf;
+        }
+    }
+
+    fn ffmpeg_path_changed(&self) {
+        self.state.borrow_mut().ffmpeg_path = PathBuf::from(self.ffmpeg_path.text().trim());
+    }
+
+    fn animation_format_selected(&self) {
+        // The first choice keeps the input's container
+        let format = match self.animation_format.selection() {
+            Some(0) | None => None,
+            Some(i) => AnimationFormat::ALL.get(i - 1).copied(),
+        };
+
+        self.state.borrow_mut().animation_format = format;
+    }
+
+    fn if_exists_selected(&self) {
+        if let Some(policy) = self.if_exists.selection().map(|i| CollisionPolicy::ALL[i]) {
+            self.state.borrow_mut().if_exists = policy;
+        }
+    }
+
+    fn batching_selected(&self) {
+        let strategy = match self.batching.selection() {
+            Some(0) => Strategy::PerFile,
+            _ => Strategy::Directory,
+        };
+
+        self.batch_threshold
+            .set_enabled(strategy == Strategy::Directory);
+        self.state.borrow_mut().batching.strategy = strategy;
+    }
+
+    fn batch_threshold_changed(&self) {
+        // Invalid text keeps the last valid threshold
+        if let Ok(threshold) = self.batch_threshold.text().trim().parse() {
+            self.state.borrow_mut().batching.threshold = threshold;
+        }
+    }
+
+    fn select_ffmpeg_path(&self) {
+        if !self.ffmpeg_path_dialog.run(Some(&self.window)) {
+            return;
+        }
+
+        if let Ok(path) = self.ffmpeg_path_dialog.get_selected_item() {
+            self.ffmpeg_path.set_text(&path.to_string_lossy());
+        }
+    }
+
+    fn denoise_clicked(&self, control: &RadioButton) {
+        let level = *control.text().as_bytes().last().unwrap();
+
+        if level == b'e' {
+            // "None"
+            self.state.borrow_mut().set_denoise_level(-1);
+        } else {
+            self.state
+                .borrow_mut()
+                .set_denoise_level((level - b'0') as i32);
+        }
+
+        self.check_options(&self.state.borrow());
+    }
+
+    fn format_clicked(&self, control: &RadioButton) {
+        let format = match control.text().as_ref() {
+            "PNG" => Format::Png,
+            "JPG" => Format::Jpg,
+            "WebP" => Format::Webp,
+            _ => unreachable!("invalid format detected"),
+        };
+
+        self.state.borrow_mut().format = format;
+    }
+
+    fn upscale_clicked(&self, control: &RadioButton) {
+        let text = control.text();
+        let level = text.trim_end_matches('x').parse::<i32>().unwrap();
+        self.state.borrow_mut().set_scale_level(level);
+        self.check_options(&self.state.borrow());
+    }
+
+    fn tta_mode_clicked(&self) {
+        self.state.borrow_mut().tta_mode = self.tta_mode.check_state() == CheckBoxState::Checked;
+    }
+
+    fn advanced_options_clicked(&self) {
+        let advanced = self.advanced_options.check_state() == CheckBoxState::Checked;
+
+        self.gpu_id.set_readonly(!advanced);
+        self.thread_count.set_readonly(!advanced);
+        self.model_path.set_readonly(!advanced);
+        self.model_list.set_enabled(advanced);
+        self.job_limit.set_readonly(!advanced);
+        self.tile_size.set_readonly(!advanced);
+        self.detect_gpus_button.set_enabled(advanced);
+        self.gpu_list.set_enabled(advanced);
+    }
+
+    fn select_output_file(&self) {
+        if self.save_file_dialog.run(Some(&self.window)) {
+            self.output_path.set_text("");
+            if let Ok(path) = self.save_file_dialog.get_selected_item() {
+                self.state.borrow_mut().output_dir = path.clone();
+                self.output_path.set_text(path.to_string_lossy().as_ref());
+            }
+        }
+    }
+
+    fn thread_count_changed(&self) {
+        self.state.borrow_mut().thread_count = self.thread_count.text();
+        self.check_options(&self.state.borrow());
+    }
+
+    fn gpu_id_changed(&self) {
+        self.state.borrow_mut().gpu_id = self.gpu_id.text();
+        self.sync_gpu_list();
+        self.check_options(&self.state.borrow());
+    }
+
+    /// Selects the devices listed in the GPU ID field in the device list.
+    fn sync_gpu_list(&self) {
+        let devices = Device::parse_list(&self.gpu_id.text()).unwrap_or_default();
+
+        self.gpu_list.unselect_all();
+        for (index, id) in self.gpu_ids.borrow().iter().enumerate() {
+            if devices.iter().any(|d| d.id == *id) {
+                self.gpu_list.multi_add_selection(index);
+            }
+        }
+    }
+
+    fn gpu_list_changed(&self) {
+        let ids = {
+            let gpu_ids = self.gpu_ids.borrow();
+            self.gpu_list
+                .multi_selection()
+                .into_iter()
+                .filter_map(|i| gpu_ids.get(i).copied())
+                .collect::<Vec<_>>()
+        };
+
+        self.gpu_id
+            .set_text(&device::select_ids(&self.gpu_id.text(), &ids));
+    }
+
+    fn clear_gpu_list(&self) {
+        self.gpu_ids.borrow_mut().clear();
+        self.gpu_list.set_collection(Vec::new());
+    }
+
+    fn detect_gpus_clicked(&self) {
+        let (engine, exe) = {
+            let state = self.state.borrow();
+            (state.engine, state.engine_exe())
+        };
+
+        // The engine upscales an image to list them, which takes a while
+        self.detect_gpus_button.set_enabled(false);
+        self.detect_gpus_button.set_text("Detecting...");
+        self.spawn_worker(move || Finished::Detected(engine, gpu::list_gpus(&exe)));
+    }
+
+    fn gpus_detected(&self, engine: Engine, result: Result<Vec<GpuInfo>, ProbeError>) {
+        let advanced = self.advanced_options.check_state() == CheckBoxState::Checked;
+        self.detect_gpus_button.set_text("Detect GPUs");
+        self.detect_gpus_button.set_enabled(advanced);
+
+        // The list of another engine would offer the wrong devices
+        if self.state.borrow().engine != engine {
+            return;
+        }
+
+        let gpus = match result {
+            Ok(gpus) => gpus,
+            Err(e) => {
+                self.clear_gpu_list();
+                nwg::modal_error_message(
+                    &self.window,
+                    "Error",
+                    &format!(
+                        "Unable to detect the GPUs: {}\n\nEnter the GPU IDs by hand instead.",
+                        e
+                    ),
+                );
+                return;
+            }
+        };
+
+        let mut entries = gpus
+            .iter()
+            .map(|g| {
+                let fp16 = if g.fp16_storage { " (fp16)" } else { "" };
+                (g.index, format!("{}: {}{}", g.index, g.name, fp16))
+            })
+            .collect::<Vec<_>>();
+
+        if engine.supports_cpu() {
+            entries.push((-1, String::from("-1: CPU")));
+        }
+
+        if gpus.is_empty() {
+            nwg::modal_info_message(
+                &self.window,
+                "No GPUs found",
+                &format!("{} did not report any Vulkan device.", engine),
+            );
+        }
+
+        let (ids, names) = entries.into_iter().unzip();
+        *self.gpu_ids.borrow_mut() = ids;
+        self.gpu_list.set_collection(names);
+        self.sync_gpu_list();
+    }
+
+    fn model_path_changed(&self) {
+        self.state.borrow_mut().model_path = self.model_path.text();
+        self.check_options(&self.state.borrow());
+    }
+
+    fn model_selected(&self) {
+        if let Some(name) = self.model_list.selection_string() {
+            self.model_path.set_text(&name);
+        }
+    }
+
+    /// Lists the models found next to the engine.
+    fn sync_model_list(&self) {
+        let (models, current) = {
+            let state = self.state.borrow();
+            let (_, current) = state.engine.model_location(&state.model_path);
+            (state.models(), current)
+        };
+
+        let names = models.into_iter().map(|m| m.name).collect::<Vec<_>>();
+        let index = names.iter().position(|name| *name == current);
+
+        self.model_list.set_collection(names);
+        self.model_list.set_selection(index);
+    }
+
+    fn tile_size_changed(&self) {
+        self.state.borrow_mut().tile_size = self.tile_size.text();
+        self.check_options(&self.state.borrow());
+    }
+
+    fn job_limit_changed(&self) {
+        // Anything that is not a positive number means "one job per GPU"
+        self.state.borrow_mut().job_limit = self.job_limit.text().trim().parse().unwrap_or(0);
+    }
+
+    fn filename_changed(&self) {
+        let mut state = self.state.borrow_mut();
+        state.filename_format = self.filename_format.text();
+
+        match state.template() {
+            Ok(_) => self.filename_error_label.set_text(""),
+            Err(e) => self.filename_error_label.set_text(&e.to_string()),
+        }
+
+        self.check_options(&state);
+    }
+
+    fn set_job_cell(&self, id: usize, column: i32, text: String) {
+        self.job_list.update_item(
+            id,
+            nwg::InsertListViewItem {
+                index: Some(id as i32),
+                column_index: column,
+                text: Some(text),
+                image: None,
+            },
+        );
+    }
+
+    fn update_job_row(&self, id: usize, job: &QueuedJob) {
+        let device = job.device.map(|d| d.to_string());
+        let tile_size = match (job.state, job.tile_size) {
+            (JobState::Pending, _) => String::new(),
+            (_, Some(size)) => size.to_string(),
+            (_, None) => String::from("auto"),
+        };
+
+        // A running video or animation shows its stage and how many frames
+        // it is through
+        let status = job.stage().unwrap_or_else(|| job.state.to_string());
+        let progress = match job.frames() {
+            Some((done, total)) => format!("{}/{} frames", done, total),
+            None => format!("{:.0}%", job.progress()),
+        };
+
+        self.set_job_cell(id, 1, device.unwrap_or_default());
+        self.set_job_cell(id, 2, tile_size);
+        self.set_job_cell(id, 3, status);
+        self.set_job_cell(id, 4, progress);
+        self.set_job_cell(id, 5, job.failure_message().unwrap_or_default());
+    }
+
+    fn populate_job_list(&self, queue: &JobQueue) {
+        self.job_list.clear();
+
+        for (id, job) in queue.jobs().iter().enumerate() {
+            self.job_list.insert_item(nwg::InsertListViewItem {
+                index: Some(id as i32),
+                column_index: 0,
+                text: Some(job.job.input.to_string_lossy().into_owned()),
+                image: None,
+            });
+            self.update_job_row(id, job);
+        }
+
+        self.progress_bar.set_pos(0);
+    }
+
+    fn retry_failed_clicked(&self) {
+        let mut state = self.state.borrow_mut();
+
+        if !state.queue.is_finished() || state.queue.retry_failed() == 0 {
+            return;
+        }
+
+        let Waifu2xState { queue, inputs, .. } = &mut *state;
+        for (id, job) in queue.jobs().iter().enumerate() {
+            self.update_job_row(id, job);
+            self.update_input_status(inputs, job);
+        }
+
+        drop(state);
+
+        self.retry_button.set_enabled(false);
+        self.copy_log_button.set_enabled(false);
+        self.start_button.set_text("Stop");
+        self.timer.start();
+    }
+
+    fn copy_log_clicked(&self) {
+        let log = self.state.borrow().queue.failure_log();
+        nwg::Clipboard::set_data_text(&self.window, &log);
+    }
+
+    fn timer_ticked(&self) {
+        // It is possible for the timer tick event to fire while an error
+        // message is being shown in the following loop, which leads to a
+        // panic
+        let state = &mut *match self.state.try_borrow_mut() {
+            Ok(x) => x,
+            Err(_) => return,
+        };
+
+        let events = state.queue.tick();
+
+        for (id, job) in state.queue.jobs().iter().enumerate() {
+            let touched = events.iter().any(|e| e.job() == id);
+
+            if touched || job.state == JobState::Running {
+                self.update_job_row(id, job);
+            }
+
+            if touched {
+                self.update_input_status(&mut state.inputs, job);
+            }
+        }
+
+        self.progress_bar
+            .set_pos((state.queue.progress() * 10.0) as u32);
+
+        let mut spawn_failed = false;
+
+        for event in events {
+            match event {
+                QueueEvent::Finished(_, Exit::Error(e)) => {
+                    nwg::modal_error_message(
+                        &self.window,
+                        "Error",
+                        &format!("Unexpected error occured while running Waifu2x: {}", e),
+                    );
+                }
+                // If one spawn fails, the rest will most likely fail the same
+                // way, so give up on the remaining jobs
+                QueueEvent::SpawnFailed(id, e) if !spawn_failed => {
+                    spawn_failed = true;
+                    state.queue.cancel_pending();
+                    let error = state.queue.jobs()[id].error.clone();
+                    nwg::modal_error_message(
+                        &self.window,
+                        "Error",
+                        &error.unwrap_or_else(|| e.to_string()),
+                    );
+                }
+                _ => {}
+            }
+        }
+
+        let failed = state.queue.count(JobState::Failed);
+
+        if state.queue.is_finished() {
+            self.timer.stop();
+
+            // Cancelled jobs are not reported as events
+            for (id, job) in state.queue.jobs().iter().enumerate() {
+                self.update_job_row(id, job);
+                self.update_input_status(&mut state.inputs, job);
+            }
+
+            self.retry_button.set_enabled(failed > 0);
+            self.copy_log_button.set_enabled(failed > 0);
+
+            if failed > 0 {
+                self.start_button.set_text("Start (error occured!)");
+                self.tabs.set_selected_tab(JOBS_TAB);
+                nwg::modal_info_message(
+                    &self.window,
+                    "Finished with errors",
+                    &format!(
+                        "{} of {} images could not be processed.\n\n{}\n\nThe Jobs tab lists every failed image with its error.",
+                        failed,
+                        state.queue.jobs().len(),
+                        failure_summary(&state.queue),
+                    ),
+                );
+            } else if state.queue.count(JobState::Cancelled) > 0 {
+                self.start_button.set_text("Start (cancelled)");
+            } else {
+                self.start_button.set_text("Start")
+            }
+
+            self.check_options(state);
+        } else {
+            let done = state.queue.count(JobState::Succeeded) + failed;
+            let total = state.queue.jobs().len();
+            let error = if failed > 0 { " (error occured!)" } else { "" };
+            self.start_button
+                .set_text(&format!("Stop (processed {}/{}{})", done, total, error));
+        }
+    }
+
+    fn start_clicked(&self) {
+        let mut state = self.state.borrow_mut();
+
+        // While processing, this button doubles as the stop button
+        if !state.queue.is_finished() {
+            state.queue.cancel();
+            drop(state);
+            self.timer_ticked();
+            return;
+        }
+
+        let Some(mut plan) = self.build_plan(&mut state) else {
+            return;
+        };
+
+        // Run what was previewed; a plan that changed since is shown again
+        // instead, so nothing runs that the user has not seen
+        let changed = self
+            .preview
+            .borrow()
+            .as_ref()
+            .is_some_and(|previewed| *previewed != plan);
+        if changed {
+            self.show_plan(plan);
+            nwg::modal_info_message(
+                &self.window,
+                "Plan changed",
+                "The files or settings changed since the preview. Check the new plan, then start again.",
+            );
+            return;
+        }
+
+        // Let the user settle the existing outputs the policy leaves to them;
+        // the others are handled as the policy says, and only reported
+        let asked = plan.count(Collision::Ask);
+        if asked > 0 {
+            let content = format!(
+                "{}\n\n{} output files already exist. Overwrite them?\nChoose No to skip them.",
+                plan, asked
+            );
+            let params = MessageParams {
+                title: "Existing outputs",
+                content: &content,
+                buttons: nwg::MessageButtons::YesNoCancel,
+                icons: MessageIcons::Question,
+            };
+
+            match nwg::modal_message(&self.window, &params) {
+                MessageChoice::Yes => plan.answer(true),
+                MessageChoice::No => plan.answer(false),
+                _ => return,
+            }
+        }
+
+        if plan.runnable().next().is_none() {
+            nwg::modal_info_message(
+                &self.window,
+                "Nothing to do",
+                "Every output already exists and is skipped.",
+            );
+            return;
+        }
+
+        // Make sure the engine runs before queueing anything, without
+        // blocking the window while it answers
+        let engine = state.engine;
+        drop(state);
+        self.start_button.set_enabled(false);
+        self.spawn_worker(move || {
+            let result = engine.probe(&plan.exe);
+            Finished::Checked(engine, plan, result)
+        });
+    }
+
+    /// Runs `plan` if its executable turned out to be the engine.
+    fn plan_checked(&self, engine: Engine, plan: Plan, result: Result<(), ProbeError>) {
+        let mut state = self.state.borrow_mut();
+
+        // Start was clicked again while the engine was being checked
+        if !state.queue.is_finished() {
+            return;
+        }
+
+        if let Err(e) = result {
+            nwg::modal_error_message(
+                &self.window,
+                "Error",
+                &format!(
+                    "Unable to use {} as {}.\n{}\n\nPick its executable with the Engine path setting.",
+                    plan.exe.display(),
+                    engine,
+                    e
+                ),
+            );
+            self.tabs.set_selected_tab(PROCESSING_TAB);
+            self.check_options(&state);
+            return;
+        }
+
+        state.execute(&plan);
+
+        // The previewed plan is used up; its outputs change what the next
+        // Start plans
+        self.preview.take();
+        self.preview_list.clear();
+        self.preview_summary_label.set_text("");
+        self.export_plan_button.set_enabled(false);
+
+        // Pick up files that changed since they were added
+        state.inputs.refresh();
+        let Waifu2xState { queue, inputs, .. } = &mut *state;
+        for job in queue.jobs() {
+            inputs.set_status(&job.job.input, InputStatus::Job(job.state));
+        }
+
+        self.populate_input_list(&state.inputs);
+        self.populate_job_list(&state.queue);
+        if plan.has_collisions() {
+            self.job_summary_label.set_text(&plan.to_string());
+        } else {
+            self.job_summary_label.set_text("");
+        }
+        self.retry_button.set_enabled(false);
+        self.copy_log_button.set_enabled(false);
+        self.tabs.set_selected_tab(JOBS_TAB);
+
+        drop(state);
+
+        self.start_button.set_text("Stop");
+        self.start_button.set_enabled(true);
+        self.timer.start();
+    }
+
+    /// Plans a batch of the selected files with the current settings,
+    /// asking for an output folder if none is set. Errors are reported to
+    /// the user.
+    fn build_plan(&self, state: &mut Waifu2xState) -> Option<Plan> {
+        match state.validate() {
+            Ok(()) => {}
+            Err(PlanError::NoInput) => return None,
+            Err(PlanError::Name(NameError::Template(e))) => {
+                self.filename_error_label.set_text(&e.to_string());
+                self.tabs.set_selected_tab(OUTPUT_TAB);
+                return None;
+            }
+            Err(e) => {
+                nwg::modal_info_message(&self.window, "Error", &e.to_string());
+                return None;
+            }
+        }
+
         let output_dir = if !state.output_dir.is_empty() {
             state.output_dir.clone()
         } else {
@@ -482,7 +2005,7 @@ impl Waifu2xApp {
             let choice = nwg::modal_message(&self.window, &params);
 
             if choice == MessageChoice::Yes {
-                let mut path = PathBuf::from(&state.selected_files[0]);
+                let mut path = state.inputs.files()[0].path.clone();
                 if path.is_file() {
                     path.pop();
                 }
@@ -496,114 +2019,166 @@ impl Waifu2xApp {
                     .set_text(&path.to_string_lossy().trim_start_matches("\\\\?\\"));
                 path
             } else {
-                return;
+                return None;
             }
         };
 
-        for f in state.selected_files.iter() {
-            let mut input = PathBuf::from(f);
-            let mut output = PathBuf::from(&output_dir);
-            let output_ext = match state.format {
-                Format::Png => "png",
-                Format::Jpg => "jpg",
-                Format::Webp => "webp",
-            };
+        match state.plan(Path::new(&output_dir)) {
+            Ok(plan) => Some(plan),
+            Err(PlanError::Name(NameError::Template(e))) => {
+                self.filename_error_label.set_text(&e.to_string());
+                self.tabs.set_selected_tab(OUTPUT_TAB);
+                None
+            }
+            Err(e) => {
+                nwg::error_message("Error", &e.to_string());
+                None
+            }
+        }
+    }
 
-            input.set_extension("");
-
-            output.push(match input.file_name() {
-                Some(x) => {
-                    let template = state
-                        .filename_format
-                        .replace("{scale}", &format!("{}", state.scale_level))
-                        .replace("{denoise}", &format!("{}", state.denoise_level))
-                        .replace("{model}", &state.model_path);
-
-                    let name_start = match template.find("{name}") {
-                        Some(x) => x,
-                        None => {
-                            nwg::error_message(
-                                "Error",
-                                "Output filename must contain a {name} section!",
-                            );
-                            return;
-                        }
-                    };
-
-                    let name_end = name_start + "{name}".len();
-
-                    let encoded = template
-                        .encode_utf16()
-                        .take(name_start)
-                        .chain(OsStrExt::encode_wide(x))
-                        .chain(template.encode_utf16().skip(name_end))
-                        .collect::<Vec<_>>();
-
-                    OsString::from_wide(&encoded)
-                }
-                None => {
-                    nwg::error_message(
-                        "Error",
-                        &format!(
-                            "The following input file has an invalid path: {}",
-                            f.to_string_lossy()
-                        ),
-                    );
-                    return;
-                }
-            });
+    fn preview_clicked(&self) {
+        let plan = self.build_plan(&mut self.state.borrow_mut());
 
-            output.set_extension(output_ext);
-
-            let mut waifu2x_command = Command::new("waifu2x-ncnn-vulkan-cli");
-            let mut waifu2x = waifu2x_command
-                .arg("-i")
-                .arg(f)
-                .arg("-o")
-                .arg(output)
-                .arg("-s")
-                .arg(state.scale_level.to_string())
-                .arg("-n")
-                .arg(state.denoise_level.to_string());
-
-            if !state.thread_count.is_empty() {
-                waifu2x = waifu2x.arg("-j").arg(&state.thread_count);
-            }
+        if let Some(plan) = plan {
+            self.show_plan(plan);
+            self.tabs.set_selected_tab(PREVIEW_TAB);
+        }
+    }
 
-            if !state.gpu_id.is_empty() {
-                waifu2x = waifu2x.arg("-g").arg(&state.gpu_id);
+    /// Fills `preview_list` with `plan` and keeps it for Start and the CSV
+    /// export.
+    fn show_plan(&self, plan: Plan) {
+        self.preview_list.clear();
+
+        for (index, job) in plan.jobs.iter().enumerate() {
+            let cells = [
+                job.job.input.to_string_lossy().into_owned(),
+                job.job.output.to_string_lossy().into_owned(),
+                plan.command_line(job),
+                job.collision.to_string(),
+                job.warnings.join("; "),
+            ];
+
+            for (column, text) in cells.into_iter().enumerate() {
+                let item = nwg::InsertListViewItem {
+                    index: Some(index as i32),
+                    column_index: column as i32,
+                    text: Some(text),
+                    image: None,
+                };
+
+                if column == 0 {
+                    self.preview_list.insert_item(item);
+                } else {
+                    self.preview_list.update_item(index, item);
+                }
             }
+        }
 
-            if !state.model_path.is_empty() {
-                waifu2x = waifu2x.arg("-m").arg(&state.model_path);
-            }
+        let warned = plan.warning_count();
+        let mut summary = if warned > 0 {
+            format!("{} ({} with warnings)", plan, warned)
+        } else {
+            plan.to_string()
+        };
+        // The device, and the frame rate of a video, are only known later
+        if plan.has_templates() {
+            summary.push_str(". Templates are filled in as their jobs start");
+        }
+        self.preview_summary_label.set_text(&summary);
+        self.export_plan_button.set_enabled(true);
 
-            if state.tta_mode {
-                waifu2x = waifu2x.arg("-x");
-            }
+        *self.preview.borrow_mut() = Some(plan);
+    }
 
-            let child = match waifu2x.spawn() {
-                Ok(x) => x,
-                Err(e) => {
-                    nwg::error_message(
-                        "Error",
-                        &format!("Unable to spawn a waifu2x instance:\n{:?}", e),
-                    );
-                    return;
-                }
-            };
+    fn export_plan_clicked(&self) {
+        if !self.export_plan_dialog.run(Some(&self.window)) {
+            return;
+        }
+
+        let Ok(path) = self.export_plan_dialog.get_selected_item() else {
+            return;
+        };
 
-            children.push(child);
+        let mut path = PathBuf::from(path);
+        if path.extension().is_none() {
+            path.set_extension("csv");
         }
 
-        state.children = children;
+        let Some(csv) = self.preview.borrow().as_ref().map(Plan::to_csv) else {
+            return;
+        };
 
-        drop(state);
+        if let Err(e) = std::fs::write(&path, csv) {
+            nwg::modal_error_message(&self.window, "Error", &e.to_string());
+        }
+    }
+}
 
-        self.start_button.set_text("Processing...");
-        self.start_button.set_enabled(false);
-        self.timer.start();
+/// Lists the first few failed inputs with their errors, for the message box
+/// shown at the end of a batch.
+fn failure_summary(queue: &JobQueue) -> String {
+    const SHOWN: usize = 5;
+
+    let failed = queue
+        .jobs()
+        .iter()
+        .filter_map(|j| Some((j, j.failure_message()?)))
+        .collect::<Vec<_>>();
+
+    let mut lines = failed
+        .iter()
+        .take(SHOWN)
+        .map(|(job, message)| {
+            let name = job.job.input.file_name().unwrap_or_default();
+            format!("{}: {}", name.to_string_lossy(), message)
+        })
+        .collect::<Vec<_>>();
+
+    if failed.len() > SHOWN {
+        lines.push(format!("... and {} more", failed.len() - SHOWN));
     }
+
+    lines.join("\n")
+}
+
+/// The filters of the Open File dialog, made of the patterns that the input
+/// list and the folder filter accept so that they cannot drift apart.
+fn input_filters() -> String {
+    format!(
+        "Image({})|Animation({})|Video({})|>Supported files({})",
+        input::IMAGE_INCLUDE,
+        animation::ANIMATION_INCLUDE,
+        video::VIDEO_INCLUDE,
+        input::default_include()
+    )
+}
+
+/// Formats a file size for the input list, e.g. `1.5 MB`.
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// The choices of the animation format list: keeping the input's container,
+/// then every format.
+fn animation_choices() -> Vec<String> {
+    let mut choices = vec![String::from("Same as input")];
+    choices.extend(AnimationFormat::ALL.iter().map(|f| f.to_string()));
+    choices
 }
 
 fn main() {